@@ -1,28 +1,59 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use mongodb::{
-    bson::{doc, DateTime},
-    options::{ClientOptions, IndexOptions},
-    Client, ClientSession, Database, IndexModel,
-};
+use jsonwebtoken::jwk::JwkSet;
+use mongodb::Client;
+use tracing::instrument;
 
-use core_rs::{error::ServiceError, ProfilePicture};
+use core_rs::{error::ServiceError, username::NormalizedUsername, ProfilePicture};
+use dirc_client::{users::DEFAULT_USERS_URL, UsersApi};
 
-use crate::{Credentials, LoginInfo, SessionToken, Username};
+use crate::{
+    generate_token, hash_token,
+    hashing::HashAlgorithm,
+    metrics::AuthMetrics,
+    notifier::{NoNotifier, Notifier},
+    policy::PasswordPolicy,
+    store::{AuthStore, MongoStore, SESSION_MAX_LIFETIME},
+    throttle::{FailedAttempts, LoginThrottle, ThrottlePolicy},
+    tokens::TokenSigner,
+    totp::{RecoveryCodes, SecondFactor, TotpEnrollment},
+    ClientInfo, Credentials, LoginChallenge, LoginInfo, LoginResponse, PasswordChange,
+    PasswordReset, ResetToken, SecondFactorChallenge, SecondFactorCode, SecondFactorVerification,
+    Session, SessionInfo, SessionToken, Username,
+};
 
 const DEFAULT_PROFILE_PICTURE_URL: &str =
     "https://upload.wikimedia.org/wikipedia/commons/2/2c/Default_pfp.svg";
 
-/// Authenticator is the main struct for the authentication service handing authentication actions using a MongoDB database.
+/// How long a password reset token can be used after it was issued
+pub const RESET_TOKEN_TTL: Duration = Duration::from_secs(3600);
+
+/// How long a login can wait for its second factor
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+
+/// How many wrong codes can be sent for a login challenge before it is discarded
+pub const MAX_CHALLENGE_ATTEMPTS: u32 = 5;
+
+/// Authenticator is the main struct for the authentication service handing authentication actions.
+///
+/// It is generic over the storage backend used for credentials and sessions, defaulting to MongoDB.
 #[derive(Clone, Debug)]
-pub struct Authenticator {
-    client: Client,
-    database: Database,
+pub struct Authenticator<S = MongoStore> {
+    store: S,
+    hash_algorithm: HashAlgorithm,
+    password_policy: PasswordPolicy,
+    notifier: Arc<dyn Notifier>,
+    token_signer: TokenSigner,
+    session_max_lifetime: Duration,
+    login_throttle: LoginThrottle,
+    generic_login_errors: bool,
+    users: UsersApi,
+    metrics: AuthMetrics,
 }
 
-impl Authenticator {
+impl Authenticator<MongoStore> {
     pub async fn get_client(&self) -> Client {
-        self.client.clone()
+        self.store.client()
     }
 
     /// Creates a new Authenticator instance given a mongodb url and database name
@@ -30,151 +61,651 @@ impl Authenticator {
     /// # Errors
     /// Construction will fail if a database error occurs
     pub async fn new(url: String, db_name: String) -> anyhow::Result<Self> {
-        let mut client_options = ClientOptions::parse(url).await?;
-        client_options.app_name = Some("auth".to_string());
-        client_options.connect_timeout = Some(Duration::from_secs(3));
-        client_options.server_selection_timeout = Some(Duration::from_secs(10));
-
-        let client = Client::with_options(client_options)?;
-        let database = client.database(db_name.as_str());
-
-        let credentials_options = IndexOptions::builder().unique(true).build();
-        let credentials_model = IndexModel::builder()
-            .keys(doc! {"username": 1})
-            .options(credentials_options)
-            .build();
-
-        database
-            .collection::<Credentials>("credentials")
-            .create_index(credentials_model, None)
-            .await?;
+        let store = MongoStore::new(url, db_name).await?;
 
-        let session_options = IndexOptions::builder()
-            .expire_after(Duration::from_secs(2592000))
-            .build();
-        let session_model = IndexModel::builder()
-            .keys(doc! {"token": 1})
-            .options(session_options)
-            .build();
-
-        database
-            .collection::<Credentials>("sessions")
-            .create_index(session_model, None)
-            .await?;
+        Ok(Self::with_store(store))
+    }
+}
+
+impl<S: AuthStore> Authenticator<S> {
+    /// Creates a new Authenticator instance backed by the given store
+    pub fn with_store(store: S) -> Self {
+        Self {
+            store,
+            hash_algorithm: HashAlgorithm::default(),
+            password_policy: PasswordPolicy::default(),
+            notifier: Arc::new(NoNotifier),
+            token_signer: TokenSigner::generate(),
+            session_max_lifetime: SESSION_MAX_LIFETIME,
+            login_throttle: LoginThrottle::default(),
+            generic_login_errors: false,
+            users: UsersApi::new(DEFAULT_USERS_URL),
+            metrics: AuthMetrics::default(),
+        }
+    }
+
+    /// Sets the algorithm used to hash new passwords.
+    ///
+    /// Existing hashes made with a different algorithm or parameters are upgraded on login.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Sets the policy new passwords have to satisfy
+    pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+        self.password_policy = password_policy;
+        self
+    }
 
-        Ok(Self { client, database })
+    /// Sets how password reset tokens are delivered to users. Without a notifier, password
+    /// resets can't be completed.
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Sets the key and lifetime of the signed access tokens issued with every session
+    pub fn with_token_signer(mut self, token_signer: TokenSigner) -> Self {
+        self.token_signer = token_signer;
+        self
+    }
+
+    /// Sets how long a session stays valid after it was created, however often it is used
+    pub fn with_session_max_lifetime(mut self, session_max_lifetime: Duration) -> Self {
+        self.session_max_lifetime = session_max_lifetime;
+        self
+    }
+
+    /// Sets how failed logins are throttled per account and per client IP address
+    pub fn with_login_throttle(mut self, login_throttle: LoginThrottle) -> Self {
+        self.login_throttle = login_throttle;
+        self
+    }
+
+    /// Sets whether failed logins return `ServiceError::InvalidCredentials` instead of telling
+    /// apart unknown users and wrong passwords, so login can't be used to find out which users
+    /// exist
+    pub fn with_generic_login_errors(mut self, generic_login_errors: bool) -> Self {
+        self.generic_login_errors = generic_login_errors;
+        self
+    }
+
+    /// Sets the client of the users service the profiles of new users are created with
+    pub fn with_users_api(mut self, users: UsersApi) -> Self {
+        self.users = users;
+        self
+    }
+
+    /// Sets the metrics logins, registrations and sessions are counted in
+    pub fn with_metrics(mut self, metrics: AuthMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Returns the metrics logins, registrations and sessions are counted in
+    pub fn metrics(&self) -> &AuthMetrics {
+        &self.metrics
+    }
+
+    /// Counts the sessions that have not expired into the `auth_sessions_active` metric. Called
+    /// every [`SESSIONS_COUNT_INTERVAL`](crate::metrics::SESSIONS_COUNT_INTERVAL) by the service
+    /// rather than on every scrape.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    pub async fn update_sessions_active(&self) -> Result<(), ServiceError> {
+        let count = self.store.count_sessions().await?;
+
+        self.metrics.set_sessions_active(count);
+
+        Ok(())
+    }
+
+    /// Returns the public keys access tokens can be verified with
+    pub fn jwks(&self) -> JwkSet {
+        self.token_signer.jwks()
     }
 
     /// Registers a new user with the given username and password
     ///
+    /// The username is stored in its normalized form, see [`NormalizedUsername`].
+    ///
     /// # Errors
     /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::InvalidUsername` if the username is not allowed
     /// `ServiceError::UsernameTaken` if the username is already taken
+    /// `ServiceError::PasswordPolicyViolation` if the password does not satisfy the password policy
+    /// Any error of the users service creating the profile of the user, in which case the user
+    /// is not registered
     pub async fn register(&self, info: LoginInfo) -> Result<SessionToken, ServiceError> {
-        let credentials = Credentials::new(&info);
+        self.register_with_client(info, ClientInfo::default()).await
+    }
 
-        let credentials_collection = self.database.collection::<Credentials>("credentials");
+    /// Registers a new user like [`Authenticator::register`], recording the client the
+    /// session was created from
+    ///
+    /// # Errors
+    /// See [`Authenticator::register`]
+    #[instrument(skip_all)]
+    pub async fn register_with_client(
+        &self,
+        info: LoginInfo,
+        client: ClientInfo,
+    ) -> Result<SessionToken, ServiceError> {
+        let result = self.try_register(info, client).await;
 
-        let mut session = self.client.start_session(None).await?;
+        if result.is_ok() {
+            self.metrics.record_registration();
+        }
 
-        let existing = credentials_collection
-            .find_one_with_session(
-                doc! { "username": credentials.username() },
-                None,
-                &mut session,
-            )
-            .await?;
+        result
+    }
+
+    /// Registers a new user, see [`Authenticator::register_with_client`]
+    async fn try_register(
+        &self,
+        info: LoginInfo,
+        client: ClientInfo,
+    ) -> Result<SessionToken, ServiceError> {
+        let info = info.normalized()?;
+
+        self.password_policy.check(&info)?;
+
+        let credentials = self.hash_credentials(&info).await;
+
+        let existing = self.store.find_credentials(credentials.username()).await?;
 
         if existing.is_some() {
             return Err(ServiceError::UsernameTaken(credentials.username().clone()));
         }
 
-        credentials_collection
-            .insert_one_with_session(credentials, None, &mut session)
+        self.store.insert_credentials(credentials).await?;
+
+        let session_token = self
+            .create_and_store_session_token(info.username.clone(), client)
             .await?;
 
-        let token_res = self
-            .create_and_store_session_token(info.username.clone(), &mut session)
-            .await;
+        if info.username.starts_with("test") {
+            return Ok(session_token);
+        }
 
-        match token_res {
-            Ok(session_token) => {
-                let username = info.username.clone();
+        let username = NormalizedUsername::parse(&info.username)?;
+        let pfp: ProfilePicture = DEFAULT_PROFILE_PICTURE_URL.to_string().into();
 
-                if username.starts_with("test") {
-                    return Ok(session_token);
-                }
+        if let Err(err) = self.users.create_info(&username, &pfp).await {
+            tracing::error!("Failed to create profile of {}: {}", info.username, err);
 
-                let client = awc::Client::default();
+            self.roll_back_registration(&session_token).await;
 
-                let pfp: ProfilePicture = DEFAULT_PROFILE_PICTURE_URL.to_string().into();
+            return Err(err);
+        }
 
-                let mut response = client
-                    .put(format!("http://users:8080/{}/info", username))
-                    .send_json(&pfp)
-                    .await
-                    .expect("Failed to make request to auth/authenticate");
+        Ok(session_token)
+    }
 
-                if response.status() == 200 {
-                    Ok(session_token)
-                } else {
-                    println!("Error: {:?}", response.body().await);
-                    Err(ServiceError::AuthenticationError)
-                }
-            }
-            Err(e) => Err(e),
+    /// Removes the session and credentials of a registration that could not be completed, so
+    /// the username can be registered again
+    async fn roll_back_registration(&self, session_token: &SessionToken) {
+        let username = session_token.username();
+
+        if let Err(err) = self
+            .store
+            .delete_session(&hash_token(session_token.token()))
+            .await
+        {
+            tracing::error!("Failed to remove session of {}: {}", username, err);
+        }
+
+        if let Err(err) = self.store.delete_credentials(username).await {
+            tracing::error!("Failed to remove credentials of {}: {}", username, err);
         }
     }
 
-    /// Creates a new session token, stores it in the database and returns it
+    /// Creates a new session, stores it and returns its token together with an access token
     ///
     /// # Errors
     /// `ServiceError::DatabaseError` if a database error occurs
     async fn create_and_store_session_token(
         &self,
         username: String,
-        session: &mut ClientSession,
+        client: ClientInfo,
     ) -> Result<SessionToken, ServiceError> {
-        let session_token = SessionToken::new(username);
-        let session_token_collection = self.database.collection::<SessionToken>("sessions");
-        session_token_collection
-            .insert_one_with_session(session_token.clone(), None, session)
-            .await?;
+        let (session, session_token) = Session::new(username, client);
+        let access_token = self.token_signer.sign(session.username(), session.id());
 
-        Ok(session_token)
+        self.store.insert_session(session).await?;
+
+        Ok(session_token.with_access_token(access_token))
     }
 
     /// Logs in a user with the given username and password
     ///
+    /// The username is normalized before looking up the user, see [`NormalizedUsername`].
+    ///
+    /// Credentials hashed with a different algorithm or parameters than the configured ones
+    /// (including legacy PBKDF2 hashes) are rehashed on success.
+    ///
+    /// If the user has two-factor authentication enabled no session is created, instead a
+    /// challenge is returned that has to be answered with [`Authenticator::verify_second_factor`].
+    ///
+    /// Failed logins are counted per username and per client IP address. Once either has too
+    /// many recent failures, further logins are rejected until a delay has passed, see
+    /// [`LoginThrottle`]. A successful login resets the count of the username. Every login is
+    /// counted as failed before the password is checked, so parallel attempts can't get around
+    /// the limit.
+    ///
     /// # Errors
     /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::InvalidUsername` if the username is not allowed
+    /// `ServiceError::TooManyAttempts` if too many logins failed recently
     /// `ServiceError::UserNotFound` if the user does not exist
     /// `ServiceError::InvalidPassword` if the password is incorrect
-    pub async fn login(&self, info: LoginInfo) -> Result<SessionToken, ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-
-        let credentials_collection = self.database.collection::<Credentials>("credentials");
-
-        let credentials = {
-            let credentials_option = credentials_collection
-                .find_one_with_session(
-                    doc! { "username": info.username.clone() },
-                    None,
-                    &mut session,
-                )
+    /// `ServiceError::InvalidCredentials` instead of the previous two if generic login errors
+    /// are enabled
+    pub async fn login(&self, info: LoginInfo) -> Result<LoginResponse, ServiceError> {
+        self.login_with_client(info, ClientInfo::default()).await
+    }
+
+    /// Logs in a user like [`Authenticator::login`], recording the client the session was
+    /// created from
+    ///
+    /// # Errors
+    /// See [`Authenticator::login`]
+    #[instrument(skip_all)]
+    pub async fn login_with_client(
+        &self,
+        info: LoginInfo,
+        client: ClientInfo,
+    ) -> Result<LoginResponse, ServiceError> {
+        let result = self.try_login(info, client).await;
+
+        self.metrics.record_login(&result);
+
+        result
+    }
+
+    /// Logs in a user, see [`Authenticator::login_with_client`]
+    async fn try_login(
+        &self,
+        info: LoginInfo,
+        client: ClientInfo,
+    ) -> Result<LoginResponse, ServiceError> {
+        let info = info.normalized()?;
+        let throttle_keys = self.login_throttle.keys(&info.username, &client);
+
+        let previous_attempts = self.record_attempt(&throttle_keys).await?;
+
+        let credentials = match self.store.find_credentials(&info.username).await? {
+            Some(credentials) if verify_password(&credentials, &info).await => credentials,
+            found => {
+                return Err(match found {
+                    _ if self.generic_login_errors => ServiceError::InvalidCredentials,
+                    Some(_) => ServiceError::InvalidPassword,
+                    None => ServiceError::UserNotFound(info.username),
+                });
+            }
+        };
+
+        self.forgive_attempt(&throttle_keys, &previous_attempts)
+            .await?;
+
+        let requires_second_factor = credentials.requires_second_factor();
+
+        // With a second factor the failures of the account are only forgotten once it was
+        // verified, as they also count wrong codes
+        if !requires_second_factor {
+            self.store
+                .clear_failed_attempts(&LoginThrottle::account_key(&info.username))
                 .await?;
+        }
+
+        if credentials.needs_rehash(&self.hash_algorithm) {
+            let (info, algorithm) = (info.clone(), self.hash_algorithm);
+            let rehashed = run_blocking(move || credentials.with_password(&info, &algorithm)).await;
+
+            self.store.update_password(&rehashed).await?;
+        }
+
+        if requires_second_factor {
+            let (challenge, challenge_token) =
+                LoginChallenge::new(info.username, client, CHALLENGE_TTL);
+
+            self.store.insert_challenge(challenge).await?;
 
-            match credentials_option {
-                Some(credentials) => credentials,
-                None => return Err(ServiceError::UserNotFound(info.username)),
+            return Ok(LoginResponse::SecondFactorRequired(SecondFactorChallenge {
+                second_factor_required: true,
+                challenge_token,
+                expires_in: CHALLENGE_TTL.as_secs(),
+            }));
+        }
+
+        self.create_and_store_session_token(info.username, client)
+            .await
+            .map(LoginResponse::Session)
+    }
+
+    /// Creates the credentials of the login info, hashing the password with the configured
+    /// algorithm on the blocking thread pool
+    async fn hash_credentials(&self, info: &LoginInfo) -> Credentials {
+        let (info, algorithm) = (info.clone(), self.hash_algorithm);
+
+        run_blocking(move || Credentials::with_algorithm(&info, &algorithm)).await
+    }
+
+    /// Records an attempt as failed under each of the keys before it is checked, failing if any
+    /// of them already had too many recent failures. Returns the records of the keys as they
+    /// were before.
+    ///
+    /// Recording first means concurrent attempts can't all pass the check before any of their
+    /// failures is recorded. Attempts that succeed have to be taken back with
+    /// [`Authenticator::forgive_attempt`], attempts rejected here are taken back right away so
+    /// they neither make the delay grow nor restart it.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::TooManyAttempts` if a key has too many recent failures
+    async fn record_attempt(
+        &self,
+        keys: &[(String, &ThrottlePolicy)],
+    ) -> Result<Vec<Option<FailedAttempts>>, ServiceError> {
+        let mut previous_attempts = Vec::with_capacity(keys.len());
+        let mut retry_after = None;
+
+        for (key, policy) in keys {
+            let previous = self.store.record_failed_attempt(key).await?;
+
+            if let Some(previous) = &previous {
+                retry_after = retry_after.max(policy.retry_after(previous));
             }
+
+            previous_attempts.push(previous);
+        }
+
+        let Some(retry_after) = retry_after else {
+            return Ok(previous_attempts);
         };
 
-        if credentials.matches(&info) {
-            self.create_and_store_session_token(info.username, &mut session)
-                .await
-        } else {
-            Err(ServiceError::InvalidPassword)
+        self.forgive_attempt(keys, &previous_attempts).await?;
+
+        Err(ServiceError::TooManyAttempts(
+            retry_after.as_secs_f64().ceil() as u64,
+        ))
+    }
+
+    /// Returns the throttle key of the account of a user, for codes and passwords checked on
+    /// behalf of a session that share the limit of failed logins of the account
+    fn account_throttle_keys(&self, username: &str) -> [(String, &ThrottlePolicy); 1] {
+        [(
+            LoginThrottle::account_key(username),
+            &self.login_throttle.account,
+        )]
+    }
+
+    /// Takes back an attempt recorded with [`Authenticator::record_attempt`], given the records
+    /// it returned
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn forgive_attempt(
+        &self,
+        keys: &[(String, &ThrottlePolicy)],
+        previous_attempts: &[Option<FailedAttempts>],
+    ) -> Result<(), ServiceError> {
+        for ((key, _), previous) in keys.iter().zip(previous_attempts) {
+            self.store
+                .forgive_failed_attempt(key, previous.as_ref())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Completes a login that requires a second factor, given the challenge token returned by
+    /// [`Authenticator::login`] and a TOTP code or an unused recovery code.
+    ///
+    /// A challenge can only be answered successfully once, and is discarded after
+    /// [`MAX_CHALLENGE_ATTEMPTS`] wrong codes. Wrong codes also count as failed logins of the
+    /// account, so starting new challenges doesn't allow guessing more codes than the
+    /// [`LoginThrottle`] allows.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::TooManyAttempts` if too many logins of the account failed recently
+    /// `ServiceError::InvalidSecondFactor` if the challenge is unknown or expired, or the code is
+    /// wrong
+    #[instrument(skip_all)]
+    pub async fn verify_second_factor(
+        &self,
+        verification: SecondFactorVerification,
+    ) -> Result<SessionToken, ServiceError> {
+        let mut challenge = match self
+            .store
+            .take_challenge(&hash_token(&verification.challenge_token))
+            .await?
+        {
+            Some(challenge) if !challenge.is_expired() => challenge,
+            _ => return Err(ServiceError::InvalidSecondFactor),
+        };
+
+        let account_key = LoginThrottle::account_key(challenge.username());
+
+        if let Err(err) = self
+            .record_attempt(&[(account_key.clone(), &self.login_throttle.account)])
+            .await
+        {
+            self.store.insert_challenge(challenge).await?;
+
+            return Err(err);
+        }
+
+        let credentials = match self.store.find_credentials(challenge.username()).await? {
+            Some(credentials) if credentials.requires_second_factor() => credentials,
+            _ => return Err(ServiceError::InvalidSecondFactor),
+        };
+
+        let used = match Self::check_second_factor(&credentials, &verification.code) {
+            Some(second_factor) => {
+                self.store
+                    .update_second_factor(
+                        credentials.username(),
+                        credentials.second_factor(),
+                        Some(&second_factor),
+                    )
+                    .await?
+            }
+            None => false,
+        };
+
+        if !used {
+            challenge.record_failed_attempt();
+
+            if challenge.failed_attempts() < MAX_CHALLENGE_ATTEMPTS {
+                self.store.insert_challenge(challenge).await?;
+            }
+
+            return Err(ServiceError::InvalidSecondFactor);
+        }
+
+        self.store.clear_failed_attempts(&account_key).await?;
+
+        self.create_and_store_session_token(challenge.username().clone(), challenge.client())
+            .await
+    }
+
+    /// Checks a TOTP code or recovery code against the enabled second factor of the credentials.
+    ///
+    /// Returns the second factor with the use of the code recorded, or `None` if the code is
+    /// wrong. The code only counts as used once the result replaced the stored second factor
+    /// with [`update_second_factor`](crate::store::CredentialStore::update_second_factor), which
+    /// fails if a concurrent request used it first.
+    fn check_second_factor(credentials: &Credentials, code: &str) -> Option<SecondFactor> {
+        let mut second_factor = credentials
+            .second_factor()
+            .filter(|second_factor| second_factor.is_enabled())?
+            .clone();
+
+        (second_factor.verify_code(code) || second_factor.use_recovery_code(code))
+            .then_some(second_factor)
+    }
+
+    /// Starts setting up TOTP two-factor authentication for the user owning the session token.
+    ///
+    /// Returns a new secret that has to be confirmed with
+    /// [`Authenticator::confirm_second_factor`] before it is required on login. Starting again
+    /// before confirming replaces the secret.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    /// `ServiceError::SecondFactorAlreadyEnabled` if two-factor authentication is already enabled
+    #[instrument(skip_all)]
+    pub async fn enroll_second_factor(
+        &self,
+        session_token: &str,
+    ) -> Result<TotpEnrollment, ServiceError> {
+        let credentials = self.find_session_credentials(session_token).await?;
+
+        if credentials.requires_second_factor() {
+            return Err(ServiceError::SecondFactorAlreadyEnabled);
+        }
+
+        let second_factor = SecondFactor::generate();
+        let enrollment = second_factor.enrollment(credentials.username());
+
+        // Fails if the second factor was enabled concurrently, which must not be replaced
+        if !self
+            .store
+            .update_second_factor(
+                credentials.username(),
+                credentials.second_factor(),
+                Some(&second_factor),
+            )
+            .await?
+        {
+            return Err(ServiceError::SecondFactorAlreadyEnabled);
+        }
+
+        Ok(enrollment)
+    }
+
+    /// Enables two-factor authentication for the user owning the session token, given a code
+    /// generated from the secret returned by [`Authenticator::enroll_second_factor`].
+    ///
+    /// Returns one-time recovery codes, which are not shown again. Wrong codes count as failed
+    /// logins of the account.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    /// `ServiceError::SecondFactorNotEnrolled` if enrollment was not started
+    /// `ServiceError::SecondFactorAlreadyEnabled` if two-factor authentication is already enabled
+    /// `ServiceError::TooManyAttempts` if too many logins of the account failed recently
+    /// `ServiceError::InvalidSecondFactor` if the code is wrong
+    #[instrument(skip_all)]
+    pub async fn confirm_second_factor(
+        &self,
+        session_token: &str,
+        code: SecondFactorCode,
+    ) -> Result<RecoveryCodes, ServiceError> {
+        let credentials = self.find_session_credentials(session_token).await?;
+
+        let mut second_factor = match credentials.second_factor() {
+            Some(second_factor) if second_factor.is_enabled() => {
+                return Err(ServiceError::SecondFactorAlreadyEnabled)
+            }
+            Some(second_factor) => second_factor.clone(),
+            None => return Err(ServiceError::SecondFactorNotEnrolled),
+        };
+
+        let throttle_keys = self.account_throttle_keys(credentials.username());
+        let previous_attempts = self.record_attempt(&throttle_keys).await?;
+
+        if !second_factor.verify_code(&code.code) {
+            return Err(ServiceError::InvalidSecondFactor);
+        }
+
+        self.forgive_attempt(&throttle_keys, &previous_attempts)
+            .await?;
+
+        let recovery_codes = second_factor.enable();
+
+        // Fails if the pending secret was replaced or confirmed concurrently, in which case the
+        // code was not generated from the secret that would be enabled
+        if !self
+            .store
+            .update_second_factor(
+                credentials.username(),
+                credentials.second_factor(),
+                Some(&second_factor),
+            )
+            .await?
+        {
+            return Err(ServiceError::InvalidSecondFactor);
+        }
+
+        Ok(recovery_codes)
+    }
+
+    /// Turns off two-factor authentication for the user owning the session token, given a TOTP
+    /// code or an unused recovery code. Wrong codes count as failed logins of the account, so a
+    /// stolen session can't be used to guess them.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    /// `ServiceError::SecondFactorNotEnrolled` if two-factor authentication is not enabled
+    /// `ServiceError::TooManyAttempts` if too many logins of the account failed recently
+    /// `ServiceError::InvalidSecondFactor` if the code is wrong
+    #[instrument(skip_all)]
+    pub async fn disable_second_factor(
+        &self,
+        session_token: &str,
+        code: SecondFactorCode,
+    ) -> Result<(), ServiceError> {
+        let credentials = self.find_session_credentials(session_token).await?;
+
+        if !credentials.requires_second_factor() {
+            return Err(ServiceError::SecondFactorNotEnrolled);
+        }
+
+        let throttle_keys = self.account_throttle_keys(credentials.username());
+        let previous_attempts = self.record_attempt(&throttle_keys).await?;
+
+        if Self::check_second_factor(&credentials, &code.code).is_none() {
+            return Err(ServiceError::InvalidSecondFactor);
+        }
+
+        self.forgive_attempt(&throttle_keys, &previous_attempts)
+            .await?;
+
+        // Removing the second factor from the state the code was checked against also uses up
+        // the code, so it can't be replayed concurrently
+        if !self
+            .store
+            .update_second_factor(credentials.username(), credentials.second_factor(), None)
+            .await?
+        {
+            return Err(ServiceError::InvalidSecondFactor);
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the credentials of the user owning the session token
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    async fn find_session_credentials(
+        &self,
+        session_token: &str,
+    ) -> Result<Credentials, ServiceError> {
+        let session = self.find_session(session_token).await?;
+
+        match self.store.find_credentials(session.username()).await? {
+            Some(credentials) => Ok(credentials),
+            None => Err(ServiceError::AuthenticationError),
         }
     }
 
@@ -183,58 +714,321 @@ impl Authenticator {
     /// # Errors
     /// `ServiceError::DatabaseError` if a database error occurs
     /// `ServiceError::AuthenticationError` if the session token is invalid
+    #[instrument(skip_all)]
     pub async fn authenticate(&self, session_token: &str) -> Result<Username, ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-        let session_token_collection = self.database.collection::<SessionToken>("sessions");
+        let session = self.find_session(session_token).await?;
 
-        let session_token_option = session_token_collection
-            .find_one_with_session(doc! { "token": session_token }, None, &mut session)
-            .await?;
+        NormalizedUsername::parse(session.username())
+            .map(Username::from)
+            .map_err(|_| ServiceError::AuthenticationError)
+    }
 
-        match session_token_option {
-            Some(session_token_object) => {
-                session_token_collection
-                    .update_one_with_session(
-                        doc! { "token": session_token },
-                        doc! { "$set": { "createdAt": DateTime::now() } },
-                        None,
-                        &mut session,
-                    )
-                    .await?;
+    /// Looks up the session of a session token, marking it as used
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    async fn find_session(&self, session_token: &str) -> Result<Session, ServiceError> {
+        match self.store.touch_session(&hash_token(session_token)).await? {
+            Some(session) => self.check_session_lifetime(session).await,
+            None => Err(ServiceError::AuthenticationError),
+        }
+    }
+
+    /// Revokes a session that outlived the maximum session lifetime
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session is too old
+    async fn check_session_lifetime(&self, session: Session) -> Result<Session, ServiceError> {
+        if session.is_older_than(self.session_max_lifetime) {
+            self.store
+                .delete_user_session(session.username(), session.id())
+                .await?;
 
-                Ok(session_token_object.username().clone().into())
+            return Err(ServiceError::AuthenticationError);
+        }
+
+        Ok(session)
+    }
+
+    /// Refreshes a session, rotating its session token and issuing a new access token.
+    ///
+    /// The given session token can't be used again afterwards. Presenting a session token that
+    /// was already rotated is treated as theft and revokes the session, so neither the thief nor
+    /// the legitimate client can keep using it.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid, was already used or
+    /// the session outlived the maximum session lifetime
+    #[instrument(skip_all)]
+    pub async fn refresh(&self, session_token: &str) -> Result<SessionToken, ServiceError> {
+        let token_hash = hash_token(session_token);
+        let new_token = generate_token();
+
+        let session = match self
+            .store
+            .rotate_session(&token_hash, &hash_token(&new_token))
+            .await?
+        {
+            Some(session) => self.check_session_lifetime(session).await?,
+            None => {
+                if let Some(session) = self.store.delete_reused_session(&token_hash).await? {
+                    tracing::warn!(
+                        "Reuse of a rotated session token detected, revoked session {} of {}",
+                        session.id(),
+                        session.username()
+                    );
+                }
+
+                return Err(ServiceError::AuthenticationError);
             }
-            None => Err(ServiceError::AuthenticationError),
+        };
+
+        let access_token = self.token_signer.sign(session.username(), session.id());
+
+        Ok(
+            SessionToken::from_parts(session.username(), &new_token)
+                .with_access_token(access_token),
+        )
+    }
+
+    /// Lists the active sessions of the user owning the session token
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    #[instrument(skip_all)]
+    pub async fn list_sessions(
+        &self,
+        session_token: &str,
+    ) -> Result<Vec<SessionInfo>, ServiceError> {
+        let current = self.find_session(session_token).await?;
+
+        let mut sessions = self
+            .store
+            .find_user_sessions(current.username())
+            .await?
+            .iter()
+            .filter(|session| !session.is_older_than(self.session_max_lifetime))
+            .map(|session| SessionInfo::new(session, session.id() == current.id()))
+            .collect::<Vec<_>>();
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen));
+
+        Ok(sessions)
+    }
+
+    /// Revokes one of the sessions of the user owning the session token by its id
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    /// `ServiceError::SessionNotFound` if the user has no session with the given id
+    #[instrument(skip_all)]
+    pub async fn revoke_session(&self, session_token: &str, id: &str) -> Result<(), ServiceError> {
+        let current = self.find_session(session_token).await?;
+
+        if id.is_empty()
+            || !self
+                .store
+                .delete_user_session(current.username(), id)
+                .await?
+        {
+            return Err(ServiceError::SessionNotFound(id.to_string()));
         }
+
+        Ok(())
+    }
+
+    /// Revokes every session of the user owning the session token, including itself. Returns
+    /// the user, so clients caching their tokens know which ones to drop.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    #[instrument(skip_all)]
+    pub async fn logout_all(&self, session_token: &str) -> Result<Username, ServiceError> {
+        let current = self.find_session(session_token).await?;
+
+        self.store.delete_user_sessions(current.username()).await?;
+
+        NormalizedUsername::parse(current.username())
+            .map(Username::from)
+            .map_err(|_| ServiceError::AuthenticationError)
     }
 
     /// Logs out a user with the given session token
     ///
     /// # Errors
     /// `ServiceError::DatabaseError` if a database error occurs
-    pub async fn logout(&self, session_token: &String) -> Result<(), ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-        let session_token_collection = self.database.collection::<SessionToken>("sessions");
+    #[instrument(skip_all)]
+    pub async fn logout(&self, session_token: &str) -> Result<(), ServiceError> {
+        self.store.delete_session(&hash_token(session_token)).await
+    }
+
+    /// Checks whether a user with a given username exists
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::InvalidUsername` if the username is not allowed
+    #[instrument(skip_all)]
+    pub async fn user_exists(&self, username: String) -> Result<bool, ServiceError> {
+        let username = NormalizedUsername::parse(&username)?;
+
+        let credentials_option = self.store.find_credentials(username.as_str()).await?;
+
+        Ok(credentials_option.is_some())
+    }
+
+    /// Changes the password of the user owning the session token.
+    ///
+    /// All existing sessions of the user are revoked and a new session token for the same client
+    /// is returned.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::AuthenticationError` if the session token is invalid
+    /// `ServiceError::TooManyAttempts` if too many logins of the account failed recently
+    /// `ServiceError::InvalidPassword` if the old password is incorrect
+    /// `ServiceError::PasswordPolicyViolation` if the new password does not satisfy the password policy
+    #[instrument(skip_all)]
+    pub async fn change_password(
+        &self,
+        session_token: &str,
+        change: PasswordChange,
+    ) -> Result<SessionToken, ServiceError> {
+        let session = self.find_session(session_token).await?;
+        let username = session.username();
+
+        let credentials = match self.store.find_credentials(username).await? {
+            Some(credentials) => credentials,
+            None => return Err(ServiceError::AuthenticationError),
+        };
 
-        session_token_collection
-            .delete_one_with_session(doc! { "token": session_token }, None, &mut session)
+        // Wrong old passwords count as failed logins, so a stolen session can't be used to guess
+        // the password
+        let throttle_keys = self.account_throttle_keys(username);
+        let previous_attempts = self.record_attempt(&throttle_keys).await?;
+
+        let old_login = LoginInfo::new(username, &change.old_password);
+
+        if !verify_password(&credentials, &old_login).await {
+            return Err(ServiceError::InvalidPassword);
+        }
+
+        self.forgive_attempt(&throttle_keys, &previous_attempts)
+            .await?;
+
+        self.replace_password(LoginInfo::new(username, &change.new_password))
             .await?;
 
+        self.create_and_store_session_token(username.clone(), session.client())
+            .await
+    }
+
+    /// Issues a password reset token for a user and delivers it through the notifier.
+    ///
+    /// Succeeds without doing anything if the user does not exist, and succeeds as well if the
+    /// token could not be delivered, so the response can't be used to find out which usernames
+    /// are registered. Delivery failures are logged instead.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::InvalidUsername` if the username is not allowed
+    #[instrument(skip_all)]
+    pub async fn request_password_reset(&self, username: String) -> Result<(), ServiceError> {
+        let username = NormalizedUsername::parse(&username)?;
+
+        if self
+            .store
+            .find_credentials(username.as_str())
+            .await?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let (reset_token, token) = ResetToken::new(username.clone().into(), RESET_TOKEN_TTL);
+        let expires_at = reset_token.expires_at();
+
+        self.store.insert_reset_token(reset_token).await?;
+
+        if let Err(err) = self
+            .notifier
+            .send_password_reset(username.as_str(), &token, expires_at)
+            .await
+        {
+            tracing::error!("Failed to deliver password reset token: {}", err);
+        }
+
         Ok(())
     }
 
-    /// Checks whether a user with a given username exists
+    /// Sets a new password using a reset token. The token can only be used once.
+    ///
+    /// All existing sessions of the user are revoked.
     ///
     /// # Errors
     /// `ServiceError::DatabaseError` if a database error occurs
-    pub async fn user_exists(&self, username: String) -> Result<bool, ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-        let credentials_collection = self.database.collection::<Credentials>("credentials");
+    /// `ServiceError::InvalidResetToken` if the token is unknown, expired or already used
+    /// `ServiceError::PasswordPolicyViolation` if the new password does not satisfy the password policy
+    #[instrument(skip_all)]
+    pub async fn reset_password(&self, reset: PasswordReset) -> Result<(), ServiceError> {
+        let reset_token = match self
+            .store
+            .take_reset_token(&ResetToken::hash(&reset.token))
+            .await?
+        {
+            Some(reset_token) if !reset_token.is_expired() => reset_token,
+            _ => return Err(ServiceError::InvalidResetToken),
+        };
+
+        let info = LoginInfo::new(reset_token.username(), &reset.new_password);
 
-        let credentials_option = credentials_collection
-            .find_one_with_session(doc! { "username": username.clone() }, None, &mut session)
+        // Keep the token usable if the new password is rejected, so the user can try again
+        if let Err(err) = self.password_policy.check(&info) {
+            self.store.insert_reset_token(reset_token).await?;
+            return Err(err);
+        }
+
+        self.replace_password(info).await
+    }
+
+    /// Checks the new password against the policy, stores its hash and revokes every session
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::PasswordPolicyViolation` if the password does not satisfy the password policy
+    async fn replace_password(&self, info: LoginInfo) -> Result<(), ServiceError> {
+        self.password_policy.check(&info)?;
+
+        self.store
+            .update_password(&self.hash_credentials(&info).await)
             .await?;
 
-        Ok(credentials_option.is_some())
+        self.store.delete_user_sessions(&info.username).await
+    }
+}
+
+/// Checks the password of the login info against the credentials on the blocking thread pool
+async fn verify_password(credentials: &Credentials, info: &LoginInfo) -> bool {
+    let (credentials, info) = (credentials.clone(), info.clone());
+
+    run_blocking(move || credentials.matches(&info)).await
+}
+
+/// Runs a password hashing function on the blocking thread pool, as hashing is deliberately slow
+/// and would otherwise hold up every other request handled by the same worker
+async fn run_blocking<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        // Hashing only fails by panicking, which is passed on as if it happened in the caller
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
     }
 }
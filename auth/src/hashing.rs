@@ -0,0 +1,262 @@
+use anyhow::{anyhow, bail};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+    Argon2,
+};
+use core_rs::config::{HashingConfig, PasswordHashAlgorithm};
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+
+/// Prefix shared by every bcrypt hash (`$2a$`, `$2b$`, `$2y$`, ...)
+const BCRYPT_PREFIX: &str = "$2";
+
+/// A password hashing algorithm together with its cost parameters.
+///
+/// Hashes are stored as self-describing strings: PHC strings for Argon2id, PBKDF2 and scrypt
+/// (e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`) and the modular crypt format for bcrypt
+/// (e.g. `$2b$12$<salt+hash>`). Every stored credential therefore records which algorithm and
+/// parameters produced it, so old hashes keep verifying after the configuration changes and can be
+/// upgraded lazily with [`HashAlgorithm::needs_rehash`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Argon2id with memory cost in KiB, number of iterations and degree of parallelism
+    Argon2id {
+        memory_cost: u32,
+        time_cost: u32,
+        parallelism: u32,
+    },
+    /// PBKDF2-HMAC-SHA256 with the given number of rounds
+    Pbkdf2 { rounds: u32 },
+    /// bcrypt with the given cost (log2 of the number of rounds).
+    ///
+    /// Note that bcrypt only uses the first 72 bytes of a password.
+    Bcrypt { cost: u32 },
+    /// scrypt with the given CPU/memory cost (log2 of N), block size and parallelism
+    Scrypt { log_n: u8, r: u32, p: u32 },
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        HashAlgorithm::Argon2id {
+            memory_cost: argon2::Params::DEFAULT_M_COST,
+            time_cost: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// Returns the algorithm with its default cost parameters given its name.
+    ///
+    /// Accepted names are `argon2id`, `pbkdf2`, `bcrypt` and `scrypt`.
+    ///
+    /// # Errors
+    /// Fails if the name is not a known algorithm
+    pub fn with_default_params(name: &str) -> anyhow::Result<Self> {
+        let kind = match name {
+            "argon2id" => PasswordHashAlgorithm::Argon2id,
+            "pbkdf2" => PasswordHashAlgorithm::Pbkdf2,
+            "bcrypt" => PasswordHashAlgorithm::Bcrypt,
+            "scrypt" => PasswordHashAlgorithm::Scrypt,
+            _ => bail!("Unknown password hashing algorithm '{}'", name),
+        };
+
+        Ok(HashAlgorithm::with_recommended_params(kind))
+    }
+
+    /// Returns the algorithm with its recommended cost parameters
+    pub fn with_recommended_params(kind: PasswordHashAlgorithm) -> Self {
+        match kind {
+            PasswordHashAlgorithm::Argon2id => HashAlgorithm::default(),
+            PasswordHashAlgorithm::Pbkdf2 => HashAlgorithm::Pbkdf2 {
+                rounds: pbkdf2::Params::RECOMMENDED_ROUNDS as u32,
+            },
+            PasswordHashAlgorithm::Bcrypt => HashAlgorithm::Bcrypt {
+                cost: bcrypt::DEFAULT_COST,
+            },
+            PasswordHashAlgorithm::Scrypt => HashAlgorithm::Scrypt {
+                log_n: scrypt::Params::RECOMMENDED_LOG_N,
+                r: scrypt::Params::RECOMMENDED_R,
+                p: scrypt::Params::RECOMMENDED_P,
+            },
+        }
+    }
+
+    /// Creates the algorithm selected in the `auth.hashing` section of the configuration.
+    ///
+    /// Costs that are not set default to the recommended values of the algorithm.
+    ///
+    /// # Errors
+    /// Fails if the resulting parameters are not accepted by the algorithm
+    pub fn from_config(config: &HashingConfig) -> anyhow::Result<Self> {
+        let algorithm = match HashAlgorithm::with_recommended_params(config.algorithm) {
+            HashAlgorithm::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => HashAlgorithm::Argon2id {
+                memory_cost: config.argon2_memory_cost.unwrap_or(memory_cost),
+                time_cost: config.argon2_time_cost.unwrap_or(time_cost),
+                parallelism: config.argon2_parallelism.unwrap_or(parallelism),
+            },
+            HashAlgorithm::Pbkdf2 { rounds } => HashAlgorithm::Pbkdf2 {
+                rounds: config.pbkdf2_rounds.unwrap_or(rounds),
+            },
+            HashAlgorithm::Bcrypt { cost } => HashAlgorithm::Bcrypt {
+                cost: config.bcrypt_cost.unwrap_or(cost),
+            },
+            HashAlgorithm::Scrypt { log_n, r, p } => HashAlgorithm::Scrypt {
+                log_n: config.scrypt_log_n.unwrap_or(log_n),
+                r: config.scrypt_r.unwrap_or(r),
+                p: config.scrypt_p.unwrap_or(p),
+            },
+        };
+
+        algorithm.validate()?;
+
+        Ok(algorithm)
+    }
+
+    /// Checks that the cost parameters are accepted by the algorithm
+    ///
+    /// # Errors
+    /// Fails with a description of the problem if the parameters are invalid
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match *self {
+            HashAlgorithm::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
+                argon2::Params::new(memory_cost, time_cost, parallelism, None)
+                    .map_err(|err| anyhow!("Invalid Argon2id parameters: {}", err))?;
+            }
+            HashAlgorithm::Pbkdf2 { rounds } => {
+                if rounds == 0 {
+                    bail!("Invalid PBKDF2 parameters: rounds must be positive");
+                }
+            }
+            HashAlgorithm::Bcrypt { cost } => {
+                if !(4..=31).contains(&cost) {
+                    bail!("Invalid bcrypt parameters: cost must be between 4 and 31");
+                }
+            }
+            HashAlgorithm::Scrypt { log_n, r, p } => {
+                scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+                    .map_err(|err| anyhow!("Invalid scrypt parameters: {}", err))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hashes a password with a fresh random salt
+    ///
+    /// # Panics
+    /// Panics if the parameters are invalid, see [`HashAlgorithm::validate`]
+    pub fn hash(&self, password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+
+        let hash = match *self {
+            HashAlgorithm::Argon2id { .. } => {
+                self.argon2().hash_password(password.as_bytes(), &salt)
+            }
+            HashAlgorithm::Pbkdf2 { rounds } => Pbkdf2.hash_password_customized(
+                password.as_bytes(),
+                Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()),
+                None,
+                pbkdf2::Params {
+                    rounds,
+                    output_length: 32,
+                },
+                &salt,
+            ),
+            HashAlgorithm::Bcrypt { cost } => {
+                return bcrypt::hash(password, cost).expect("Invalid bcrypt parameters")
+            }
+            HashAlgorithm::Scrypt { log_n, r, p } => Scrypt.hash_password_customized(
+                password.as_bytes(),
+                None,
+                None,
+                scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+                    .expect("Invalid scrypt parameters"),
+                &salt,
+            ),
+        };
+
+        hash.expect("Invalid password hashing parameters")
+            .to_string()
+    }
+
+    /// Returns true if the password matches the hash, whichever supported algorithm produced it
+    ///
+    /// The comparison is done in constant time.
+    pub fn verify(hash: &str, password: &str) -> bool {
+        if hash.starts_with(BCRYPT_PREFIX) {
+            return bcrypt::verify(password, hash).unwrap_or(false);
+        }
+
+        match PasswordHash::new(hash) {
+            Ok(hash) => hash
+                .verify_password(&[&Argon2::default(), &Pbkdf2, &Scrypt], password)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns true if the hash was not produced by this algorithm with these exact parameters
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        if let Some(cost) = hash.strip_prefix(BCRYPT_PREFIX) {
+            let cost = cost.split('$').nth(1).and_then(|cost| cost.parse().ok());
+
+            return !matches!(*self, HashAlgorithm::Bcrypt { cost: expected } if cost == Some(expected));
+        }
+
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+
+        let current = match hash.algorithm.as_str() {
+            "argon2id" => {
+                argon2::Params::try_from(&hash)
+                    .ok()
+                    .map(|params| HashAlgorithm::Argon2id {
+                        memory_cost: params.m_cost(),
+                        time_cost: params.t_cost(),
+                        parallelism: params.p_cost(),
+                    })
+            }
+            "pbkdf2-sha256" => {
+                pbkdf2::Params::try_from(&hash)
+                    .ok()
+                    .map(|params| HashAlgorithm::Pbkdf2 {
+                        rounds: params.rounds,
+                    })
+            }
+            "scrypt" => scrypt::Params::try_from(&hash)
+                .ok()
+                .map(|params| HashAlgorithm::Scrypt {
+                    log_n: params.log_n(),
+                    r: params.r(),
+                    p: params.p(),
+                }),
+            _ => None,
+        };
+
+        current != Some(*self)
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        let params = match *self {
+            HashAlgorithm::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => argon2::Params::new(memory_cost, time_cost, parallelism, None)
+                .expect("Invalid Argon2id parameters"),
+            _ => argon2::Params::default(),
+        };
+
+        Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+    }
+}
@@ -1,12 +1,34 @@
-use actix_web::{error::ParseError, http::header::Header, HttpRequest};
-use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
-use core_rs::{error::ServiceError, Username};
+use std::time::Duration;
+
+use actix_web::{http::header, HttpRequest};
+use core_rs::{
+    client_ip::TrustedProxies,
+    error::{FieldError, ServiceError},
+    username::NormalizedUsername,
+    validation::Validate,
+    Username,
+};
+use hashing::HashAlgorithm;
+use mongodb::bson::DateTime;
 use pbkdf2::pbkdf2_hmac_array;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokens::AccessToken;
+use totp::SecondFactor;
+
+pub use core_rs::auth_client::extract_bearer_token;
 
 pub mod db;
+pub mod hashing;
+pub mod metrics;
+pub mod notifier;
+pub mod policy;
+pub mod store;
+pub mod throttle;
+pub mod tokens;
+pub mod totp;
 
 #[derive(Deserialize)]
 pub struct UserExistsParams {
@@ -28,25 +50,143 @@ impl LoginInfo {
             password: password.to_string(),
         }
     }
+
+    /// Returns the LoginInfo with its username validated and normalized
+    ///
+    /// # Errors
+    /// `ServiceError::InvalidUsername` if the username is not allowed
+    pub fn normalized(self) -> Result<Self, ServiceError> {
+        let username = NormalizedUsername::parse(&self.username)?;
+
+        Ok(LoginInfo {
+            username: username.into(),
+            password: self.password,
+        })
+    }
+}
+
+/// Requires both fields, the username itself and the password are checked by the login or
+/// registration
+impl Validate for LoginInfo {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if self.username.trim().is_empty() {
+            errors.push(FieldError::new(
+                "username",
+                "required",
+                "Username is required".to_string(),
+            ));
+        }
+
+        if self.password.is_empty() {
+            errors.push(FieldError::new(
+                "password",
+                "required",
+                "Password is required".to_string(),
+            ));
+        }
+
+        errors
+    }
+}
+
+/// Returns a `required` error for the field if its value is empty
+fn required(field: &str, value: &str, name: &str) -> Option<FieldError> {
+    value
+        .is_empty()
+        .then(|| FieldError::new(field, "required", format!("{} is required", name)))
+}
+
+/// Body of a password change request
+#[derive(Clone, Deserialize)]
+pub struct PasswordChange {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+impl Validate for PasswordChange {
+    fn validate(&self) -> Vec<FieldError> {
+        [
+            required("old_password", &self.old_password, "Old password"),
+            required("new_password", &self.new_password, "New password"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Body of a request for a password reset token
+#[derive(Clone, Deserialize)]
+pub struct PasswordResetRequest {
+    pub username: String,
+}
+
+impl Validate for PasswordResetRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        required("username", self.username.trim(), "Username")
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Body of a password reset using a previously issued reset token
+#[derive(Clone, Deserialize)]
+pub struct PasswordReset {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl Validate for PasswordReset {
+    fn validate(&self) -> Vec<FieldError> {
+        [
+            required("token", &self.token, "Reset token"),
+            required("new_password", &self.new_password, "New password"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Generates a random alphanumeric token of 32 characters
+pub(crate) fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect::<String>()
+}
+
+/// Returns the SHA-256 digest under which a random token is stored.
+///
+/// Tokens are generated with enough entropy that a plain digest can't be brute forced, and a
+/// leaked database does not contain any usable token.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 /// A struct that contains the username and a session token
+///
+/// The session token is long-lived and doubles as the refresh token for short-lived signed
+/// access tokens, which are included when the session is created.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SessionToken {
     username: String,
     token: String,
+    #[serde(flatten)]
+    access_token: Option<AccessToken>,
 }
 
 impl SessionToken {
     /// Creates a new random SessionToken given a username
     pub fn new(username: String) -> Self {
-        let token = rand::thread_rng()
-            .sample_iter(&rand::distributions::Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect::<String>();
-
-        SessionToken { username, token }
+        SessionToken {
+            username,
+            token: generate_token(),
+            access_token: None,
+        }
     }
 
     /// Returns the username of the SessionToken
@@ -64,43 +204,437 @@ impl SessionToken {
         SessionToken {
             username: username.to_string(),
             token: token.to_string(),
+            access_token: None,
+        }
+    }
+
+    /// Returns the signed access token issued together with the SessionToken, if any
+    pub fn access_token(&self) -> Option<&AccessToken> {
+        self.access_token.as_ref()
+    }
+
+    /// Attaches a signed access token to the SessionToken
+    pub fn with_access_token(mut self, access_token: AccessToken) -> Self {
+        self.access_token = Some(access_token);
+        self
+    }
+}
+
+/// Information about the client a session was created from
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl ClientInfo {
+    /// Reads the user agent and client IP address from a request
+    ///
+    /// The IP address only honours the `Forwarded` and `X-Forwarded-For` headers for requests
+    /// from one of the [`TrustedProxies`] registered as app data, otherwise it is the address
+    /// the request was received from.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let ip = TrustedProxies::of(req).client_ip(req);
+
+        ClientInfo { user_agent, ip }
+    }
+}
+
+/// How many of the previous tokens of a session are remembered to detect their reuse
+pub const USED_TOKEN_HISTORY: usize = 64;
+
+/// A session as it is stored, tying a session token to its user and client.
+///
+/// Only the digest of the token is stored, see [`hash_token`]. `lastSeen` is bumped every time
+/// the session is used, while `createdAt` keeps the time the session was created.
+///
+/// The session token is rotated every time it is used to refresh the session. The digests of
+/// the most recent previous tokens are kept in `used_token_hashes`, so presenting one of them
+/// again can be detected as theft and revoke the session along with every token descending from
+/// it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    id: String,
+    username: String,
+    token_hash: String,
+    #[serde(rename = "createdAt", default = "DateTime::now")]
+    created_at: DateTime,
+    #[serde(rename = "lastSeen", default = "DateTime::now")]
+    last_seen: DateTime,
+    #[serde(default)]
+    user_agent: Option<String>,
+    #[serde(default)]
+    client_ip: Option<String>,
+    #[serde(default)]
+    used_token_hashes: Vec<String>,
+}
+
+impl Session {
+    /// Creates a new session with a random id and token for a user.
+    ///
+    /// Returns the session to store together with the token to hand to the client.
+    pub fn new(username: String, client: ClientInfo) -> (Self, SessionToken) {
+        let now = DateTime::now();
+        let session_token = SessionToken::new(username.clone());
+
+        let session = Session {
+            id: generate_token(),
+            username,
+            token_hash: hash_token(session_token.token()),
+            created_at: now,
+            last_seen: now,
+            user_agent: client.user_agent,
+            client_ip: client.ip,
+            used_token_hashes: Vec::new(),
+        };
+
+        (session, session_token)
+    }
+
+    /// Returns the public identifier of the Session
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    /// Returns the username of the Session
+    pub fn username(&self) -> &String {
+        &self.username
+    }
+
+    /// Returns the digest of the token of the Session
+    pub fn token_hash(&self) -> &String {
+        &self.token_hash
+    }
+
+    /// Returns the digests of the previous tokens of the Session, oldest first
+    pub fn used_token_hashes(&self) -> &[String] {
+        &self.used_token_hashes
+    }
+
+    /// Returns the time the Session was created
+    pub fn created_at(&self) -> DateTime {
+        self.created_at
+    }
+
+    /// Returns the time the Session was last used
+    pub fn last_seen(&self) -> DateTime {
+        self.last_seen
+    }
+
+    /// Returns true if the Session was created longer ago than the given lifetime
+    pub fn is_older_than(&self, lifetime: Duration) -> bool {
+        let age = DateTime::now().timestamp_millis() - self.created_at.timestamp_millis();
+
+        age >= lifetime.as_millis() as i64
+    }
+
+    /// Marks the Session as used now
+    pub fn touch(&mut self) {
+        self.last_seen = DateTime::now();
+    }
+
+    /// Replaces the token of the Session, remembering the digest of the previous one, and marks
+    /// the Session as used now
+    pub fn rotate(&mut self, new_token_hash: String) {
+        let previous = std::mem::replace(&mut self.token_hash, new_token_hash);
+
+        self.used_token_hashes.push(previous);
+
+        if self.used_token_hashes.len() > USED_TOKEN_HISTORY {
+            let excess = self.used_token_hashes.len() - USED_TOKEN_HISTORY;
+            self.used_token_hashes.drain(..excess);
+        }
+
+        self.touch();
+    }
+
+    /// Returns the client the Session was created from
+    pub fn client(&self) -> ClientInfo {
+        ClientInfo {
+            user_agent: self.user_agent.clone(),
+            ip: self.client_ip.clone(),
         }
     }
 }
 
-/// A struct that contains the username, password hash and salt of a user
-#[derive(Debug, Serialize, Deserialize)]
+/// A session as it is shown to its user, without the token itself
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: String,
+    /// Creation time in milliseconds since the Unix epoch
+    pub created_at: i64,
+    /// Last seen time in milliseconds since the Unix epoch
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub client_ip: Option<String>,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+}
+
+impl SessionInfo {
+    /// Creates the public view of a session
+    pub fn new(session: &Session, current: bool) -> Self {
+        SessionInfo {
+            id: session.id.clone(),
+            created_at: session.created_at.timestamp_millis(),
+            last_seen: session.last_seen.timestamp_millis(),
+            user_agent: session.user_agent.clone(),
+            client_ip: session.client_ip.clone(),
+            current,
+        }
+    }
+}
+
+/// Body of a request carrying a TOTP code or a recovery code
+#[derive(Clone, Deserialize)]
+pub struct SecondFactorCode {
+    pub code: String,
+}
+
+impl Validate for SecondFactorCode {
+    fn validate(&self) -> Vec<FieldError> {
+        required("code", &self.code, "Code").into_iter().collect()
+    }
+}
+
+/// Body of the second step of a login, answering a [`SecondFactorChallenge`]
+#[derive(Clone, Deserialize)]
+pub struct SecondFactorVerification {
+    pub challenge_token: String,
+    /// A TOTP code or an unused recovery code
+    pub code: String,
+}
+
+impl Validate for SecondFactorVerification {
+    fn validate(&self) -> Vec<FieldError> {
+        [
+            required("challenge_token", &self.challenge_token, "Challenge token"),
+            required("code", &self.code, "Code"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Returned by a login with a correct password when the user has two-factor authentication
+/// enabled. The challenge token has to be sent back together with a code to get a session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SecondFactorChallenge {
+    pub second_factor_required: bool,
+    pub challenge_token: String,
+    /// Lifetime of the challenge token in seconds
+    pub expires_in: u64,
+}
+
+/// The result of a successful password check on login
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    /// The user is logged in
+    Session(SessionToken),
+    /// The user has to provide a second factor to log in
+    SecondFactorRequired(SecondFactorChallenge),
+}
+
+impl LoginResponse {
+    /// Returns the session token if no second factor is required
+    pub fn into_session_token(self) -> Option<SessionToken> {
+        match self {
+            LoginResponse::Session(session_token) => Some(session_token),
+            LoginResponse::SecondFactorRequired(_) => None,
+        }
+    }
+}
+
+/// A pending login waiting for its second factor, as it is stored.
+///
+/// Only the SHA-256 digest of the challenge token is kept.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginChallenge {
+    username: String,
+    token_hash: String,
+    expires_at: DateTime,
+    #[serde(default)]
+    failed_attempts: u32,
+    #[serde(default)]
+    user_agent: Option<String>,
+    #[serde(default)]
+    client_ip: Option<String>,
+}
+
+impl LoginChallenge {
+    /// Creates a new random login challenge for a username and the client logging in that expires
+    /// after the given duration.
+    ///
+    /// Returns the challenge to store together with the plain text token to send to the client.
+    pub fn new(username: String, client: ClientInfo, ttl: Duration) -> (Self, String) {
+        let token = generate_token();
+        let expires_at =
+            DateTime::from_millis(DateTime::now().timestamp_millis() + ttl.as_millis() as i64);
+
+        let challenge = LoginChallenge {
+            username,
+            token_hash: hash_token(&token),
+            expires_at,
+            failed_attempts: 0,
+            user_agent: client.user_agent,
+            client_ip: client.ip,
+        };
+
+        (challenge, token)
+    }
+
+    /// Returns the username the LoginChallenge was issued for
+    pub fn username(&self) -> &String {
+        &self.username
+    }
+
+    /// Returns the digest of the LoginChallenge token
+    pub fn token_hash(&self) -> &String {
+        &self.token_hash
+    }
+
+    /// Returns the time after which the LoginChallenge can no longer be answered
+    pub fn expires_at(&self) -> DateTime {
+        self.expires_at
+    }
+
+    /// Returns true if the LoginChallenge can no longer be answered
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= DateTime::now()
+    }
+
+    /// Returns how many wrong codes were sent for the LoginChallenge
+    pub fn failed_attempts(&self) -> u32 {
+        self.failed_attempts
+    }
+
+    /// Records a wrong code sent for the LoginChallenge
+    pub fn record_failed_attempt(&mut self) {
+        self.failed_attempts += 1;
+    }
+
+    /// Returns the client that started the login
+    pub fn client(&self) -> ClientInfo {
+        ClientInfo {
+            user_agent: self.user_agent.clone(),
+            ip: self.client_ip.clone(),
+        }
+    }
+}
+
+/// A single-use password reset token as it is stored.
+///
+/// Only the SHA-256 digest of the token is kept, the token itself is handed to the user
+/// through a [`Notifier`](notifier::Notifier).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResetToken {
+    username: String,
+    token_hash: String,
+    expires_at: DateTime,
+}
+
+impl ResetToken {
+    /// Creates a new random reset token for a username that expires after the given duration.
+    ///
+    /// Returns the token to store together with the plain text token to send to the user.
+    pub fn new(username: String, ttl: Duration) -> (Self, String) {
+        let token = generate_token();
+        let expires_at =
+            DateTime::from_millis(DateTime::now().timestamp_millis() + ttl.as_millis() as i64);
+
+        let reset_token = ResetToken {
+            username,
+            token_hash: hash_token(&token),
+            expires_at,
+        };
+
+        (reset_token, token)
+    }
+
+    /// Returns the digest under which a plain text reset token is stored
+    pub fn hash(token: &str) -> String {
+        hash_token(token)
+    }
+
+    /// Returns the username the ResetToken was issued for
+    pub fn username(&self) -> &String {
+        &self.username
+    }
+
+    /// Returns the digest of the ResetToken
+    pub fn token_hash(&self) -> &String {
+        &self.token_hash
+    }
+
+    /// Returns the time after which the ResetToken can no longer be used
+    pub fn expires_at(&self) -> DateTime {
+        self.expires_at
+    }
+
+    /// Returns true if the ResetToken can no longer be used
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= DateTime::now()
+    }
+}
+
+/// A struct that contains the username and password hash of a user
+///
+/// Password hashes are self-describing strings recording the algorithm and parameters used,
+/// see [`HashAlgorithm`]. Records created before the switch to configurable hashing store a
+/// PBKDF2 hash hex encoded twice together with a separate `salt`; these still verify but should
+/// be replaced with a fresh hash, see [`Credentials::needs_rehash`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Credentials {
     username: String,
     password_hash: String,
-    salt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    second_factor: Option<SecondFactor>,
 }
 
 impl Credentials {
     /// Creates a new Credentials struct given a LoginInfo
     ///
-    /// Will generate a random salt and hash the password with the salt
+    /// Will hash the password with the default algorithm (Argon2id) and a random salt
     pub fn new(login_info: &LoginInfo) -> Self {
-        let salt = rand::thread_rng()
-            .sample_iter(&rand::distributions::Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect::<String>();
-
-        let hashed_password = Credentials::create_hash(&login_info.password, &salt);
+        Credentials::with_algorithm(login_info, &HashAlgorithm::default())
+    }
 
+    /// Creates a new Credentials struct given a LoginInfo, hashing the password with the given
+    /// algorithm and a random salt
+    pub fn with_algorithm(login_info: &LoginInfo, algorithm: &HashAlgorithm) -> Self {
         Credentials {
             username: login_info.username.clone(),
-            password_hash: hex::encode(hashed_password),
-            salt,
+            password_hash: algorithm.hash(&login_info.password),
+            salt: None,
+            second_factor: None,
         }
     }
 
-    fn create_hash(password: &String, salt: &String) -> String {
-        let hashed_password =
-            pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), salt.as_bytes(), 4096);
+    /// Returns the Credentials with the password replaced by the one of the LoginInfo, hashed
+    /// with the given algorithm and a random salt. Everything else, such as the second factor,
+    /// is kept.
+    pub fn with_password(self, login_info: &LoginInfo, algorithm: &HashAlgorithm) -> Self {
+        Credentials {
+            password_hash: algorithm.hash(&login_info.password),
+            salt: None,
+            ..self
+        }
+    }
 
-        hex::encode(hashed_password)
+    fn create_legacy_hash(password: &str, salt: &str) -> [u8; 32] {
+        pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), salt.as_bytes(), 4096)
     }
 
     /// Returns the username of the Credentials
@@ -108,20 +642,47 @@ impl Credentials {
         &self.username
     }
 
+    /// Returns the second factor of the Credentials, whether it is enabled or still pending
+    pub fn second_factor(&self) -> Option<&SecondFactor> {
+        self.second_factor.as_ref()
+    }
+
+    /// Returns the password hash of the Credentials
+    pub fn password_hash(&self) -> &String {
+        &self.password_hash
+    }
+
+    /// Returns true if logging in requires a second factor
+    pub fn requires_second_factor(&self) -> bool {
+        self.second_factor
+            .as_ref()
+            .is_some_and(SecondFactor::is_enabled)
+    }
+
     /// Returns true if the given password matches the password of the Credentials
+    ///
+    /// The comparison is done in constant time for both current and legacy hashes.
     pub fn matches(&self, login_info: &LoginInfo) -> bool {
-        let hashed_password = Credentials::create_hash(&login_info.password, &self.salt);
+        match &self.salt {
+            Some(salt) => {
+                // Legacy hashes were hex encoded twice, decoding once leaves the hex encoded hash
+                let Ok(expected) = hex::decode(&self.password_hash) else {
+                    return false;
+                };
+                let hashed_password = Credentials::create_legacy_hash(&login_info.password, salt);
 
-        self.password_hash == hex::encode(hashed_password)
+                hex::encode(hashed_password)
+                    .as_bytes()
+                    .ct_eq(expected.as_slice())
+                    .into()
+            }
+            None => HashAlgorithm::verify(&self.password_hash, &login_info.password),
+        }
     }
-}
-
-pub fn extract_bearer_token(req: &HttpRequest) -> Result<String, ServiceError> {
-    let parsed_auth = Authorization::<Bearer>::parse(req);
 
-    match parsed_auth {
-        Ok(auth) => Ok(auth.into_scheme().token().to_string()),
-        Err(ParseError::Header) => Err(ServiceError::AuthorizationHeaderError),
-        Err(_) => Err(ServiceError::AuthenticationError),
+    /// Returns true if the password hash was not produced by the given algorithm and parameters
+    /// (including legacy PBKDF2 hashes) and should be replaced once the plain text password is known
+    pub fn needs_rehash(&self, algorithm: &HashAlgorithm) -> bool {
+        self.salt.is_some() || algorithm.needs_rehash(&self.password_hash)
     }
 }
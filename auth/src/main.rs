@@ -1,34 +1,128 @@
-use std::env;
-
-use actix_cors::Cors;
-use actix_web::{
-    get,
-    http::{self},
-    middleware::Logger,
-    post, put, web, App, HttpRequest, HttpResponse, HttpServer,
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{delete, get, post, put, web, App, HttpRequest, HttpResponse, HttpServer};
+use auth::{
+    db, extract_bearer_token,
+    hashing::HashAlgorithm,
+    metrics::{AuthMetrics, SESSIONS_COUNT_INTERVAL},
+    notifier::{FileNotifier, LogNotifier, NoNotifier, Notifier},
+    policy::PasswordPolicy,
+    store::{AuthStore, MemoryStore, MongoStore, SESSION_MAX_LIFETIME},
+    throttle::LoginThrottle,
+    tokens::TokenSigner,
+    totp::{RecoveryCodes, TotpEnrollment},
+    ClientInfo, LoginInfo, LoginResponse, PasswordChange, PasswordReset, PasswordResetRequest,
+    SecondFactorCode, SecondFactorVerification, SessionInfo, SessionToken, UserExistsParams,
 };
-use auth::{db::Authenticator, extract_bearer_token, LoginInfo, SessionToken, UserExistsParams};
 
 use core_rs::{
+    client_ip::TrustedProxies,
+    config::{AuthStorage, Config, NotifierKind},
     create_json_cfg,
     error::{Response, ServiceError},
+    health::{
+        self, BackgroundSetup, HealthChecks, HttpCheck, MongoPing, DEFAULT_SETUP_RETRY_INTERVAL,
+        HEALTH_PATH, READY_PATH,
+    },
+    metrics::{self, Metrics, RecordMetrics},
+    rate_limit::{
+        MemoryRateLimitStore, MongoRateLimitStore, RateLimit, RateLimitKey, RateLimitStore,
+        RateLimiter,
+    },
+    request_id::{self, AssignRequestId},
+    telemetry::{Telemetry, TraceExporter, TraceRequests},
+    validation::ValidJson,
     Username,
 };
+use dirc_client::UsersApi;
+use futures::future;
+
+/// The authenticator used by the handlers, with the storage backend chosen at startup
+type Authenticator = db::Authenticator<Arc<dyn AuthStore>>;
 
 #[post("/login")]
 async fn login(
     authenticator: web::Data<Authenticator>,
-    info: web::Json<LoginInfo>,
-) -> Response<SessionToken> {
-    authenticator.login(info.into_inner()).await.into()
+    info: ValidJson<LoginInfo>,
+    req: HttpRequest,
+) -> Response<LoginResponse> {
+    authenticator
+        .login_with_client(info.into_inner(), ClientInfo::from_request(&req))
+        .await
+        .into()
 }
 
 #[put("/register")]
 async fn register(
     authenticator: web::Data<Authenticator>,
-    info: web::Json<LoginInfo>,
+    info: ValidJson<LoginInfo>,
+    req: HttpRequest,
 ) -> Response<SessionToken> {
-    authenticator.register(info.into_inner()).await.into()
+    authenticator
+        .register_with_client(info.into_inner(), ClientInfo::from_request(&req))
+        .await
+        .into()
+}
+
+#[post("/2fa/verify")]
+async fn verify_second_factor(
+    authenticator: web::Data<Authenticator>,
+    verification: ValidJson<SecondFactorVerification>,
+) -> Response<SessionToken> {
+    authenticator
+        .verify_second_factor(verification.into_inner())
+        .await
+        .into()
+}
+
+#[post("/2fa/enroll")]
+async fn enroll_second_factor(
+    authenticator: web::Data<Authenticator>,
+    req: HttpRequest,
+) -> Response<TotpEnrollment> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator
+        .enroll_second_factor(&bearer_auth)
+        .await
+        .into()
+}
+
+#[post("/2fa/confirm")]
+async fn confirm_second_factor(
+    authenticator: web::Data<Authenticator>,
+    code: ValidJson<SecondFactorCode>,
+    req: HttpRequest,
+) -> Response<RecoveryCodes> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator
+        .confirm_second_factor(&bearer_auth, code.into_inner())
+        .await
+        .into()
+}
+
+#[post("/2fa/disable")]
+async fn disable_second_factor(
+    authenticator: web::Data<Authenticator>,
+    code: ValidJson<SecondFactorCode>,
+    req: HttpRequest,
+) -> Response<()> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator
+        .disable_second_factor(&bearer_auth, code.into_inner())
+        .await
+        .into()
 }
 
 #[get("/authenticate")]
@@ -77,6 +171,106 @@ async fn logout(authenticator: web::Data<Authenticator>, req: HttpRequest) -> Re
     authenticator.logout(&bearer_auth).await.into()
 }
 
+#[get("/logout_all")]
+async fn logout_all(
+    authenticator: web::Data<Authenticator>,
+    req: HttpRequest,
+) -> Response<Username> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator.logout_all(&bearer_auth).await.into()
+}
+
+#[get("/sessions")]
+async fn list_sessions(
+    authenticator: web::Data<Authenticator>,
+    req: HttpRequest,
+) -> Response<Vec<SessionInfo>> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator.list_sessions(&bearer_auth).await.into()
+}
+
+#[delete("/sessions/{id}")]
+async fn revoke_session(
+    authenticator: web::Data<Authenticator>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Response<()> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator
+        .revoke_session(&bearer_auth, &path.into_inner())
+        .await
+        .into()
+}
+
+#[post("/token/refresh")]
+async fn refresh(
+    authenticator: web::Data<Authenticator>,
+    req: HttpRequest,
+) -> Response<SessionToken> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator.refresh(&bearer_auth).await.into()
+}
+
+#[get("/.well-known/jwks.json")]
+async fn jwks(authenticator: web::Data<Authenticator>) -> HttpResponse {
+    HttpResponse::Ok().json(authenticator.jwks())
+}
+
+#[post("/password")]
+async fn change_password(
+    authenticator: web::Data<Authenticator>,
+    change: ValidJson<PasswordChange>,
+    req: HttpRequest,
+) -> Response<SessionToken> {
+    let bearer_auth = match extract_bearer_token(&req) {
+        Ok(bearer_auth) => bearer_auth,
+        Err(err) => return Response::Err(err),
+    };
+
+    authenticator
+        .change_password(&bearer_auth, change.into_inner())
+        .await
+        .into()
+}
+
+#[post("/password/reset/request")]
+async fn request_password_reset(
+    authenticator: web::Data<Authenticator>,
+    request: ValidJson<PasswordResetRequest>,
+) -> Response<()> {
+    authenticator
+        .request_password_reset(request.into_inner().username)
+        .await
+        .into()
+}
+
+#[post("/password/reset")]
+async fn reset_password(
+    authenticator: web::Data<Authenticator>,
+    reset: ValidJson<PasswordReset>,
+) -> Response<()> {
+    authenticator
+        .reset_password(reset.into_inner())
+        .await
+        .into()
+}
+
 #[get("/user_exists")]
 async fn user_exists(
     authenticator: web::Data<Authenticator>,
@@ -89,27 +283,195 @@ async fn user_exists(
 }
 
 /// Custom 404 handler to return JSON
-async fn not_found() -> HttpResponse {
-    HttpResponse::NotFound().json(ServiceError::NotFound)
+async fn not_found(req: HttpRequest) -> HttpResponse {
+    ServiceError::NotFound.response_for(&req)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Starting auth server...");
 
-    let mongodb_hostname = env::var("MONGODB_HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let config = Config::load().expect("Invalid configuration");
 
-    let mongodb_url = format!("mongodb://{}:27017", mongodb_hostname);
+    let trace_exporter = TraceExporter::from_config(&config.telemetry);
 
-    println!("MongoDB url: {}", mongodb_url);
+    println!("Trace exporter: {}", trace_exporter);
 
-    let authenticator = Authenticator::new(mongodb_url, "auth".to_string())
-        .await
-        .expect("Failed to connect to MongoDB");
+    let telemetry = Telemetry::init("auth", trace_exporter).expect("Failed to set up tracing");
+
+    for warning in &config.warnings {
+        tracing::warn!("{}", warning);
+    }
 
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let metrics = Metrics::new();
+    let auth_metrics = AuthMetrics::default();
 
-    HttpServer::new(move || {
+    auth_metrics
+        .register(&metrics)
+        .expect("Failed to register metrics");
+
+    let (store, rate_limit_store, health_checks): (
+        Arc<dyn AuthStore>,
+        Arc<dyn RateLimitStore>,
+        HealthChecks,
+    ) = match config.auth.storage {
+        AuthStorage::Memory => {
+            println!("Using in-memory storage, data will not be persisted");
+
+            (
+                Arc::new(MemoryStore::new()),
+                Arc::new(MemoryRateLimitStore::new()),
+                HealthChecks::new(),
+            )
+        }
+        AuthStorage::Mongodb => {
+            let database = config.mongodb.database_or("auth");
+
+            println!("MongoDB url: {}", config.mongodb.redacted_url());
+            println!("MongoDB database: {}", database);
+
+            let store = MongoStore::with_metrics(
+                config.mongodb.url.clone(),
+                database.to_string(),
+                &metrics,
+            )
+            .await
+            .expect("Invalid MongoDB configuration");
+
+            let rate_limit_store =
+                MongoRateLimitStore::with_database(&store.client().database(database));
+
+            // MongoDB may not be available yet, the service reports not ready until it is set up
+            let setup = {
+                let store = store.clone();
+                let rate_limit_store = rate_limit_store.clone();
+
+                BackgroundSetup::spawn("MongoDB", DEFAULT_SETUP_RETRY_INTERVAL, move || {
+                    let store = store.clone();
+                    let rate_limit_store = rate_limit_store.clone();
+
+                    async move {
+                        store.setup().await?;
+                        rate_limit_store.setup().await
+                    }
+                })
+            };
+
+            let health_checks = HealthChecks::new()
+                .with_check("mongodb", MongoPing::new(store.client()))
+                .with_check("mongodb_setup", setup);
+
+            (Arc::new(store), Arc::new(rate_limit_store), health_checks)
+        }
+    };
+
+    let trusted_proxies = TrustedProxies::new(config.server.trusted_proxies.clone());
+
+    // Endpoints that can be used to guess passwords or find out which users exist are limited
+    // more strictly than the rest
+    let rate_limiter = RateLimiter::new(rate_limit_store)
+        .trusted_proxies(trusted_proxies.clone())
+        .route("/login", RateLimit::per_minute(10))
+        .route("/register", RateLimit::per_minute(5))
+        .route("/2fa/verify", RateLimit::per_minute(10))
+        .route("/password/reset/request", RateLimit::per_minute(5))
+        .route("/user_exists", RateLimit::per_minute(30))
+        // Mostly called by other services on behalf of their users, so not keyed by IP
+        .route(
+            "/authenticate",
+            RateLimit::per_minute(300).keyed_by(RateLimitKey::BearerToken),
+        )
+        .route(
+            "/authorize",
+            RateLimit::per_minute(300).keyed_by(RateLimitKey::BearerToken),
+        )
+        .exempt(HEALTH_PATH)
+        .exempt(READY_PATH)
+        .default_limit(RateLimit::per_minute(300))
+        .with_config(&config.rate_limits);
+
+    let hash_algorithm = HashAlgorithm::from_config(&config.auth.hashing)
+        .expect("Invalid password hashing configuration");
+
+    println!("Password hashing: {:?}", hash_algorithm);
+
+    let password_policy = PasswordPolicy::from_config(&config.auth.password_policy)
+        .expect("Invalid password policy configuration");
+
+    let notifier: Arc<dyn Notifier> = match config.auth.notifier {
+        NotifierKind::File => {
+            let path = config.auth.notifier_file.clone();
+
+            println!("Writing password reset tokens to {}", path);
+
+            Arc::new(FileNotifier::new(path))
+        }
+        NotifierKind::Log => {
+            println!("Writing password reset tokens to the log, only use this for development");
+
+            Arc::new(LogNotifier)
+        }
+        NotifierKind::None => {
+            println!("No notifier configured, password reset tokens can't be delivered");
+
+            Arc::new(NoNotifier)
+        }
+    };
+
+    let token_signer =
+        TokenSigner::from_config(&config.auth).expect("Invalid access token configuration");
+
+    println!("Access token signing key: {}", token_signer.key_id());
+
+    let session_max_lifetime = config
+        .auth
+        .session_max_lifetime
+        .map_or(SESSION_MAX_LIFETIME, Duration::from_secs);
+
+    let login_throttle = LoginThrottle::from_config(&config.auth.throttle)
+        .expect("Invalid login throttle configuration");
+
+    let users_api = UsersApi::from_config(&config);
+
+    println!("Users service url: {}", users_api.base_url());
+
+    let health_checks = health_checks.with_check("users", HttpCheck::service(users_api.base_url()));
+
+    println!("Readiness checks: {}", health_checks.names().join(", "));
+
+    let authenticator = Authenticator::with_store(store)
+        .with_hash_algorithm(hash_algorithm)
+        .with_password_policy(password_policy)
+        .with_notifier(notifier)
+        .with_token_signer(token_signer)
+        .with_session_max_lifetime(session_max_lifetime)
+        .with_login_throttle(login_throttle)
+        .with_generic_login_errors(config.auth.generic_login_errors)
+        .with_users_api(users_api)
+        .with_metrics(auth_metrics);
+
+    let sessions_counter = authenticator.clone();
+
+    actix_web::rt::spawn(async move {
+        loop {
+            if let Err(err) = sessions_counter.update_sessions_active().await {
+                tracing::error!("Failed to count active sessions: {}", err);
+            }
+
+            actix_web::rt::time::sleep(SESSIONS_COUNT_INTERVAL).await;
+        }
+    });
+
+    let json_limit = config.server.json_limit;
+    let bind_address = config.server.bind_address;
+    let metrics_address = config.server.metrics_address;
+
+    println!("Listening on {}", bind_address);
+    println!("Serving metrics on {}", metrics_address);
+
+    let metrics_server = metrics::serve(metrics.clone(), metrics_address)?;
+
+    let server = HttpServer::new(move || {
         // let cors = Cors::permissive()
         // .allowed_origin("http://localhost:3000")
         // .allowed_origin("http://live-chat:8080")
@@ -121,18 +483,43 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             // .wrap(cors)
-            .wrap(Logger::default())
-            .app_data(create_json_cfg())
+            .wrap(rate_limiter.clone())
+            .wrap(RecordMetrics::new(metrics.clone()))
+            .wrap(request_id::access_logger())
+            .wrap(TraceRequests)
+            .wrap(AssignRequestId)
+            .app_data(create_json_cfg(json_limit))
+            .app_data(web::Data::new(trusted_proxies.clone()))
             .app_data(web::Data::new(authenticator.clone()))
+            .app_data(web::Data::new(health_checks.clone()))
             .service(login)
             .service(register)
+            .service(verify_second_factor)
+            .service(enroll_second_factor)
+            .service(confirm_second_factor)
+            .service(disable_second_factor)
             .service(authenticate)
             .service(authorize)
             .service(logout)
+            .service(logout_all)
+            .service(list_sessions)
+            .service(revoke_session)
+            .service(refresh)
+            .service(jwks)
+            .service(change_password)
+            .service(request_password_reset)
+            .service(reset_password)
             .service(user_exists)
+            .route(HEALTH_PATH, web::get().to(health::healthz))
+            .route(READY_PATH, web::get().to(health::readyz))
             .default_service(web::route().to(not_found))
     })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .bind(bind_address)?
+    .run();
+
+    future::try_join(server, metrics_server).await?;
+
+    telemetry.shutdown();
+
+    Ok(())
 }
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use core_rs::{error::ServiceError, metrics::Metrics};
+use prometheus::{IntCounter, IntCounterVec, IntGauge, Opts};
+
+use crate::LoginResponse;
+
+/// How often the active sessions are counted, so scraping the metrics doesn't query the database
+pub const SESSIONS_COUNT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Metrics of the authentication service on top of the HTTP and MongoDB metrics every service
+/// records.
+///
+/// Unregistered until [`AuthMetrics::register`] is called, so authenticators created in tests
+/// don't need a registry. Clones share the same metrics.
+#[derive(Clone, Debug)]
+pub struct AuthMetrics {
+    logins: IntCounterVec,
+    registrations: IntCounter,
+    sessions_active: IntGauge,
+}
+
+impl AuthMetrics {
+    /// Adds the metrics to the registry of the service
+    ///
+    /// # Errors
+    /// Fails if metrics with the same names are already registered
+    pub fn register(&self, metrics: &Metrics) -> prometheus::Result<()> {
+        metrics.register(self.logins.clone())?;
+        metrics.register(self.registrations.clone())?;
+        metrics.register(self.sessions_active.clone())
+    }
+
+    /// Counts a login attempt by its outcome, see [`login_result`]
+    pub fn record_login(&self, result: &Result<LoginResponse, ServiceError>) {
+        self.logins.with_label_values(&[login_result(result)]).inc();
+    }
+
+    /// Counts a successful registration
+    pub fn record_registration(&self) {
+        self.registrations.inc();
+    }
+
+    /// Sets the number of sessions that have not expired
+    pub fn set_sessions_active(&self, count: u64) {
+        self.sessions_active.set(count as i64);
+    }
+
+    /// Returns how many logins had the given result
+    pub fn logins(&self, result: &str) -> u64 {
+        self.logins.with_label_values(&[result]).get()
+    }
+
+    /// Returns how many users registered
+    pub fn registrations(&self) -> u64 {
+        self.registrations.get()
+    }
+
+    /// Returns the number of sessions that had not expired when it was last set
+    pub fn sessions_active(&self) -> i64 {
+        self.sessions_active.get()
+    }
+}
+
+impl Default for AuthMetrics {
+    fn default() -> Self {
+        // The options are fixed and valid, so creating the metrics can't fail
+        AuthMetrics {
+            logins: IntCounterVec::new(
+                Opts::new("auth_login_total", "Number of login attempts by result"),
+                &["result"],
+            )
+            .unwrap(),
+            registrations: IntCounter::new("auth_register_total", "Number of registered users")
+                .unwrap(),
+            sessions_active: IntGauge::new(
+                "auth_sessions_active",
+                "Number of sessions that have not expired",
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Returns the `result` label of a login attempt: `success`, `second_factor_required`,
+/// `invalid_credentials`, `throttled` or `error`
+pub fn login_result(result: &Result<LoginResponse, ServiceError>) -> &'static str {
+    match result {
+        Ok(LoginResponse::Session(_)) => "success",
+        Ok(LoginResponse::SecondFactorRequired(_)) => "second_factor_required",
+        Err(
+            ServiceError::InvalidPassword
+            | ServiceError::InvalidCredentials
+            | ServiceError::UserNotFound(_)
+            | ServiceError::InvalidUsername(_),
+        ) => "invalid_credentials",
+        Err(ServiceError::TooManyAttempts(_)) => "throttled",
+        Err(_) => "error",
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fmt::Debug, path::PathBuf};
+
+use async_trait::async_trait;
+use mongodb::bson::DateTime;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use core_rs::error::ServiceError;
+
+/// Delivers password reset tokens to users
+#[async_trait]
+pub trait Notifier: Debug + Send + Sync {
+    /// Sends a password reset token to the given user
+    ///
+    /// # Errors
+    /// `ServiceError::NotificationError` if the token could not be delivered
+    async fn send_password_reset(
+        &self,
+        username: &str,
+        token: &str,
+        expires_at: DateTime,
+    ) -> Result<(), ServiceError>;
+}
+
+/// Delivers nothing, so password resets can't be completed. Used until a notifier is
+/// configured, so reset tokens never end up anywhere by accident.
+#[derive(Clone, Debug, Default)]
+pub struct NoNotifier;
+
+#[async_trait]
+impl Notifier for NoNotifier {
+    async fn send_password_reset(
+        &self,
+        _username: &str,
+        _token: &str,
+        _expires_at: DateTime,
+    ) -> Result<(), ServiceError> {
+        Err(ServiceError::NotificationError(
+            "No notifier is configured".to_string(),
+        ))
+    }
+}
+
+/// Writes reset tokens to the service log. Only meant for local development, as anyone who can
+/// read the log can reset any password.
+#[derive(Clone, Debug, Default)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send_password_reset(
+        &self,
+        username: &str,
+        token: &str,
+        expires_at: DateTime,
+    ) -> Result<(), ServiceError> {
+        tracing::info!(
+            "Password reset token for '{}': {} (expires at {})",
+            username,
+            token,
+            expires_at
+        );
+
+        Ok(())
+    }
+}
+
+/// Appends reset tokens to a file, one line per token. Only meant for local development.
+#[derive(Clone, Debug)]
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    /// Creates a new FileNotifier writing to the given path
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileNotifier { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send_password_reset(
+        &self,
+        username: &str,
+        token: &str,
+        expires_at: DateTime,
+    ) -> Result<(), ServiceError> {
+        let line = format!("{}\t{}\t{}\n", username, token, expires_at);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|err| ServiceError::NotificationError(err.to_string()))?;
+
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|err| ServiceError::NotificationError(err.to_string()))
+    }
+}
@@ -0,0 +1,187 @@
+use core_rs::{
+    config::PasswordPolicyConfig,
+    error::{PolicyViolation, ServiceError},
+};
+
+use crate::LoginInfo;
+
+/// Common passwords bundled with the service, one per line
+const COMMON_PASSWORDS: &str = include_str!("../data/common-passwords.txt");
+
+/// Usernames shorter than this are not checked for being contained in the password
+const MIN_SIMILAR_USERNAME_LENGTH: usize = 3;
+
+/// Rules a password has to satisfy on registration and password change
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters
+    pub min_length: usize,
+    /// Maximum number of characters
+    pub max_length: usize,
+    /// Require at least one lowercase letter
+    pub require_lowercase: bool,
+    /// Require at least one uppercase letter
+    pub require_uppercase: bool,
+    /// Require at least one digit
+    pub require_digit: bool,
+    /// Require at least one character that is not a letter or digit
+    pub require_symbol: bool,
+    /// Reject passwords on the bundled list of common passwords
+    pub deny_common: bool,
+    /// Reject passwords that are, contain or reverse the username
+    pub check_username: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            deny_common: true,
+            check_username: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Creates the policy from the `auth.password_policy` section of the configuration, falling
+    /// back to the defaults for rules that are not set.
+    ///
+    /// # Errors
+    /// Fails if the lengths are inconsistent
+    pub fn from_config(config: &PasswordPolicyConfig) -> anyhow::Result<Self> {
+        let default = PasswordPolicy::default();
+
+        let policy = PasswordPolicy {
+            min_length: config.min_length.unwrap_or(default.min_length),
+            max_length: config.max_length.unwrap_or(default.max_length),
+            require_lowercase: config
+                .require_lowercase
+                .unwrap_or(default.require_lowercase),
+            require_uppercase: config
+                .require_uppercase
+                .unwrap_or(default.require_uppercase),
+            require_digit: config.require_digit.unwrap_or(default.require_digit),
+            require_symbol: config.require_symbol.unwrap_or(default.require_symbol),
+            deny_common: config.deny_common.unwrap_or(default.deny_common),
+            check_username: config.check_username.unwrap_or(default.check_username),
+        };
+
+        if policy.min_length > policy.max_length {
+            anyhow::bail!("Minimum password length is larger than the maximum length");
+        }
+
+        Ok(policy)
+    }
+
+    /// Returns every rule the password in the given login info violates
+    pub fn violations(&self, info: &LoginInfo) -> Vec<PolicyViolation> {
+        let password = info.password.as_str();
+        let length = password.chars().count();
+        let mut violations = Vec::new();
+
+        if length < self.min_length {
+            violations.push(PolicyViolation::new(
+                "min_length",
+                format!("Must be at least {} characters long", self.min_length),
+            ));
+        }
+
+        if length > self.max_length {
+            violations.push(PolicyViolation::new(
+                "max_length",
+                format!("Must be at most {} characters long", self.max_length),
+            ));
+        }
+
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violations.push(PolicyViolation::new(
+                "lowercase",
+                "Must contain a lowercase letter".to_string(),
+            ));
+        }
+
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push(PolicyViolation::new(
+                "uppercase",
+                "Must contain an uppercase letter".to_string(),
+            ));
+        }
+
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(PolicyViolation::new(
+                "digit",
+                "Must contain a digit".to_string(),
+            ));
+        }
+
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            violations.push(PolicyViolation::new(
+                "symbol",
+                "Must contain a symbol".to_string(),
+            ));
+        }
+
+        if self.deny_common && is_common_password(password) {
+            violations.push(PolicyViolation::new(
+                "common",
+                "Must not be a commonly used password".to_string(),
+            ));
+        }
+
+        if self.check_username && is_similar_to_username(password, &info.username) {
+            violations.push(PolicyViolation::new(
+                "username",
+                "Must not be similar to the username".to_string(),
+            ));
+        }
+
+        violations
+    }
+
+    /// Checks the password in the given login info against the policy
+    ///
+    /// # Errors
+    /// `ServiceError::PasswordPolicyViolation` listing every violated rule
+    pub fn check(&self, info: &LoginInfo) -> Result<(), ServiceError> {
+        let violations = self.violations(info);
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ServiceError::PasswordPolicyViolation(violations))
+        }
+    }
+}
+
+/// Returns true if the password is on the bundled list of common passwords, ignoring case
+fn is_common_password(password: &str) -> bool {
+    let password = password.to_lowercase();
+
+    COMMON_PASSWORDS
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .any(|line| line == password)
+}
+
+/// Returns true if the password equals, contains or reverses the username, ignoring case
+fn is_similar_to_username(password: &str, username: &str) -> bool {
+    let password = password.to_lowercase();
+    let username = username.trim().to_lowercase();
+
+    if username.is_empty() {
+        return false;
+    }
+
+    let reversed = username.chars().rev().collect::<String>();
+
+    password == username
+        || password == reversed
+        || (username.chars().count() >= MIN_SIMILAR_USERNAME_LENGTH
+            && (password.contains(&username) || password.contains(&reversed)))
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+
+use core_rs::error::ServiceError;
+
+use crate::{
+    throttle::FailedAttempts, totp::SecondFactor, Credentials, LoginChallenge, ResetToken, Session,
+};
+
+use super::{
+    AttemptStore, ChallengeStore, CredentialStore, ResetTokenStore, SessionStore,
+    FAILED_ATTEMPT_WINDOW, SESSION_TTL,
+};
+
+/// Keeps credentials and sessions in process memory.
+///
+/// Nothing is persisted, so this is only meant for tests and local development.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    credentials: Arc<Mutex<HashMap<String, Credentials>>>,
+    sessions: Arc<Mutex<HashMap<String, StoredSession>>>,
+    reset_tokens: Arc<Mutex<HashMap<String, ResetToken>>>,
+    challenges: Arc<Mutex<HashMap<String, LoginChallenge>>>,
+    failed_attempts: Arc<Mutex<HashMap<String, FailedAttempts>>>,
+}
+
+#[derive(Debug)]
+struct StoredSession {
+    session: Session,
+    last_used: Instant,
+}
+
+impl StoredSession {
+    fn is_expired(&self) -> bool {
+        self.last_used.elapsed() >= SESSION_TTL
+    }
+}
+
+impl MemoryStore {
+    /// Creates a new empty MemoryStore
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CredentialStore for MemoryStore {
+    async fn find_credentials(&self, username: &str) -> Result<Option<Credentials>, ServiceError> {
+        let credentials = self.credentials.lock().unwrap();
+
+        Ok(credentials.get(username).cloned())
+    }
+
+    async fn insert_credentials(&self, credentials: Credentials) -> Result<(), ServiceError> {
+        let mut stored = self.credentials.lock().unwrap();
+
+        if stored.contains_key(credentials.username()) {
+            return Err(ServiceError::UsernameTaken(credentials.username().clone()));
+        }
+
+        stored.insert(credentials.username().clone(), credentials);
+
+        Ok(())
+    }
+
+    async fn update_password(&self, credentials: &Credentials) -> Result<(), ServiceError> {
+        let mut stored = self.credentials.lock().unwrap();
+
+        if let Some(existing) = stored.get_mut(credentials.username()) {
+            existing.password_hash = credentials.password_hash.clone();
+            existing.salt = None;
+        }
+
+        Ok(())
+    }
+
+    async fn update_second_factor(
+        &self,
+        username: &str,
+        current: Option<&SecondFactor>,
+        second_factor: Option<&SecondFactor>,
+    ) -> Result<bool, ServiceError> {
+        let mut stored = self.credentials.lock().unwrap();
+
+        match stored.get_mut(username) {
+            Some(existing) if existing.second_factor.as_ref() == current => {
+                existing.second_factor = second_factor.cloned();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_credentials(&self, username: &str) -> Result<(), ServiceError> {
+        self.credentials.lock().unwrap().remove(username);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn insert_session(&self, session: Session) -> Result<(), ServiceError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        sessions.insert(
+            session.token_hash().clone(),
+            StoredSession {
+                session,
+                last_used: Instant::now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn touch_session(&self, token_hash: &str) -> Result<Option<Session>, ServiceError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let expired = match sessions.get_mut(token_hash) {
+            Some(stored) if !stored.is_expired() => {
+                stored.last_used = Instant::now();
+                stored.session.touch();
+                return Ok(Some(stored.session.clone()));
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            sessions.remove(token_hash);
+        }
+
+        Ok(None)
+    }
+
+    async fn rotate_session(
+        &self,
+        token_hash: &str,
+        new_token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let Some(mut stored) = sessions.remove(token_hash) else {
+            return Ok(None);
+        };
+
+        if stored.is_expired() {
+            return Ok(None);
+        }
+
+        stored.session.rotate(new_token_hash.to_string());
+        stored.last_used = Instant::now();
+
+        let session = stored.session.clone();
+        sessions.insert(new_token_hash.to_string(), stored);
+
+        Ok(Some(session))
+    }
+
+    async fn delete_reused_session(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let current_hash = sessions.iter().find_map(|(current_hash, stored)| {
+            stored
+                .session
+                .used_token_hashes()
+                .iter()
+                .any(|used| used == token_hash)
+                .then(|| current_hash.clone())
+        });
+
+        Ok(current_hash
+            .and_then(|current_hash| sessions.remove(&current_hash))
+            .map(|stored| stored.session))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServiceError> {
+        self.sessions.lock().unwrap().remove(token_hash);
+
+        Ok(())
+    }
+
+    async fn find_user_sessions(&self, username: &str) -> Result<Vec<Session>, ServiceError> {
+        let sessions = self.sessions.lock().unwrap();
+
+        Ok(sessions
+            .values()
+            .filter(|stored| stored.session.username() == username && !stored.is_expired())
+            .map(|stored| stored.session.clone())
+            .collect())
+    }
+
+    async fn delete_user_session(&self, username: &str, id: &str) -> Result<bool, ServiceError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let count = sessions.len();
+
+        sessions
+            .retain(|_, stored| stored.session.username() != username || stored.session.id() != id);
+
+        Ok(sessions.len() != count)
+    }
+
+    async fn delete_user_sessions(&self, username: &str) -> Result<(), ServiceError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, stored| stored.session.username() != username);
+
+        Ok(())
+    }
+
+    async fn count_sessions(&self) -> Result<u64, ServiceError> {
+        let sessions = self.sessions.lock().unwrap();
+
+        Ok(sessions
+            .values()
+            .filter(|stored| !stored.is_expired())
+            .count() as u64)
+    }
+}
+
+#[async_trait]
+impl ResetTokenStore for MemoryStore {
+    async fn insert_reset_token(&self, reset_token: ResetToken) -> Result<(), ServiceError> {
+        self.reset_tokens
+            .lock()
+            .unwrap()
+            .insert(reset_token.token_hash().clone(), reset_token);
+
+        Ok(())
+    }
+
+    async fn take_reset_token(&self, token_hash: &str) -> Result<Option<ResetToken>, ServiceError> {
+        Ok(self.reset_tokens.lock().unwrap().remove(token_hash))
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for MemoryStore {
+    async fn insert_challenge(&self, challenge: LoginChallenge) -> Result<(), ServiceError> {
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(challenge.token_hash().clone(), challenge);
+
+        Ok(())
+    }
+
+    async fn take_challenge(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<LoginChallenge>, ServiceError> {
+        Ok(self.challenges.lock().unwrap().remove(token_hash))
+    }
+}
+
+#[async_trait]
+impl AttemptStore for MemoryStore {
+    async fn find_failed_attempts(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError> {
+        let failed_attempts = self.failed_attempts.lock().unwrap();
+
+        Ok(failed_attempts
+            .get(key)
+            .filter(|attempts| !attempts.is_older_than(FAILED_ATTEMPT_WINDOW))
+            .cloned())
+    }
+
+    async fn record_failed_attempt(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError> {
+        let mut failed_attempts = self.failed_attempts.lock().unwrap();
+
+        match failed_attempts.get_mut(key) {
+            Some(attempts) => {
+                let previous = attempts.clone();
+                attempts.record_failure(FAILED_ATTEMPT_WINDOW);
+
+                Ok(
+                    Some(previous)
+                        .filter(|previous| !previous.is_older_than(FAILED_ATTEMPT_WINDOW)),
+                )
+            }
+            None => {
+                failed_attempts.insert(key.to_string(), FailedAttempts::new(key.to_string()));
+
+                Ok(None)
+            }
+        }
+    }
+
+    async fn forgive_failed_attempt(
+        &self,
+        key: &str,
+        previous: Option<&FailedAttempts>,
+    ) -> Result<(), ServiceError> {
+        if let Some(attempts) = self.failed_attempts.lock().unwrap().get_mut(key) {
+            attempts.forgive_failure(previous.map(FailedAttempts::last_failed_at));
+        }
+
+        Ok(())
+    }
+
+    async fn clear_failed_attempts(&self, key: &str) -> Result<(), ServiceError> {
+        self.failed_attempts.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+}
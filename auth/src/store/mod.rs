@@ -0,0 +1,360 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use core_rs::error::ServiceError;
+
+use crate::{
+    throttle::FailedAttempts, totp::SecondFactor, Credentials, LoginChallenge, ResetToken, Session,
+};
+
+pub mod memory;
+pub mod mongo;
+
+pub use memory::MemoryStore;
+pub use mongo::MongoStore;
+
+/// How long a session token stays valid after it was last used
+pub const SESSION_TTL: Duration = Duration::from_secs(2592000);
+
+/// How long a session stays valid after it was created by default, however often it is used
+pub const SESSION_MAX_LIFETIME: Duration = Duration::from_secs(7776000);
+
+/// How long failed login attempts are remembered after the most recent one
+pub const FAILED_ATTEMPT_WINDOW: Duration = Duration::from_secs(86400);
+
+/// Storage backend for user credentials
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    /// Returns the credentials stored for the given username, if any
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn find_credentials(&self, username: &str) -> Result<Option<Credentials>, ServiceError>;
+
+    /// Stores a new set of credentials
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    /// `ServiceError::UsernameTaken` if credentials for the username already exist
+    async fn insert_credentials(&self, credentials: Credentials) -> Result<(), ServiceError>;
+
+    /// Replaces the stored password hash of a user with the one of the credentials, dropping the
+    /// salt of a legacy hash. Nothing else, such as the second factor, is touched, so this can't
+    /// undo a concurrent change to it.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn update_password(&self, credentials: &Credentials) -> Result<(), ServiceError>;
+
+    /// Replaces the second factor of a user with `second_factor`, or removes it if `None`, but
+    /// only if the stored one still equals `current`. Returns whether it was replaced.
+    ///
+    /// The comparison and replacement are atomic, so of concurrent updates starting from the
+    /// same second factor only one succeeds. This is what makes TOTP and recovery codes single
+    /// use.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn update_second_factor(
+        &self,
+        username: &str,
+        current: Option<&SecondFactor>,
+        second_factor: Option<&SecondFactor>,
+    ) -> Result<bool, ServiceError>;
+
+    /// Removes the credentials of a user, if any
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn delete_credentials(&self, username: &str) -> Result<(), ServiceError>;
+}
+
+/// Storage backend for sessions
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Stores a new session
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn insert_session(&self, session: Session) -> Result<(), ServiceError>;
+
+    /// Looks up a session by the digest of its token and marks it as used, extending its lifetime
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn touch_session(&self, token_hash: &str) -> Result<Option<Session>, ServiceError>;
+
+    /// Replaces the token of a session looked up by the digest of its current token, see
+    /// [`Session::rotate`]. Returns the updated session, or `None` if no session currently has
+    /// the token.
+    ///
+    /// The lookup and replacement are atomic, so a token can only be rotated once.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn rotate_session(
+        &self,
+        token_hash: &str,
+        new_token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError>;
+
+    /// Removes and returns the session that previously had the token with the given digest,
+    /// if any
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn delete_reused_session(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError>;
+
+    /// Removes a session by the digest of its token, doing nothing if it does not exist
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServiceError>;
+
+    /// Returns every active session belonging to a user
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn find_user_sessions(&self, username: &str) -> Result<Vec<Session>, ServiceError>;
+
+    /// Removes a session of a user by its id, returning whether it existed
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn delete_user_session(&self, username: &str, id: &str) -> Result<bool, ServiceError>;
+
+    /// Removes every session belonging to a user
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn delete_user_sessions(&self, username: &str) -> Result<(), ServiceError>;
+
+    /// Returns the number of sessions that have not expired, of every user
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn count_sessions(&self) -> Result<u64, ServiceError>;
+}
+
+/// Storage backend for password reset tokens
+#[async_trait]
+pub trait ResetTokenStore: Send + Sync {
+    /// Stores a new reset token
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn insert_reset_token(&self, reset_token: ResetToken) -> Result<(), ServiceError>;
+
+    /// Removes and returns the reset token with the given digest, so it can only be used once
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn take_reset_token(&self, token_hash: &str) -> Result<Option<ResetToken>, ServiceError>;
+}
+
+/// Storage backend for logins waiting for their second factor
+#[async_trait]
+pub trait ChallengeStore: Send + Sync {
+    /// Stores a login challenge
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn insert_challenge(&self, challenge: LoginChallenge) -> Result<(), ServiceError>;
+
+    /// Removes and returns the login challenge with the given digest, so it can only be
+    /// answered once
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn take_challenge(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<LoginChallenge>, ServiceError>;
+}
+
+/// Storage backend for failed login attempts, counted per key such as a username or client IP
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    /// Returns the failed attempts recorded for a key, if any
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn find_failed_attempts(&self, key: &str)
+        -> Result<Option<FailedAttempts>, ServiceError>;
+
+    /// Records a failed attempt for a key, starting over if the previous failure is older than
+    /// [`FAILED_ATTEMPT_WINDOW`]. Returns the record as it was before, if any.
+    ///
+    /// Recording and reading the previous record are atomic, so of concurrent attempts every one
+    /// sees the ones recorded before it.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn record_failed_attempt(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError>;
+
+    /// Takes back one failed attempt recorded for a key, for an attempt that was recorded before
+    /// it turned out to succeed or was rejected. Given the record as it was before, as returned
+    /// by [`AttemptStore::record_failed_attempt`], the time of the previous failure is put back.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn forgive_failed_attempt(
+        &self,
+        key: &str,
+        previous: Option<&FailedAttempts>,
+    ) -> Result<(), ServiceError>;
+
+    /// Forgets the failed attempts recorded for a key, doing nothing if there are none
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn clear_failed_attempts(&self, key: &str) -> Result<(), ServiceError>;
+}
+
+/// A backend providing every kind of storage the authenticator needs
+pub trait AuthStore:
+    CredentialStore + SessionStore + ResetTokenStore + ChallengeStore + AttemptStore
+{
+}
+
+impl<
+        T: CredentialStore + SessionStore + ResetTokenStore + ChallengeStore + AttemptStore + ?Sized,
+    > AuthStore for T
+{
+}
+
+#[async_trait]
+impl<T: CredentialStore + ?Sized> CredentialStore for Arc<T> {
+    async fn find_credentials(&self, username: &str) -> Result<Option<Credentials>, ServiceError> {
+        (**self).find_credentials(username).await
+    }
+
+    async fn insert_credentials(&self, credentials: Credentials) -> Result<(), ServiceError> {
+        (**self).insert_credentials(credentials).await
+    }
+
+    async fn update_password(&self, credentials: &Credentials) -> Result<(), ServiceError> {
+        (**self).update_password(credentials).await
+    }
+
+    async fn update_second_factor(
+        &self,
+        username: &str,
+        current: Option<&SecondFactor>,
+        second_factor: Option<&SecondFactor>,
+    ) -> Result<bool, ServiceError> {
+        (**self)
+            .update_second_factor(username, current, second_factor)
+            .await
+    }
+
+    async fn delete_credentials(&self, username: &str) -> Result<(), ServiceError> {
+        (**self).delete_credentials(username).await
+    }
+}
+
+#[async_trait]
+impl<T: SessionStore + ?Sized> SessionStore for Arc<T> {
+    async fn insert_session(&self, session: Session) -> Result<(), ServiceError> {
+        (**self).insert_session(session).await
+    }
+
+    async fn touch_session(&self, token_hash: &str) -> Result<Option<Session>, ServiceError> {
+        (**self).touch_session(token_hash).await
+    }
+
+    async fn rotate_session(
+        &self,
+        token_hash: &str,
+        new_token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError> {
+        (**self).rotate_session(token_hash, new_token_hash).await
+    }
+
+    async fn delete_reused_session(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError> {
+        (**self).delete_reused_session(token_hash).await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServiceError> {
+        (**self).delete_session(token_hash).await
+    }
+
+    async fn find_user_sessions(&self, username: &str) -> Result<Vec<Session>, ServiceError> {
+        (**self).find_user_sessions(username).await
+    }
+
+    async fn delete_user_session(&self, username: &str, id: &str) -> Result<bool, ServiceError> {
+        (**self).delete_user_session(username, id).await
+    }
+
+    async fn delete_user_sessions(&self, username: &str) -> Result<(), ServiceError> {
+        (**self).delete_user_sessions(username).await
+    }
+
+    async fn count_sessions(&self) -> Result<u64, ServiceError> {
+        (**self).count_sessions().await
+    }
+}
+
+#[async_trait]
+impl<T: ResetTokenStore + ?Sized> ResetTokenStore for Arc<T> {
+    async fn insert_reset_token(&self, reset_token: ResetToken) -> Result<(), ServiceError> {
+        (**self).insert_reset_token(reset_token).await
+    }
+
+    async fn take_reset_token(&self, token_hash: &str) -> Result<Option<ResetToken>, ServiceError> {
+        (**self).take_reset_token(token_hash).await
+    }
+}
+
+#[async_trait]
+impl<T: ChallengeStore + ?Sized> ChallengeStore for Arc<T> {
+    async fn insert_challenge(&self, challenge: LoginChallenge) -> Result<(), ServiceError> {
+        (**self).insert_challenge(challenge).await
+    }
+
+    async fn take_challenge(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<LoginChallenge>, ServiceError> {
+        (**self).take_challenge(token_hash).await
+    }
+}
+
+#[async_trait]
+impl<T: AttemptStore + ?Sized> AttemptStore for Arc<T> {
+    async fn find_failed_attempts(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError> {
+        (**self).find_failed_attempts(key).await
+    }
+
+    async fn record_failed_attempt(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError> {
+        (**self).record_failed_attempt(key).await
+    }
+
+    async fn forgive_failed_attempt(
+        &self,
+        key: &str,
+        previous: Option<&FailedAttempts>,
+    ) -> Result<(), ServiceError> {
+        (**self).forgive_failed_attempt(key, previous).await
+    }
+
+    async fn clear_failed_attempts(&self, key: &str) -> Result<(), ServiceError> {
+        (**self).clear_failed_attempts(key).await
+    }
+}
@@ -0,0 +1,866 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, to_bson, DateTime, Document, Regex},
+    error::{ErrorKind, WriteFailure},
+    options::{ClientOptions, FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    Client, Database, IndexModel,
+};
+
+use core_rs::{
+    error::ServiceError, metrics::Metrics, telemetry::mongodb_span, username::NormalizedUsername,
+};
+use tracing::Instrument;
+
+use crate::{
+    hash_token, throttle::FailedAttempts, totp::SecondFactor, Credentials, LoginChallenge,
+    ResetToken, Session, USED_TOKEN_HISTORY,
+};
+
+use super::{
+    AttemptStore, ChallengeStore, CredentialStore, ResetTokenStore, SessionStore,
+    FAILED_ATTEMPT_WINDOW, SESSION_TTL,
+};
+
+/// MongoDB error code for a unique index violation
+const DUPLICATE_KEY_ERROR: i32 = 11000;
+
+/// Stores credentials and sessions in a MongoDB database
+#[derive(Clone, Debug)]
+pub struct MongoStore {
+    client: Client,
+    database: Database,
+}
+
+impl MongoStore {
+    /// Creates a new MongoStore given a mongodb url and database name
+    ///
+    /// # Errors
+    /// Construction will fail if a database error occurs
+    pub async fn new(url: String, db_name: String) -> anyhow::Result<Self> {
+        let store = Self::with_options(Self::client_options(url).await?, &db_name)?;
+
+        store.setup().await?;
+
+        Ok(store)
+    }
+
+    /// Creates a new MongoStore like [`MongoStore::new`], recording how long its commands take
+    /// in the given metrics.
+    ///
+    /// Nothing is sent to MongoDB yet, so this succeeds while it is unavailable. The store has to
+    /// be set up with [`MongoStore::setup`] once it is.
+    ///
+    /// # Errors
+    /// Fails if the url is invalid
+    pub async fn with_metrics(
+        url: String,
+        db_name: String,
+        metrics: &Metrics,
+    ) -> anyhow::Result<Self> {
+        let mut client_options = Self::client_options(url).await?;
+        client_options.command_event_handler = Some(metrics.mongodb_event_handler());
+
+        Self::with_options(client_options, &db_name)
+    }
+
+    /// Parses a mongodb url into the client options of the store
+    ///
+    /// # Errors
+    /// Fails if the url is invalid
+    async fn client_options(url: String) -> anyhow::Result<ClientOptions> {
+        let mut client_options = ClientOptions::parse(url).await?;
+        client_options.app_name = Some("auth".to_string());
+        client_options.connect_timeout = Some(Duration::from_secs(3));
+        client_options.server_selection_timeout = Some(Duration::from_secs(10));
+
+        Ok(client_options)
+    }
+
+    /// Creates a store with the given client options, without connecting yet
+    ///
+    /// # Errors
+    /// Fails if the options are invalid
+    fn with_options(client_options: ClientOptions, db_name: &str) -> anyhow::Result<Self> {
+        let client = Client::with_options(client_options)?;
+        let database = client.database(db_name);
+
+        Ok(Self { client, database })
+    }
+
+    /// Migrates documents stored by older versions and creates the indexes of every collection. Can be
+    /// retried if it fails.
+    ///
+    /// # Errors
+    /// Fails if a database error occurs
+    pub async fn setup(&self) -> anyhow::Result<()> {
+        let database = &self.database;
+
+        let credentials_options = IndexOptions::builder().unique(true).build();
+        let credentials_model = IndexModel::builder()
+            .keys(doc! {"username": 1})
+            .options(credentials_options)
+            .build();
+
+        database
+            .collection::<Credentials>("credentials")
+            .create_index(credentials_model, None)
+            .await?;
+
+        let sessions = database.collection::<Session>("sessions");
+
+        MongoStore::migrate_sessions(database).await?;
+        MongoStore::migrate_usernames(database).await?;
+
+        let session_options = IndexOptions::builder().unique(true).build();
+        let session_model = IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(session_options)
+            .build();
+
+        let last_seen_options = IndexOptions::builder().expire_after(SESSION_TTL).build();
+        let last_seen_model = IndexModel::builder()
+            .keys(doc! {"lastSeen": 1})
+            .options(last_seen_options)
+            .build();
+        let session_username_model = IndexModel::builder().keys(doc! {"username": 1}).build();
+        let used_token_model = IndexModel::builder()
+            .keys(doc! {"used_token_hashes": 1})
+            .build();
+
+        sessions
+            .create_indexes(
+                [
+                    session_model,
+                    last_seen_model,
+                    session_username_model,
+                    used_token_model,
+                ],
+                None,
+            )
+            .await?;
+
+        let reset_token_options = IndexOptions::builder().unique(true).build();
+        let reset_token_model = IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(reset_token_options)
+            .build();
+
+        let reset_expiry_options = IndexOptions::builder()
+            .expire_after(Duration::from_secs(0))
+            .build();
+        let reset_expiry_model = IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(reset_expiry_options)
+            .build();
+
+        database
+            .collection::<ResetToken>("reset_tokens")
+            .create_indexes([reset_token_model, reset_expiry_model], None)
+            .await?;
+
+        let challenge_options = IndexOptions::builder().unique(true).build();
+        let challenge_model = IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(challenge_options)
+            .build();
+
+        let challenge_expiry_options = IndexOptions::builder()
+            .expire_after(Duration::from_secs(0))
+            .build();
+        let challenge_expiry_model = IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(challenge_expiry_options)
+            .build();
+
+        database
+            .collection::<LoginChallenge>("login_challenges")
+            .create_indexes([challenge_model, challenge_expiry_model], None)
+            .await?;
+
+        let attempts_options = IndexOptions::builder().unique(true).build();
+        let attempts_model = IndexModel::builder()
+            .keys(doc! {"key": 1})
+            .options(attempts_options)
+            .build();
+
+        let attempts_expiry_options = IndexOptions::builder()
+            .expire_after(FAILED_ATTEMPT_WINDOW)
+            .build();
+        let attempts_expiry_model = IndexModel::builder()
+            .keys(doc! {"last_failed_at": 1})
+            .options(attempts_expiry_options)
+            .build();
+
+        database
+            .collection::<FailedAttempts>("failed_attempts")
+            .create_indexes([attempts_model, attempts_expiry_model], None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Brings sessions stored by older versions up to date.
+    ///
+    /// Sessions created before session metadata was recorded get an id, sessions storing their
+    /// raw token get the token replaced by its digest, and sessions storing the time they were
+    /// last seen in `createdAt` get it moved to `lastSeen`. Where the creation time was not
+    /// recorded, the last seen time is the closest estimate.
+    ///
+    /// # Errors
+    /// Fails if a database error occurs
+    async fn migrate_sessions(database: &Database) -> anyhow::Result<()> {
+        let sessions = database.collection::<Document>("sessions");
+
+        sessions
+            .update_many(
+                doc! { "id": { "$exists": false } },
+                vec![doc! { "$set": { "id": { "$toString": "$_id" } } }],
+                None,
+            )
+            .await?;
+
+        sessions
+            .update_many(
+                doc! { "lastSeen": { "$exists": false } },
+                vec![
+                    doc! { "$set": {
+                        "lastSeen": { "$ifNull": ["$createdAt", "$$NOW"] },
+                        "createdAt": { "$ifNull": [
+                            "$created_at",
+                            { "$ifNull": ["$createdAt", "$$NOW"] },
+                        ] },
+                    } },
+                    doc! { "$unset": "created_at" },
+                ],
+                None,
+            )
+            .await?;
+
+        // The index on the raw token is replaced by a unique index on its digest, and sessions
+        // expire once they were not seen for a while rather than after they were created
+        for index in ["token_1", "createdAt_1"] {
+            if sessions
+                .list_index_names()
+                .await?
+                .iter()
+                .any(|name| name == index)
+            {
+                sessions.drop_index(index, None).await?;
+            }
+        }
+
+        let mut legacy_sessions = sessions
+            .find(doc! { "token": { "$exists": true } }, None)
+            .await?;
+
+        while let Some(legacy_session) = legacy_sessions.try_next().await? {
+            let token = legacy_session.get_str("token")?;
+
+            sessions
+                .update_one(
+                    doc! { "_id": legacy_session.get("_id"), "token": token },
+                    doc! {
+                        "$set": { "token_hash": hash_token(token) },
+                        "$unset": { "token": "" },
+                    },
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves credentials and sessions stored under a username that is not normalized, from
+    /// before usernames were normalized, to the normalized username.
+    ///
+    /// A user is left alone if the username is not valid or its normalized form is taken by
+    /// another user, so it can be sorted out by hand.
+    ///
+    /// # Errors
+    /// Fails if a database error occurs
+    async fn migrate_usernames(database: &Database) -> anyhow::Result<()> {
+        let credentials = database.collection::<Document>("credentials");
+        let sessions = database.collection::<Document>("sessions");
+
+        // Usernames made of these characters only can't change when normalized
+        let mut candidates = credentials
+            .find(
+                doc! { "username": { "$not": Regex {
+                    pattern: "^[a-z0-9._-]*$".to_string(),
+                    options: String::new(),
+                } } },
+                None,
+            )
+            .await?;
+
+        while let Some(candidate) = candidates.try_next().await? {
+            let raw_username = candidate.get_str("username")?;
+
+            let username = match NormalizedUsername::parse(raw_username) {
+                Ok(username) if username == raw_username => continue,
+                Ok(username) => username,
+                Err(err) => {
+                    tracing::warn!("Can't normalize username {:?}: {}", raw_username, err);
+                    continue;
+                }
+            };
+
+            let result = credentials
+                .update_one(
+                    doc! { "_id": candidate.get("_id"), "username": raw_username },
+                    doc! { "$set": { "username": username.as_str() } },
+                    None,
+                )
+                .await;
+
+            match result {
+                Ok(_) => {}
+                Err(err) => match *err.kind {
+                    ErrorKind::Write(WriteFailure::WriteError(ref write_error))
+                        if write_error.code == DUPLICATE_KEY_ERROR =>
+                    {
+                        tracing::warn!(
+                            "Can't normalize username {:?}, {} is taken by another user",
+                            raw_username,
+                            username
+                        );
+                        continue;
+                    }
+                    _ => return Err(err.into()),
+                },
+            }
+
+            sessions
+                .update_many(
+                    doc! { "username": raw_username },
+                    doc! { "$set": { "username": username.as_str() } },
+                    None,
+                )
+                .await?;
+
+            tracing::info!("Normalized username {:?} to {}", raw_username, username);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the underlying MongoDB client
+    pub fn client(&self) -> Client {
+        self.client.clone()
+    }
+}
+
+#[async_trait]
+impl CredentialStore for MongoStore {
+    async fn find_credentials(&self, username: &str) -> Result<Option<Credentials>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let credentials_collection = self.database.collection::<Credentials>("credentials");
+
+            let credentials = credentials_collection
+                .find_one_with_session(doc! { "username": username }, None, &mut session)
+                .await?;
+
+            Ok(credentials)
+        }
+        .instrument(mongodb_span("credentials", "find_one"))
+        .await
+    }
+
+    async fn insert_credentials(&self, credentials: Credentials) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let credentials_collection = self.database.collection::<Credentials>("credentials");
+            let username = credentials.username().clone();
+
+            let result = credentials_collection
+                .insert_one_with_session(credentials, None, &mut session)
+                .await;
+
+            match result {
+                Ok(_) => Ok(()),
+                Err(err) => match *err.kind {
+                    ErrorKind::Write(WriteFailure::WriteError(ref write_error))
+                        if write_error.code == DUPLICATE_KEY_ERROR =>
+                    {
+                        Err(ServiceError::UsernameTaken(username))
+                    }
+                    _ => Err(err.into()),
+                },
+            }
+        }
+        .instrument(mongodb_span("credentials", "insert_one"))
+        .await
+    }
+
+    async fn update_password(&self, credentials: &Credentials) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let credentials_collection = self.database.collection::<Credentials>("credentials");
+
+            credentials_collection
+                .update_one_with_session(
+                    doc! { "username": credentials.username() },
+                    doc! {
+                        "$set": { "password_hash": credentials.password_hash() },
+                        "$unset": { "salt": "" },
+                    },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("credentials", "update_one"))
+        .await
+    }
+
+    async fn update_second_factor(
+        &self,
+        username: &str,
+        current: Option<&SecondFactor>,
+        second_factor: Option<&SecondFactor>,
+    ) -> Result<bool, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let credentials_collection = self.database.collection::<Credentials>("credentials");
+
+            // Embedded documents only compare equal with the same fields in the same order, which
+            // holds since every second factor is written from the same struct. `null` also matches
+            // credentials without a second factor.
+            let current =
+                to_bson(&current).map_err(|err| ServiceError::DatabaseError(err.to_string()))?;
+
+            let update = match second_factor {
+                Some(second_factor) => {
+                    let second_factor = to_bson(second_factor)
+                        .map_err(|err| ServiceError::DatabaseError(err.to_string()))?;
+
+                    doc! { "$set": { "second_factor": second_factor } }
+                }
+                None => doc! { "$unset": { "second_factor": "" } },
+            };
+
+            let result = credentials_collection
+                .update_one_with_session(
+                    doc! { "username": username, "second_factor": current },
+                    update,
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(result.matched_count > 0)
+        }
+        .instrument(mongodb_span("credentials", "update_one"))
+        .await
+    }
+
+    async fn delete_credentials(&self, username: &str) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let credentials_collection = self.database.collection::<Credentials>("credentials");
+
+            credentials_collection
+                .delete_one_with_session(doc! { "username": username }, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("credentials", "delete_one"))
+        .await
+    }
+}
+
+#[async_trait]
+impl SessionStore for MongoStore {
+    async fn insert_session(&self, session_object: Session) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            session_collection
+                .insert_one_with_session(session_object, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("sessions", "insert_one"))
+        .await
+    }
+
+    async fn touch_session(&self, token_hash: &str) -> Result<Option<Session>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            let options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+
+            let session_option = session_collection
+                .find_one_and_update_with_session(
+                    doc! { "token_hash": token_hash },
+                    doc! { "$set": { "lastSeen": DateTime::now() } },
+                    options,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(session_option)
+        }
+        .instrument(mongodb_span("sessions", "find_one_and_update"))
+        .await
+    }
+
+    async fn rotate_session(
+        &self,
+        token_hash: &str,
+        new_token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            let options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+
+            let session_option = session_collection
+                .find_one_and_update_with_session(
+                    doc! { "token_hash": token_hash },
+                    doc! {
+                        "$set": { "token_hash": new_token_hash, "lastSeen": DateTime::now() },
+                        "$push": { "used_token_hashes": {
+                            "$each": [token_hash],
+                            "$slice": -(USED_TOKEN_HISTORY as i64),
+                        } },
+                    },
+                    options,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(session_option)
+        }
+        .instrument(mongodb_span("sessions", "find_one_and_update"))
+        .await
+    }
+
+    async fn delete_reused_session(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Session>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            let session_option = session_collection
+                .find_one_and_delete_with_session(
+                    doc! { "used_token_hashes": token_hash },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(session_option)
+        }
+        .instrument(mongodb_span("sessions", "find_one_and_delete"))
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            session_collection
+                .delete_one_with_session(doc! { "token_hash": token_hash }, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("sessions", "delete_one"))
+        .await
+    }
+
+    async fn find_user_sessions(&self, username: &str) -> Result<Vec<Session>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            let sessions = session_collection
+                .find_with_session(doc! { "username": username }, None, &mut session)
+                .await?
+                .stream(&mut session)
+                .try_collect()
+                .await?;
+
+            Ok(sessions)
+        }
+        .instrument(mongodb_span("sessions", "find"))
+        .await
+    }
+
+    async fn delete_user_session(&self, username: &str, id: &str) -> Result<bool, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            let result = session_collection
+                .delete_one_with_session(
+                    doc! { "username": username, "id": id },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(result.deleted_count > 0)
+        }
+        .instrument(mongodb_span("sessions", "delete_one"))
+        .await
+    }
+
+    async fn delete_user_sessions(&self, username: &str) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            session_collection
+                .delete_many_with_session(doc! { "username": username }, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("sessions", "delete_many"))
+        .await
+    }
+
+    async fn count_sessions(&self) -> Result<u64, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let session_collection = self.database.collection::<Session>("sessions");
+
+            // The TTL index only removes expired sessions about once a minute
+            let expired_before = DateTime::from_millis(
+                DateTime::now().timestamp_millis() - SESSION_TTL.as_millis() as i64,
+            );
+
+            let count = session_collection
+                .count_documents_with_session(
+                    doc! { "lastSeen": { "$gt": expired_before } },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(count)
+        }
+        .instrument(mongodb_span("sessions", "count_documents"))
+        .await
+    }
+}
+
+#[async_trait]
+impl ResetTokenStore for MongoStore {
+    async fn insert_reset_token(&self, reset_token: ResetToken) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let reset_token_collection = self.database.collection::<ResetToken>("reset_tokens");
+
+            reset_token_collection
+                .insert_one_with_session(reset_token, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("reset_tokens", "insert_one"))
+        .await
+    }
+
+    async fn take_reset_token(&self, token_hash: &str) -> Result<Option<ResetToken>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let reset_token_collection = self.database.collection::<ResetToken>("reset_tokens");
+
+            let reset_token = reset_token_collection
+                .find_one_and_delete_with_session(
+                    doc! { "token_hash": token_hash },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(reset_token)
+        }
+        .instrument(mongodb_span("reset_tokens", "find_one_and_delete"))
+        .await
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for MongoStore {
+    async fn insert_challenge(&self, challenge: LoginChallenge) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let challenge_collection = self
+                .database
+                .collection::<LoginChallenge>("login_challenges");
+
+            challenge_collection
+                .insert_one_with_session(challenge, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("login_challenges", "insert_one"))
+        .await
+    }
+
+    async fn take_challenge(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<LoginChallenge>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let challenge_collection = self
+                .database
+                .collection::<LoginChallenge>("login_challenges");
+
+            let challenge = challenge_collection
+                .find_one_and_delete_with_session(
+                    doc! { "token_hash": token_hash },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(challenge)
+        }
+        .instrument(mongodb_span("login_challenges", "find_one_and_delete"))
+        .await
+    }
+}
+
+#[async_trait]
+impl AttemptStore for MongoStore {
+    async fn find_failed_attempts(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let attempts_collection = self
+                .database
+                .collection::<FailedAttempts>("failed_attempts");
+
+            let attempts = attempts_collection
+                .find_one_with_session(doc! { "key": key }, None, &mut session)
+                .await?;
+
+            // The TTL monitor only runs periodically, so expired records can still be around
+            Ok(attempts.filter(|attempts| !attempts.is_older_than(FAILED_ATTEMPT_WINDOW)))
+        }
+        .instrument(mongodb_span("failed_attempts", "find_one"))
+        .await
+    }
+
+    async fn record_failed_attempt(
+        &self,
+        key: &str,
+    ) -> Result<Option<FailedAttempts>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let attempts_collection = self
+                .database
+                .collection::<FailedAttempts>("failed_attempts");
+
+            let now = DateTime::now();
+            let window_start = DateTime::from_millis(
+                now.timestamp_millis() - FAILED_ATTEMPT_WINDOW.as_millis() as i64,
+            );
+
+            let options = FindOneAndUpdateOptions::builder()
+                .upsert(true)
+                .return_document(ReturnDocument::Before)
+                .build();
+
+            // Counting starts over if the previous failure fell out of the window
+            let previous = attempts_collection
+                .find_one_and_update_with_session(
+                    doc! { "key": key },
+                    vec![doc! { "$set": {
+                        "count": { "$cond": [
+                            { "$gt": ["$last_failed_at", window_start] },
+                            { "$add": ["$count", 1] },
+                            1,
+                        ] },
+                        "last_failed_at": now,
+                    } }],
+                    options,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(previous.filter(|previous| !previous.is_older_than(FAILED_ATTEMPT_WINDOW)))
+        }
+        .instrument(mongodb_span("failed_attempts", "find_one_and_update"))
+        .await
+    }
+
+    async fn forgive_failed_attempt(
+        &self,
+        key: &str,
+        previous: Option<&FailedAttempts>,
+    ) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let attempts_collection = self
+                .database
+                .collection::<FailedAttempts>("failed_attempts");
+
+            let update = match previous {
+                Some(previous) => doc! {
+                    "$inc": { "count": -1 },
+                    "$set": { "last_failed_at": previous.last_failed_at() },
+                },
+                None => doc! { "$inc": { "count": -1 } },
+            };
+
+            attempts_collection
+                .update_one_with_session(
+                    doc! { "key": key, "count": { "$gt": 0 } },
+                    update,
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("failed_attempts", "update_one"))
+        .await
+    }
+
+    async fn clear_failed_attempts(&self, key: &str) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+            let attempts_collection = self
+                .database
+                .collection::<FailedAttempts>("failed_attempts");
+
+            attempts_collection
+                .delete_one_with_session(doc! { "key": key }, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("failed_attempts", "delete_one"))
+        .await
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use core_rs::config::ThrottleConfig;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::ClientInfo;
+
+/// Failed login attempts recorded for a single key, such as a username or a client IP address.
+///
+/// Attempts are forgotten once no failure was recorded for
+/// [`FAILED_ATTEMPT_WINDOW`](crate::store::FAILED_ATTEMPT_WINDOW).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FailedAttempts {
+    key: String,
+    count: u32,
+    last_failed_at: DateTime,
+}
+
+impl FailedAttempts {
+    /// Creates a new record holding a single failed attempt made now
+    pub fn new(key: String) -> Self {
+        FailedAttempts {
+            key,
+            count: 1,
+            last_failed_at: DateTime::now(),
+        }
+    }
+
+    /// Returns the key the attempts were recorded for
+    pub fn key(&self) -> &String {
+        &self.key
+    }
+
+    /// Returns how many failed attempts were recorded
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the time of the most recent failed attempt
+    pub fn last_failed_at(&self) -> DateTime {
+        self.last_failed_at
+    }
+
+    /// Returns true if the most recent failure is older than the given window
+    pub fn is_older_than(&self, window: Duration) -> bool {
+        DateTime::now().timestamp_millis() - self.last_failed_at.timestamp_millis()
+            >= window.as_millis() as i64
+    }
+
+    /// Records another failed attempt made now, starting over if the previous failure is older
+    /// than the given window
+    pub fn record_failure(&mut self, window: Duration) {
+        self.count = if self.is_older_than(window) {
+            1
+        } else {
+            self.count + 1
+        };
+        self.last_failed_at = DateTime::now();
+    }
+
+    /// Takes back the most recent failed attempt, for an attempt that turned out to succeed or
+    /// was rejected. The time of the failure before it, if any, is put back, so the attempt
+    /// doesn't push back when the next one is allowed.
+    pub fn forgive_failure(&mut self, previous_failed_at: Option<DateTime>) {
+        self.count = self.count.saturating_sub(1);
+
+        if let Some(previous_failed_at) = previous_failed_at {
+            self.last_failed_at = previous_failed_at;
+        }
+    }
+}
+
+/// Exponential backoff applied to a single kind of key once it has too many failed attempts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThrottlePolicy {
+    /// Failed attempts allowed before a delay is enforced
+    pub free_attempts: u32,
+    /// Delay enforced after the last free attempt, doubled with every further failure
+    pub base_delay: Duration,
+    /// Upper bound of the delay. Once reached, the key is effectively locked out for this long
+    /// after every failure.
+    pub max_delay: Duration,
+}
+
+impl ThrottlePolicy {
+    /// Returns how long to wait after the last of the given number of failed attempts
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures < self.free_attempts {
+            return Duration::ZERO;
+        }
+
+        let doublings = (failures - self.free_attempts).min(31);
+
+        self.base_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+
+    /// Returns how long until another attempt is allowed, or `None` if one is allowed now
+    pub fn retry_after(&self, attempts: &FailedAttempts) -> Option<Duration> {
+        let delay = self.delay(attempts.count()).as_millis() as i64;
+        let elapsed =
+            DateTime::now().timestamp_millis() - attempts.last_failed_at().timestamp_millis();
+
+        (elapsed < delay).then(|| Duration::from_millis((delay - elapsed) as u64))
+    }
+}
+
+/// Limits how often logins can fail, both for a single account and for a single client IP
+/// address.
+///
+/// The per account limit slows down guessing the password of one user, the per IP limit slows
+/// down trying a few common passwords against many users. The IP limit is more lenient by
+/// default, since many users can share an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoginThrottle {
+    /// Backoff for failed logins to the same account
+    pub account: ThrottlePolicy,
+    /// Backoff for failed logins from the same client IP address
+    pub ip: ThrottlePolicy,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        LoginThrottle {
+            account: ThrottlePolicy {
+                free_attempts: 5,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(900),
+            },
+            ip: ThrottlePolicy {
+                free_attempts: 20,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(900),
+            },
+        }
+    }
+}
+
+impl LoginThrottle {
+    /// Creates the throttle from the configuration, falling back to the defaults for everything
+    /// that is not set. The delays apply to both the account and the IP address limit.
+    ///
+    /// # Errors
+    /// Fails if the base delay is larger than the maximum delay
+    pub fn from_config(config: &ThrottleConfig) -> anyhow::Result<Self> {
+        let default = LoginThrottle::default();
+
+        let base_delay = config
+            .base_delay
+            .map_or(default.account.base_delay, Duration::from_secs);
+        let max_delay = config
+            .max_delay
+            .map_or(default.account.max_delay, Duration::from_secs);
+
+        if base_delay > max_delay {
+            anyhow::bail!("Base throttle delay is larger than the maximum delay");
+        }
+
+        Ok(LoginThrottle {
+            account: ThrottlePolicy {
+                free_attempts: config
+                    .account_free_attempts
+                    .unwrap_or(default.account.free_attempts),
+                base_delay,
+                max_delay,
+            },
+            ip: ThrottlePolicy {
+                free_attempts: config.ip_free_attempts.unwrap_or(default.ip.free_attempts),
+                base_delay,
+                max_delay,
+            },
+        })
+    }
+
+    /// Returns the keys failed attempts are tracked under for a login, together with the policy
+    /// applying to each
+    pub fn keys<'a>(
+        &'a self,
+        username: &str,
+        client: &ClientInfo,
+    ) -> Vec<(String, &'a ThrottlePolicy)> {
+        let mut keys = vec![(LoginThrottle::account_key(username), &self.account)];
+
+        if let Some(ip) = &client.ip {
+            keys.push((format!("ip:{}", ip), &self.ip));
+        }
+
+        keys
+    }
+
+    /// Returns the key failed attempts to log in to an account are tracked under
+    pub fn account_key(username: &str) -> String {
+        format!("user:{}", username)
+    }
+}
@@ -0,0 +1,190 @@
+use std::{fmt, fs, time::Duration};
+
+use anyhow::anyhow;
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use core_rs::{
+    config::AuthConfig,
+    token::{AccessClaims, ISSUER},
+};
+use jsonwebtoken::{
+    encode, get_current_timestamp,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, EncodingKey, Header,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How long an access token is valid by default
+pub const DEFAULT_ACCESS_TOKEN_TTL: Duration = Duration::from_secs(300);
+
+/// A signed access token as it is handed to the client
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccessToken {
+    pub access_token: String,
+    pub token_type: String,
+    /// Lifetime of the token in seconds
+    pub expires_in: u64,
+}
+
+/// Signs short-lived access tokens with an Ed25519 key.
+///
+/// The public key is published as a JWK set so other services can verify access tokens locally
+/// with a [`TokenVerifier`](core_rs::token::TokenVerifier).
+#[derive(Clone)]
+pub struct TokenSigner {
+    key_id: String,
+    encoding_key: EncodingKey,
+    public_key: Vec<u8>,
+    access_token_ttl: Duration,
+}
+
+impl fmt::Debug for TokenSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSigner")
+            .field("key_id", &self.key_id)
+            .field("access_token_ttl", &self.access_token_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenSigner {
+    /// Creates a new TokenSigner with a freshly generated key
+    ///
+    /// Tokens signed with a generated key can't be verified once the process exits, so this is
+    /// only meant for tests and local development.
+    pub fn generate() -> Self {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .expect("Failed to generate an Ed25519 key");
+
+        TokenSigner::from_pkcs8(pkcs8.as_ref()).expect("Generated key should be valid")
+    }
+
+    /// Creates a new TokenSigner from a DER encoded PKCS#8 Ed25519 private key
+    ///
+    /// # Errors
+    /// Fails if the key is not a valid Ed25519 key
+    pub fn from_pkcs8(der: &[u8]) -> anyhow::Result<Self> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+            .map_err(|err| anyhow!("Invalid Ed25519 key: {}", err))?;
+        let public_key = key_pair.public_key().as_ref().to_vec();
+
+        Ok(TokenSigner {
+            key_id: hex::encode(&Sha256::digest(&public_key)[..8]),
+            encoding_key: EncodingKey::from_ed_der(der),
+            public_key,
+            access_token_ttl: DEFAULT_ACCESS_TOKEN_TTL,
+        })
+    }
+
+    /// Creates the signer from the signing key file and access token lifetime of the auth
+    /// configuration.
+    ///
+    /// The key file is a PKCS#8 Ed25519 private key in PEM or DER format, e.g. generated with
+    /// `openssl genpkey -algorithm ed25519`. A key is generated if it is not set.
+    ///
+    /// # Errors
+    /// Fails if the key can't be read or is not a valid Ed25519 key
+    pub fn from_config(config: &AuthConfig) -> anyhow::Result<Self> {
+        let signer = match &config.signing_key_file {
+            Some(path) => {
+                let contents = fs::read(path)
+                    .map_err(|err| anyhow!("Failed to read signing key {}: {}", path, err))?;
+
+                TokenSigner::from_pkcs8(&decode_pem(&contents)?)?
+            }
+            None => {
+                tracing::warn!("No signing key file configured, generating an ephemeral key");
+                TokenSigner::generate()
+            }
+        };
+
+        let ttl = config
+            .access_token_ttl
+            .map_or(DEFAULT_ACCESS_TOKEN_TTL, Duration::from_secs);
+
+        Ok(signer.with_access_token_ttl(ttl))
+    }
+
+    /// Sets how long access tokens are valid
+    pub fn with_access_token_ttl(mut self, ttl: Duration) -> Self {
+        self.access_token_ttl = ttl;
+        self
+    }
+
+    /// Returns the id of the signing key, sent in the `kid` header of every access token
+    pub fn key_id(&self) -> &String {
+        &self.key_id
+    }
+
+    /// Signs a new access token for a session of a user
+    pub fn sign(&self, username: &str, session_id: &str) -> AccessToken {
+        let now = get_current_timestamp();
+        let claims = AccessClaims {
+            sub: username.to_string(),
+            sid: session_id.to_string(),
+            iss: ISSUER.to_string(),
+            iat: now,
+            exp: now + self.access_token_ttl.as_secs(),
+        };
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.key_id.clone());
+
+        AccessToken {
+            access_token: encode(&header, &claims, &self.encoding_key)
+                .expect("Signing an access token should not fail"),
+            token_type: "Bearer".to_string(),
+            expires_in: self.access_token_ttl.as_secs(),
+        }
+    }
+
+    /// Returns the public key as a JWK set
+    pub fn jwks(&self) -> JwkSet {
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(self.key_id.clone()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(&self.public_key),
+            }),
+        };
+
+        JwkSet { keys: vec![jwk] }
+    }
+}
+
+/// Returns the DER contents of a PEM file, or the contents unchanged if they are not PEM
+fn decode_pem(contents: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let Ok(text) = std::str::from_utf8(contents) else {
+        return Ok(contents.to_vec());
+    };
+
+    if !text.trim_start().starts_with("-----BEGIN") {
+        return Ok(contents.to_vec());
+    }
+
+    let base64: String = text
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+
+    STANDARD
+        .decode(base64)
+        .map_err(|err| anyhow!("Invalid PEM signing key: {}", err))
+}
@@ -0,0 +1,201 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{distributions::Uniform, Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::hash_token;
+
+/// Issuer shown next to the account in authenticator apps
+pub const TOTP_ISSUER: &str = "dirc";
+
+/// Number of digits of a TOTP code
+const TOTP_DIGITS: usize = 6;
+
+/// Length of a TOTP time step in seconds
+const TOTP_STEP: u64 = 30;
+
+/// Number of time steps before and after the current one a code is still accepted for, to
+/// tolerate clock drift
+const TOTP_SKEW: u64 = 1;
+
+/// How many recovery codes are issued when two-factor authentication is enabled
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Characters recovery codes are made of, without easily confused ones like `0`/`o` and `1`/`l`
+const RECOVERY_CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// TOTP (RFC 6238) second factor of a user, stored alongside their [`Credentials`](crate::Credentials).
+///
+/// A second factor starts out pending after enrollment and is only enforced on login once it was
+/// confirmed with a valid code. Recovery codes are single use and only their digests are stored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecondFactor {
+    /// Base32 encoded shared secret
+    secret: String,
+    enabled: bool,
+    #[serde(default)]
+    recovery_code_hashes: Vec<String>,
+    /// The time step of the last accepted code, so a code can't be replayed
+    #[serde(default)]
+    last_used_step: Option<u64>,
+}
+
+/// Result of starting the enrollment of a second factor
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    /// Base32 encoded shared secret, for entering into an authenticator app manually
+    pub secret: String,
+    /// `otpauth://` URI of the secret, usually shown as a QR code
+    pub otpauth_uri: String,
+}
+
+/// One-time recovery codes, handed to the user once when two-factor authentication is enabled
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+impl SecondFactor {
+    /// Creates a new pending second factor with a random 160-bit secret
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 20];
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        let Secret::Encoded(secret) = Secret::Raw(secret.to_vec()).to_encoded() else {
+            unreachable!("Encoding a secret returns an encoded secret");
+        };
+
+        SecondFactor {
+            secret,
+            enabled: false,
+            recovery_code_hashes: Vec::new(),
+            last_used_step: None,
+        }
+    }
+
+    /// Returns true if the second factor was confirmed and is enforced on login
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns how many unused recovery codes are left
+    pub fn recovery_codes_left(&self) -> usize {
+        self.recovery_code_hashes.len()
+    }
+
+    /// Returns the secret and `otpauth://` URI for setting up an authenticator app
+    pub fn enrollment(&self, username: &str) -> TotpEnrollment {
+        TotpEnrollment {
+            secret: self.secret.clone(),
+            otpauth_uri: self.totp(username).get_url(),
+        }
+    }
+
+    /// Marks the second factor as enabled and issues a fresh set of recovery codes, replacing
+    /// any previous ones. Returns the plain text recovery codes.
+    pub fn enable(&mut self) -> RecoveryCodes {
+        let recovery_codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect::<Vec<_>>();
+
+        self.enabled = true;
+        self.recovery_code_hashes = recovery_codes
+            .iter()
+            .map(|code| hash_token(&normalize_recovery_code(code)))
+            .collect();
+
+        RecoveryCodes { recovery_codes }
+    }
+
+    /// Checks a TOTP code against the current time, see [`SecondFactor::verify_code_at`]
+    pub fn verify_code(&mut self, code: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before the Unix epoch")
+            .as_secs();
+
+        self.verify_code_at(code, now)
+    }
+
+    /// Checks a TOTP code for the given Unix time, allowing one time step of clock drift.
+    ///
+    /// A code is only accepted once: codes from the time step of the last accepted code or
+    /// earlier are rejected.
+    pub fn verify_code_at(&mut self, code: &str, time: u64) -> bool {
+        let code = code.trim();
+        let totp = self.totp("");
+        let current_step = time / TOTP_STEP;
+
+        let matched_step = (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+            .filter(|step| self.last_used_step.is_none_or(|last| *step > last))
+            .find(|step| {
+                let expected = totp.generate(step * TOTP_STEP);
+                bool::from(expected.as_bytes().ct_eq(code.as_bytes()))
+            });
+
+        match matched_step {
+            Some(step) => {
+                self.last_used_step = Some(step);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks a recovery code, removing it if it matches so it can't be used again
+    pub fn use_recovery_code(&mut self, code: &str) -> bool {
+        let code_hash = hash_token(&normalize_recovery_code(code));
+
+        let position = self
+            .recovery_code_hashes
+            .iter()
+            .position(|stored| bool::from(stored.as_bytes().ct_eq(code_hash.as_bytes())));
+
+        match position {
+            Some(position) => {
+                self.recovery_code_hashes.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn totp(&self, username: &str) -> TOTP {
+        let secret = Secret::Encoded(self.secret.clone())
+            .to_bytes()
+            .expect("Stored TOTP secret should be valid base32");
+
+        TOTP::new_unchecked(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            TOTP_SKEW as u8,
+            TOTP_STEP,
+            secret,
+            Some(TOTP_ISSUER.to_string()),
+            username.to_string(),
+        )
+    }
+}
+
+/// Generates a random recovery code of the form `xxxxx-xxxxx`
+fn generate_recovery_code() -> String {
+    let alphabet = Uniform::from(0..RECOVERY_CODE_ALPHABET.len());
+    let mut rng = rand::thread_rng();
+
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.sample(alphabet)] as char)
+        .collect();
+    code.insert(5, '-');
+
+    code
+}
+
+/// Makes recovery codes match regardless of case, whitespace and dashes
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
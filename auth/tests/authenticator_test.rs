@@ -2,6 +2,8 @@ use auth::{LoginInfo, SessionToken};
 
 use core_rs::{error::ServiceError, Username};
 
+const PASSWORD: &str = "correct horse battery staple";
+
 #[derive(Clone)]
 struct Authenticator {
     inner: auth::db::Authenticator,
@@ -27,10 +29,14 @@ impl Authenticator {
     }
 
     async fn login(&self, info: LoginInfo) -> Result<SessionToken, ServiceError> {
-        self.inner.login(info).await
+        self.inner.login(info).await.map(|response| {
+            response
+                .into_session_token()
+                .expect("Test users don't have a second factor")
+        })
     }
 
-    async fn logout(&self, token: &String) -> Result<(), ServiceError> {
+    async fn logout(&self, token: &str) -> Result<(), ServiceError> {
         self.inner.logout(token).await
     }
 
@@ -64,8 +70,8 @@ async fn get_authenticator() -> Authenticator {
 
 macro_rules! assert_not_error {
     ($result:expr) => {
-        if $result.is_err() {
-            panic!("{}", format!("{:?}", $result.unwrap_err()));
+        if let Err(err) = &$result {
+            panic!("{:?}", err);
         }
     };
 }
@@ -73,7 +79,7 @@ macro_rules! assert_not_error {
 #[tokio::test]
 async fn test_register_succeeds() {
     let auth = get_authenticator().await;
-    let result = auth.register(LoginInfo::new("username", "password")).await;
+    let result = auth.register(LoginInfo::new("username", PASSWORD)).await;
 
     assert_not_error!(result);
 }
@@ -81,19 +87,15 @@ async fn test_register_succeeds() {
 #[tokio::test]
 async fn test_register_fails_if_username_is_taken() {
     let auth = get_authenticator().await;
-    let result = auth.register(LoginInfo::new("username", "password")).await;
+    let result = auth.register(LoginInfo::new("username", PASSWORD)).await;
     assert_not_error!(result);
 
     let result = auth
         .register(LoginInfo::new("username", "another password"))
         .await;
 
-    if result.is_ok() {
-        panic!("Expected to fail registering a username that is already taken");
-    }
-
     assert_eq!(
-        result.unwrap_err(),
+        result.expect_err("Expected to fail registering a username that is already taken"),
         ServiceError::UsernameTaken("username".to_string())
     );
 }
@@ -101,7 +103,7 @@ async fn test_register_fails_if_username_is_taken() {
 #[tokio::test]
 async fn test_register_returns_session_token() {
     let auth = get_authenticator().await;
-    let result = auth.register(LoginInfo::new("username", "password")).await;
+    let result = auth.register(LoginInfo::new("username", PASSWORD)).await;
     assert_not_error!(result);
 
     let token = result.unwrap();
@@ -111,7 +113,7 @@ async fn test_register_returns_session_token() {
 #[tokio::test]
 async fn test_register_session_token_can_auth() {
     let auth = get_authenticator().await;
-    let result = auth.register(LoginInfo::new("username", "password")).await;
+    let result = auth.register(LoginInfo::new("username", PASSWORD)).await;
     assert_not_error!(result);
 
     let token = result.unwrap();
@@ -123,7 +125,7 @@ async fn test_register_session_token_can_auth() {
 #[tokio::test]
 async fn test_invalid_session_token_fails_auth() {
     let auth = get_authenticator().await;
-    let result = auth.register(LoginInfo::new("username", "password")).await;
+    let result = auth.register(LoginInfo::new("username", PASSWORD)).await;
     assert_not_error!(result);
 
     let token = result.unwrap();
@@ -131,18 +133,17 @@ async fn test_invalid_session_token_fails_auth() {
 
     let result = auth.authenticate(wrong_token.token()).await;
 
-    if result.is_ok() {
-        panic!("Expected to fail authenticating with an invalid token");
-    }
-
-    assert_eq!(result.unwrap_err(), ServiceError::AuthenticationError);
+    assert_eq!(
+        result.expect_err("Expected to fail authenticating with an invalid token"),
+        ServiceError::AuthenticationError
+    );
 }
 
 #[tokio::test]
 async fn test_login_fails_before_register() {
     let auth = get_authenticator().await;
 
-    let info = LoginInfo::new("username", "password");
+    let info = LoginInfo::new("username", PASSWORD);
     let result = auth.login(info.clone()).await;
     assert!(result.is_err(), "Login should fail before registration");
 }
@@ -150,7 +151,7 @@ async fn test_login_fails_before_register() {
 #[tokio::test]
 async fn test_can_login_after_register() {
     let auth = get_authenticator().await;
-    let info = LoginInfo::new("username", "password");
+    let info = LoginInfo::new("username", PASSWORD);
     let result = auth.register(info.clone()).await;
     assert_not_error!(result);
 
@@ -161,7 +162,7 @@ async fn test_can_login_after_register() {
 #[tokio::test]
 async fn test_login_wrong_password() {
     let auth = get_authenticator().await;
-    let result = auth.register(LoginInfo::new("username", "password")).await;
+    let result = auth.register(LoginInfo::new("username", PASSWORD)).await;
     assert_not_error!(result);
 
     let wrong_info = LoginInfo::new("username", "wrong password");
@@ -172,7 +173,7 @@ async fn test_login_wrong_password() {
 #[tokio::test]
 async fn test_login_returns_session_token() {
     let auth = get_authenticator().await;
-    let info = LoginInfo::new("username", "password");
+    let info = LoginInfo::new("username", PASSWORD);
 
     assert_not_error!(auth.register(info.clone()).await);
 
@@ -186,7 +187,7 @@ async fn test_login_returns_session_token() {
 #[tokio::test]
 async fn test_login_session_token_can_auth() {
     let auth = get_authenticator().await;
-    let info = LoginInfo::new("username", "password");
+    let info = LoginInfo::new("username", PASSWORD);
 
     assert_not_error!(auth.register(info.clone()).await);
 
@@ -202,7 +203,7 @@ async fn test_login_session_token_can_auth() {
 #[tokio::test]
 async fn test_logout() {
     let auth = get_authenticator().await;
-    let info = LoginInfo::new("username", "password");
+    let info = LoginInfo::new("username", PASSWORD);
 
     assert_not_error!(auth.register(info.clone()).await);
 
@@ -215,3 +216,30 @@ async fn test_logout() {
     let result = auth.authenticate(token.token()).await;
     assert!(result.is_err(), "Login should fail after logout");
 }
+
+#[tokio::test]
+async fn test_login_after_username_is_normalized() {
+    let auth = get_authenticator().await;
+
+    // Stored by a version that did not normalize usernames
+    let credentials = auth::Credentials::new(&LoginInfo::new("User.Name", PASSWORD));
+    auth.inner
+        .get_client()
+        .await
+        .database("auth")
+        .collection::<auth::Credentials>("credentials")
+        .insert_one(credentials, None)
+        .await
+        .expect("Failed to insert credentials");
+
+    // Opening the store again normalizes the stored usernames
+    let migrated =
+        auth::db::Authenticator::new("mongodb://localhost:27017".to_string(), "auth".to_string())
+            .await
+            .expect("Failed to connect to MongoDB");
+
+    for username in ["User.Name", "user.name"] {
+        let result = migrated.login(LoginInfo::new(username, PASSWORD)).await;
+        assert_not_error!(result);
+    }
+}
@@ -0,0 +1,213 @@
+use auth::{
+    db::Authenticator,
+    hashing::HashAlgorithm,
+    store::{CredentialStore, MemoryStore},
+    Credentials, LoginInfo,
+};
+use core_rs::validation::Validate;
+use pbkdf2::pbkdf2_hmac_array;
+use sha2::Sha256;
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+
+/// Builds a credentials record the way it was stored before the switch to Argon2id
+fn legacy_credentials(username: &str, password: &str) -> Credentials {
+    let salt = "0123456789abcdefghijklmnopqrstuv";
+    let hash = pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), salt.as_bytes(), 4096);
+
+    serde_json::from_value(serde_json::json!({
+        "username": username,
+        // The original implementation encoded the already hex encoded hash a second time
+        "password_hash": hex::encode(hex::encode(hash)),
+        "salt": salt,
+    }))
+    .expect("Legacy credentials should deserialize")
+}
+
+#[test]
+fn test_new_credentials_use_argon2id() {
+    let credentials = Credentials::new(&LoginInfo::new(USERNAME, "password"));
+    let json = serde_json::to_value(&credentials).unwrap();
+
+    assert!(json["password_hash"]
+        .as_str()
+        .unwrap()
+        .starts_with("$argon2id$"));
+    assert!(json.get("salt").is_none());
+    assert!(!credentials.needs_rehash(&HashAlgorithm::default()));
+}
+
+#[test]
+fn test_argon2id_credentials_match() {
+    let credentials = Credentials::new(&LoginInfo::new(USERNAME, "password"));
+
+    assert!(credentials.matches(&LoginInfo::new(USERNAME, "password")));
+    assert!(!credentials.matches(&LoginInfo::new(USERNAME, "wrong password")));
+}
+
+#[test]
+fn test_legacy_credentials_match() {
+    let credentials = legacy_credentials(USERNAME, "password");
+
+    assert!(credentials.needs_rehash(&HashAlgorithm::default()));
+    assert!(credentials.matches(&LoginInfo::new(USERNAME, "password")));
+    assert!(!credentials.matches(&LoginInfo::new(USERNAME, "wrong password")));
+}
+
+#[test]
+fn test_stored_legacy_credentials_match() {
+    // A record written by the original PBKDF2 implementation
+    let credentials: Credentials = serde_json::from_value(serde_json::json!({
+        "username": USERNAME,
+        "password_hash": "30343631303338336165366166316431666161363339393065316135343831613262633862633837636462656439626533663437306434343137646138393234",
+        "salt": "hT4kQ9zRw2mB7xLc1VpN8sYd3GfJ6aEu",
+    }))
+    .unwrap();
+
+    assert!(credentials.matches(&LoginInfo::new(USERNAME, "hunter2 is not secure")));
+    assert!(!credentials.matches(&LoginInfo::new(USERNAME, "hunter2")));
+}
+
+#[tokio::test]
+async fn test_login_rehashes_legacy_credentials() {
+    let store = MemoryStore::new();
+    store
+        .insert_credentials(legacy_credentials(USERNAME, "password"))
+        .await
+        .unwrap();
+
+    let auth = Authenticator::with_store(store.clone());
+    auth.login(LoginInfo::new(USERNAME, "password"))
+        .await
+        .expect("Login with a legacy hash should succeed");
+
+    let credentials = store.find_credentials(USERNAME).await.unwrap().unwrap();
+    assert!(!credentials.needs_rehash(&HashAlgorithm::default()));
+
+    auth.login(LoginInfo::new(USERNAME, "password"))
+        .await
+        .expect("Login with the rehashed password should succeed");
+}
+
+#[tokio::test]
+async fn test_failed_login_keeps_legacy_credentials() {
+    let store = MemoryStore::new();
+    store
+        .insert_credentials(legacy_credentials(USERNAME, "password"))
+        .await
+        .unwrap();
+
+    let auth = Authenticator::with_store(store.clone());
+    assert!(auth
+        .login(LoginInfo::new(USERNAME, "wrong password"))
+        .await
+        .is_err());
+
+    let credentials = store.find_credentials(USERNAME).await.unwrap().unwrap();
+    assert!(credentials.needs_rehash(&HashAlgorithm::default()));
+}
+
+/// Cheap parameters for every supported algorithm so the tests stay fast
+fn cheap_algorithms() -> Vec<HashAlgorithm> {
+    vec![
+        HashAlgorithm::Argon2id {
+            memory_cost: 1024,
+            time_cost: 1,
+            parallelism: 1,
+        },
+        HashAlgorithm::Pbkdf2 { rounds: 1000 },
+        HashAlgorithm::Bcrypt { cost: 4 },
+        HashAlgorithm::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        },
+    ]
+}
+
+#[test]
+fn test_every_algorithm_verifies_its_hashes() {
+    for algorithm in cheap_algorithms() {
+        let hash = algorithm.hash("password");
+
+        assert!(HashAlgorithm::verify(&hash, "password"), "{:?}", algorithm);
+        assert!(
+            !HashAlgorithm::verify(&hash, "wrong password"),
+            "{:?}",
+            algorithm
+        );
+        assert!(!algorithm.needs_rehash(&hash), "{:?}", algorithm);
+    }
+}
+
+#[test]
+fn test_changed_algorithm_or_cost_needs_rehash() {
+    let algorithms = cheap_algorithms();
+
+    for algorithm in &algorithms {
+        let hash = algorithm.hash("password");
+
+        for other in algorithms.iter().filter(|other| *other != algorithm) {
+            assert!(other.needs_rehash(&hash), "{:?} -> {:?}", algorithm, other);
+        }
+    }
+
+    let hash = HashAlgorithm::Bcrypt { cost: 4 }.hash("password");
+    assert!(HashAlgorithm::Bcrypt { cost: 5 }.needs_rehash(&hash));
+
+    let hash = HashAlgorithm::Pbkdf2 { rounds: 1000 }.hash("password");
+    assert!(HashAlgorithm::Pbkdf2 { rounds: 2000 }.needs_rehash(&hash));
+}
+
+#[test]
+fn test_invalid_parameters_are_rejected() {
+    assert!(HashAlgorithm::Bcrypt { cost: 2 }.validate().is_err());
+    assert!(HashAlgorithm::Pbkdf2 { rounds: 0 }.validate().is_err());
+    assert!(HashAlgorithm::with_default_params("md5").is_err());
+}
+
+#[tokio::test]
+async fn test_login_upgrades_hash_after_cost_increase() {
+    let weak = HashAlgorithm::Pbkdf2 { rounds: 1000 };
+    let strong = HashAlgorithm::Pbkdf2 { rounds: 2000 };
+    let info = LoginInfo::new(USERNAME, "correct horse battery staple");
+
+    let store = MemoryStore::new();
+    Authenticator::with_store(store.clone())
+        .with_hash_algorithm(weak)
+        .register(info.clone())
+        .await
+        .expect("Registration should succeed");
+
+    let credentials = store.find_credentials(USERNAME).await.unwrap().unwrap();
+    assert!(credentials.needs_rehash(&strong));
+
+    Authenticator::with_store(store.clone())
+        .with_hash_algorithm(strong)
+        .login(info)
+        .await
+        .expect("Login should succeed with the old hash");
+
+    let credentials = store.find_credentials(USERNAME).await.unwrap().unwrap();
+    assert!(!credentials.needs_rehash(&strong));
+}
+
+#[test]
+fn test_login_info_requires_both_fields() {
+    assert!(LoginInfo::new(USERNAME, "secret").validate().is_empty());
+
+    let fields = LoginInfo::new(" ", "")
+        .validate()
+        .into_iter()
+        .map(|error| (error.field, error.code))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        fields,
+        [
+            ("username".to_string(), "required".to_string()),
+            ("password".to_string(), "required".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,187 @@
+use auth::{db::Authenticator, store::MemoryStore, LoginInfo, SessionToken};
+
+use core_rs::error::ServiceError;
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+const PASSWORD: &str = "correct horse battery staple";
+
+fn get_authenticator() -> Authenticator<MemoryStore> {
+    Authenticator::with_store(MemoryStore::new())
+}
+
+#[tokio::test]
+async fn test_register_returns_session_token() {
+    let auth = get_authenticator();
+
+    let token = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    assert_eq!(token.username(), USERNAME);
+}
+
+#[tokio::test]
+async fn test_register_fails_if_username_is_taken() {
+    let auth = get_authenticator();
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    let result = auth
+        .register(LoginInfo::new(USERNAME, "another password"))
+        .await;
+
+    assert_eq!(
+        result.unwrap_err(),
+        ServiceError::UsernameTaken(USERNAME.to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_login_and_authenticate() {
+    let auth = get_authenticator();
+    let info = LoginInfo::new(USERNAME, PASSWORD);
+
+    auth.register(info.clone())
+        .await
+        .expect("Registration should succeed");
+
+    let token = auth
+        .login(info)
+        .await
+        .expect("Login should succeed")
+        .into_session_token()
+        .unwrap();
+
+    let username = auth
+        .authenticate(token.token())
+        .await
+        .expect("Session token should authenticate");
+
+    assert_eq!(username.username, USERNAME);
+}
+
+#[tokio::test]
+async fn test_login_errors() {
+    let auth = get_authenticator();
+
+    let result = auth.login(LoginInfo::new(USERNAME, PASSWORD)).await;
+    assert_eq!(
+        result.unwrap_err(),
+        ServiceError::UserNotFound(USERNAME.to_string())
+    );
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    let result = auth.login(LoginInfo::new(USERNAME, "wrong password")).await;
+    assert_eq!(result.unwrap_err(), ServiceError::InvalidPassword);
+}
+
+#[tokio::test]
+async fn test_invalid_session_token_fails_auth() {
+    let auth = get_authenticator();
+
+    let token = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    let wrong_token = SessionToken::from_parts(token.username(), "token");
+
+    let result = auth.authenticate(wrong_token.token()).await;
+    assert_eq!(result.unwrap_err(), ServiceError::AuthenticationError);
+}
+
+#[tokio::test]
+async fn test_logout() {
+    let auth = get_authenticator();
+
+    let token = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    auth.logout(token.token())
+        .await
+        .expect("Logout should succeed");
+
+    let result = auth.authenticate(token.token()).await;
+    assert_eq!(result.unwrap_err(), ServiceError::AuthenticationError);
+}
+
+#[tokio::test]
+async fn test_user_exists() {
+    let auth = get_authenticator();
+
+    assert_eq!(auth.user_exists(USERNAME.to_string()).await, Ok(false));
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    assert_eq!(auth.user_exists(USERNAME.to_string()).await, Ok(true));
+}
+
+#[tokio::test]
+async fn test_register_enforces_password_policy() {
+    let auth = get_authenticator();
+
+    let result = auth.register(LoginInfo::new(USERNAME, "test")).await;
+
+    let Err(ServiceError::PasswordPolicyViolation(violations)) = result else {
+        panic!("Expected a password policy violation, got {:?}", result);
+    };
+
+    let rules = violations
+        .iter()
+        .map(|violation| violation.rule.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(rules, vec!["min_length", "common"]);
+
+    let result = auth.register(LoginInfo::new(USERNAME, USERNAME)).await;
+    assert!(matches!(
+        result,
+        Err(ServiceError::PasswordPolicyViolation(violations)) if violations[0].rule == "username"
+    ));
+
+    assert_eq!(auth.user_exists(USERNAME.to_string()).await, Ok(false));
+}
+
+#[tokio::test]
+async fn test_usernames_are_normalized() {
+    let auth = get_authenticator();
+
+    let token = auth
+        .register(LoginInfo::new("Test_User ", PASSWORD))
+        .await
+        .expect("Registration should succeed");
+    assert_eq!(token.username(), USERNAME);
+
+    let result = auth.register(LoginInfo::new("TEST_USER", PASSWORD)).await;
+    assert_eq!(
+        result.unwrap_err(),
+        ServiceError::UsernameTaken(USERNAME.to_string())
+    );
+
+    auth.login(LoginInfo::new("  test_USER", PASSWORD))
+        .await
+        .expect("Login should succeed with a differently cased username");
+
+    assert_eq!(auth.user_exists("TEST_user".to_string()).await, Ok(true));
+}
+
+#[tokio::test]
+async fn test_invalid_usernames_are_rejected() {
+    let auth = get_authenticator();
+
+    let result = auth.register(LoginInfo::new("test/user", PASSWORD)).await;
+    assert!(matches!(result, Err(ServiceError::InvalidUsername(_))));
+
+    let result = auth.login(LoginInfo::new("te", PASSWORD)).await;
+    assert!(matches!(result, Err(ServiceError::InvalidUsername(_))));
+}
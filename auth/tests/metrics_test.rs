@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use auth::{
+    db::Authenticator,
+    metrics::AuthMetrics,
+    store::MemoryStore,
+    throttle::{LoginThrottle, ThrottlePolicy},
+    LoginInfo,
+};
+use core_rs::metrics::Metrics;
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+const PASSWORD: &str = "correct horse battery staple";
+
+fn get_authenticator() -> Authenticator<MemoryStore> {
+    let policy = ThrottlePolicy {
+        free_attempts: 2,
+        base_delay: Duration::from_secs(60),
+        max_delay: Duration::from_secs(60),
+    };
+
+    Authenticator::with_store(MemoryStore::new()).with_login_throttle(LoginThrottle {
+        account: policy.clone(),
+        ip: policy,
+    })
+}
+
+#[tokio::test]
+async fn test_logins_are_counted_by_result() {
+    let auth = get_authenticator();
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    auth.login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+    auth.login(LoginInfo::new("test_nobody", PASSWORD))
+        .await
+        .unwrap_err();
+
+    for _ in 0..2 {
+        auth.login(LoginInfo::new(USERNAME, "wrong password"))
+            .await
+            .unwrap_err();
+    }
+
+    auth.login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap_err();
+
+    let metrics = auth.metrics();
+    assert_eq!(metrics.logins("success"), 1);
+    assert_eq!(metrics.logins("invalid_credentials"), 3);
+    assert_eq!(metrics.logins("throttled"), 1);
+    assert_eq!(metrics.logins("error"), 0);
+}
+
+#[tokio::test]
+async fn test_only_successful_registrations_are_counted() {
+    let auth = get_authenticator();
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap_err();
+
+    assert_eq!(auth.metrics().registrations(), 1);
+}
+
+#[tokio::test]
+async fn test_active_sessions_are_counted() {
+    let auth = get_authenticator();
+    let session = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    auth.login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    auth.update_sessions_active().await.unwrap();
+    assert_eq!(auth.metrics().sessions_active(), 2);
+
+    auth.logout(session.token()).await.unwrap();
+
+    auth.update_sessions_active().await.unwrap();
+    assert_eq!(auth.metrics().sessions_active(), 1);
+}
+
+#[tokio::test]
+async fn test_metrics_are_exported_by_the_registry() {
+    let metrics = Metrics::new();
+    let auth_metrics = AuthMetrics::default();
+    auth_metrics.register(&metrics).unwrap();
+
+    let auth = get_authenticator().with_metrics(auth_metrics);
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+    auth.login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+    auth.update_sessions_active().await.unwrap();
+
+    let body = metrics.render();
+    assert!(body.contains(r#"auth_login_total{result="success"} 1"#));
+    assert!(body.contains("auth_register_total 1"));
+    assert!(body.contains("auth_sessions_active 2"));
+}
@@ -0,0 +1,296 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use auth::{
+    db::Authenticator,
+    notifier::Notifier,
+    store::{MemoryStore, ResetTokenStore},
+    throttle::LoginThrottle,
+    LoginInfo, PasswordChange, PasswordReset, PasswordResetRequest, ResetToken,
+};
+use core_rs::{
+    error::{FieldError, ServiceError},
+    validation::Validate,
+};
+use mongodb::bson::DateTime;
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+const PASSWORD: &str = "correct horse battery staple";
+const NEW_PASSWORD: &str = "tr0ub4dor and then some";
+
+/// Remembers every reset token it was asked to deliver
+#[derive(Debug, Default)]
+struct RecordingNotifier {
+    tokens: Mutex<Vec<(String, String)>>,
+}
+
+#[async_trait]
+impl Notifier for RecordingNotifier {
+    async fn send_password_reset(
+        &self,
+        username: &str,
+        token: &str,
+        _expires_at: DateTime,
+    ) -> Result<(), ServiceError> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .push((username.to_string(), token.to_string()));
+
+        Ok(())
+    }
+}
+
+async fn get_authenticator() -> (Authenticator<MemoryStore>, Arc<RecordingNotifier>) {
+    let notifier = Arc::new(RecordingNotifier::default());
+    let auth = Authenticator::with_store(MemoryStore::new()).with_notifier(notifier.clone());
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    (auth, notifier)
+}
+
+fn change(old_password: &str, new_password: &str) -> PasswordChange {
+    PasswordChange {
+        old_password: old_password.to_string(),
+        new_password: new_password.to_string(),
+    }
+}
+
+fn reset(token: &str, new_password: &str) -> PasswordReset {
+    PasswordReset {
+        token: token.to_string(),
+        new_password: new_password.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_change_password() {
+    let (auth, _) = get_authenticator().await;
+    let old_session = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    let new_session = auth
+        .change_password(old_session.token(), change(PASSWORD, NEW_PASSWORD))
+        .await
+        .expect("Password change should succeed");
+
+    assert_eq!(
+        auth.authenticate(old_session.token()).await.unwrap_err(),
+        ServiceError::AuthenticationError
+    );
+    assert!(auth.authenticate(new_session.token()).await.is_ok());
+
+    assert_eq!(
+        auth.login(LoginInfo::new(USERNAME, PASSWORD))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidPassword
+    );
+    assert!(auth
+        .login(LoginInfo::new(USERNAME, NEW_PASSWORD))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_change_password_requires_old_password_and_policy() {
+    let (auth, _) = get_authenticator().await;
+    let session = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    let result = auth
+        .change_password(session.token(), change("wrong password", NEW_PASSWORD))
+        .await;
+    assert_eq!(result.unwrap_err(), ServiceError::InvalidPassword);
+
+    let result = auth
+        .change_password(session.token(), change(PASSWORD, "short"))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ServiceError::PasswordPolicyViolation(_))
+    ));
+
+    let result = auth
+        .change_password("invalid token", change(PASSWORD, NEW_PASSWORD))
+        .await;
+    assert_eq!(result.unwrap_err(), ServiceError::AuthenticationError);
+
+    assert!(auth.authenticate(session.token()).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_wrong_old_passwords_count_as_failed_logins() {
+    let (auth, _) = get_authenticator().await;
+    let session = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+    let free_attempts = LoginThrottle::default().account.free_attempts;
+
+    for _ in 0..free_attempts {
+        let result = auth
+            .change_password(session.token(), change("wrong password", NEW_PASSWORD))
+            .await;
+        assert_eq!(result.unwrap_err(), ServiceError::InvalidPassword);
+    }
+
+    let result = auth
+        .change_password(session.token(), change(PASSWORD, NEW_PASSWORD))
+        .await;
+    assert!(matches!(result, Err(ServiceError::TooManyAttempts(_))));
+
+    assert!(matches!(
+        auth.login(LoginInfo::new(USERNAME, PASSWORD)).await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+}
+
+#[test]
+fn test_password_bodies_require_every_field() {
+    assert!(change(PASSWORD, NEW_PASSWORD).validate().is_empty());
+    assert!(reset("token", NEW_PASSWORD).validate().is_empty());
+
+    let fields = |errors: Vec<FieldError>| {
+        errors
+            .into_iter()
+            .map(|error| (error.field, error.code))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        fields(change("", "").validate()),
+        [
+            ("old_password".to_string(), "required".to_string()),
+            ("new_password".to_string(), "required".to_string()),
+        ]
+    );
+    assert_eq!(
+        fields(reset("", NEW_PASSWORD).validate()),
+        [("token".to_string(), "required".to_string())]
+    );
+
+    let request = PasswordResetRequest {
+        username: " ".to_string(),
+    };
+    assert_eq!(
+        fields(request.validate()),
+        [("username".to_string(), "required".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn test_reset_password() {
+    let (auth, notifier) = get_authenticator().await;
+    let session = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    auth.request_password_reset("Test_User".to_string())
+        .await
+        .expect("Requesting a reset should succeed");
+
+    let (username, token) = notifier.tokens.lock().unwrap()[0].clone();
+    assert_eq!(username, USERNAME);
+
+    auth.reset_password(reset(&token, NEW_PASSWORD))
+        .await
+        .expect("Reset should succeed");
+
+    assert_eq!(
+        auth.authenticate(session.token()).await.unwrap_err(),
+        ServiceError::AuthenticationError
+    );
+    assert!(auth
+        .login(LoginInfo::new(USERNAME, NEW_PASSWORD))
+        .await
+        .is_ok());
+
+    let result = auth
+        .reset_password(reset(&token, "another new password"))
+        .await;
+    assert_eq!(result.unwrap_err(), ServiceError::InvalidResetToken);
+}
+
+#[tokio::test]
+async fn test_reset_token_survives_policy_violation() {
+    let (auth, notifier) = get_authenticator().await;
+
+    auth.request_password_reset(USERNAME.to_string())
+        .await
+        .unwrap();
+    let (_, token) = notifier.tokens.lock().unwrap()[0].clone();
+
+    let result = auth.reset_password(reset(&token, "short")).await;
+    assert!(matches!(
+        result,
+        Err(ServiceError::PasswordPolicyViolation(_))
+    ));
+
+    auth.reset_password(reset(&token, NEW_PASSWORD))
+        .await
+        .expect("Reset should succeed after fixing the password");
+}
+
+#[tokio::test]
+async fn test_reset_unknown_user_sends_nothing() {
+    let (auth, notifier) = get_authenticator().await;
+
+    auth.request_password_reset("nobody".to_string())
+        .await
+        .expect("Requesting a reset for an unknown user should not fail");
+
+    assert!(notifier.tokens.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_delivery_looks_like_an_unknown_user() {
+    // Without a notifier, delivering the token fails
+    let auth = Authenticator::with_store(MemoryStore::new());
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    for username in [USERNAME, "nobody"] {
+        auth.request_password_reset(username.to_string())
+            .await
+            .expect("Requesting a reset should not tell whether the user exists");
+    }
+}
+
+#[tokio::test]
+async fn test_expired_reset_token_is_rejected() {
+    let store = MemoryStore::new();
+    let auth = Authenticator::with_store(store.clone());
+
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    let (reset_token, token) = ResetToken::new(USERNAME.to_string(), Duration::ZERO);
+    store.insert_reset_token(reset_token).await.unwrap();
+
+    let result = auth.reset_password(reset(&token, NEW_PASSWORD)).await;
+    assert_eq!(result.unwrap_err(), ServiceError::InvalidResetToken);
+}
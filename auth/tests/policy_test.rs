@@ -0,0 +1,68 @@
+use auth::{policy::PasswordPolicy, LoginInfo};
+
+fn rules(policy: &PasswordPolicy, username: &str, password: &str) -> Vec<String> {
+    policy
+        .violations(&LoginInfo::new(username, password))
+        .into_iter()
+        .map(|violation| violation.rule)
+        .collect()
+}
+
+#[test]
+fn test_default_policy_accepts_strong_password() {
+    let policy = PasswordPolicy::default();
+
+    assert!(rules(&policy, "alice", "correct horse battery staple").is_empty());
+    assert!(policy
+        .check(&LoginInfo::new("alice", "correct horse battery staple"))
+        .is_ok());
+}
+
+#[test]
+fn test_length_limits() {
+    let policy = PasswordPolicy::default();
+
+    assert_eq!(rules(&policy, "alice", ""), vec!["min_length"]);
+    assert_eq!(rules(&policy, "alice", "x7#kQ"), vec!["min_length"]);
+    assert_eq!(
+        rules(&policy, "alice", &"x7#kQ".repeat(30)),
+        vec!["max_length"]
+    );
+}
+
+#[test]
+fn test_common_passwords_are_denied_case_insensitively() {
+    let policy = PasswordPolicy::default();
+
+    assert_eq!(rules(&policy, "alice", "Password123"), vec!["common"]);
+    assert_eq!(rules(&policy, "alice", "QWERTYUIOP"), vec!["common"]);
+}
+
+#[test]
+fn test_username_similarity() {
+    let policy = PasswordPolicy::default();
+
+    assert_eq!(
+        rules(&policy, "alice_w", "ALICE_W"),
+        vec!["min_length", "username"]
+    );
+    assert_eq!(rules(&policy, "alice_w", "alice_w2023"), vec!["username"]);
+    assert_eq!(rules(&policy, "alice_w", "w_ecila!!"), vec!["username"]);
+}
+
+#[test]
+fn test_character_classes() {
+    let policy = PasswordPolicy {
+        require_lowercase: true,
+        require_uppercase: true,
+        require_digit: true,
+        require_symbol: true,
+        ..PasswordPolicy::default()
+    };
+
+    assert_eq!(
+        rules(&policy, "alice", "correcthorsebattery"),
+        vec!["uppercase", "digit", "symbol"]
+    );
+    assert!(rules(&policy, "alice", "Correct-Horse-7").is_empty());
+}
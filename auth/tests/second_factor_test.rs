@@ -0,0 +1,440 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use auth::{
+    db::{Authenticator, MAX_CHALLENGE_ATTEMPTS},
+    store::{CredentialStore, MemoryStore},
+    throttle::LoginThrottle,
+    totp::{SecondFactor, RECOVERY_CODE_COUNT},
+    Credentials, LoginInfo, LoginResponse, PasswordChange, SecondFactorChallenge, SecondFactorCode,
+    SecondFactorVerification, SessionToken,
+};
+use core_rs::{error::ServiceError, validation::Validate};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+const PASSWORD: &str = "correct horse battery staple";
+
+/// Generates the code of a secret for the time step `steps` steps away from the current one
+fn code(secret: &str, steps: i64) -> String {
+    let totp = TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+        None,
+        String::new(),
+    );
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    totp.generate((now + steps * 30) as u64)
+}
+
+fn code_body(code: &str) -> SecondFactorCode {
+    SecondFactorCode {
+        code: code.to_string(),
+    }
+}
+
+fn verification(challenge: &SecondFactorChallenge, code: &str) -> SecondFactorVerification {
+    SecondFactorVerification {
+        challenge_token: challenge.challenge_token.clone(),
+        code: code.to_string(),
+    }
+}
+
+/// The test user after enabling two-factor authentication
+struct EnabledSecondFactor {
+    session: SessionToken,
+    secret: String,
+    confirmation_code: String,
+    recovery_codes: Vec<String>,
+}
+
+/// Registers the test user and enables two-factor authentication
+async fn enable_second_factor(auth: &Authenticator<MemoryStore>) -> EnabledSecondFactor {
+    let session = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    let enrollment = auth
+        .enroll_second_factor(session.token())
+        .await
+        .expect("Enrollment should succeed");
+
+    let confirmation_code = code(&enrollment.secret, 0);
+    let recovery_codes = auth
+        .confirm_second_factor(session.token(), code_body(&confirmation_code))
+        .await
+        .expect("Confirmation should succeed");
+
+    EnabledSecondFactor {
+        session,
+        secret: enrollment.secret,
+        confirmation_code,
+        recovery_codes: recovery_codes.recovery_codes,
+    }
+}
+
+async fn login_challenge(auth: &Authenticator<MemoryStore>) -> SecondFactorChallenge {
+    match auth.login(LoginInfo::new(USERNAME, PASSWORD)).await {
+        Ok(LoginResponse::SecondFactorRequired(challenge)) => challenge,
+        other => panic!("Expected a second factor challenge, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_enrollment_returns_otpauth_uri() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let session = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    let enrollment = auth.enroll_second_factor(session.token()).await.unwrap();
+
+    assert!(enrollment
+        .otpauth_uri
+        .starts_with("otpauth://totp/dirc:test_user?"));
+    assert!(enrollment
+        .otpauth_uri
+        .contains(&format!("secret={}", enrollment.secret)));
+
+    // Not enforced until confirmed
+    let response = auth.login(LoginInfo::new(USERNAME, PASSWORD)).await;
+    assert!(matches!(response, Ok(LoginResponse::Session(_))));
+}
+
+#[tokio::test]
+async fn test_login_requires_second_factor_once_confirmed() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let EnabledSecondFactor {
+        secret,
+        confirmation_code,
+        recovery_codes,
+        ..
+    } = enable_second_factor(&auth).await;
+
+    assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+
+    let challenge = login_challenge(&auth).await;
+
+    assert_eq!(
+        auth.authenticate(&challenge.challenge_token)
+            .await
+            .unwrap_err(),
+        ServiceError::AuthenticationError
+    );
+
+    // The code used for confirmation can't be replayed
+    assert_eq!(
+        auth.verify_second_factor(verification(&challenge, &confirmation_code))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidSecondFactor
+    );
+
+    let session = auth
+        .verify_second_factor(verification(&challenge, &code(&secret, 1)))
+        .await
+        .expect("A code for the next time step should be accepted");
+
+    assert!(auth.authenticate(session.token()).await.is_ok());
+
+    // The challenge can only be answered once
+    assert_eq!(
+        auth.verify_second_factor(verification(&challenge, &code(&secret, 1)))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidSecondFactor
+    );
+}
+
+#[tokio::test]
+async fn test_recovery_codes_are_single_use() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let recovery_codes = enable_second_factor(&auth).await.recovery_codes;
+
+    let challenge = login_challenge(&auth).await;
+    auth.verify_second_factor(verification(&challenge, &recovery_codes[0].to_uppercase()))
+        .await
+        .expect("Recovery codes should be accepted regardless of case");
+
+    let challenge = login_challenge(&auth).await;
+    assert_eq!(
+        auth.verify_second_factor(verification(&challenge, &recovery_codes[0]))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidSecondFactor
+    );
+
+    let challenge = login_challenge(&auth).await;
+    assert!(auth
+        .verify_second_factor(verification(&challenge, &recovery_codes[1]))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_challenge_is_discarded_after_too_many_attempts() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let secret = enable_second_factor(&auth).await.secret;
+
+    let challenge = login_challenge(&auth).await;
+
+    for _ in 0..MAX_CHALLENGE_ATTEMPTS {
+        assert_eq!(
+            auth.verify_second_factor(verification(&challenge, "000000"))
+                .await
+                .unwrap_err(),
+            ServiceError::InvalidSecondFactor
+        );
+    }
+
+    assert_eq!(
+        auth.verify_second_factor(verification(&challenge, &code(&secret, 1)))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidSecondFactor
+    );
+}
+
+#[tokio::test]
+async fn test_enrollment_errors() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let session = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        auth.confirm_second_factor(session.token(), code_body("123456"))
+            .await
+            .unwrap_err(),
+        ServiceError::SecondFactorNotEnrolled
+    );
+
+    auth.enroll_second_factor(session.token()).await.unwrap();
+
+    assert_eq!(
+        auth.confirm_second_factor(session.token(), code_body("not a code"))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidSecondFactor
+    );
+
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let session = enable_second_factor(&auth).await.session;
+
+    assert_eq!(
+        auth.enroll_second_factor(session.token())
+            .await
+            .unwrap_err(),
+        ServiceError::SecondFactorAlreadyEnabled
+    );
+}
+
+#[tokio::test]
+async fn test_second_factor_survives_password_change_and_can_be_disabled() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let EnabledSecondFactor {
+        session, secret, ..
+    } = enable_second_factor(&auth).await;
+
+    let new_password = "another correct horse battery staple";
+    let session = auth
+        .change_password(
+            session.token(),
+            PasswordChange {
+                old_password: PASSWORD.to_string(),
+                new_password: new_password.to_string(),
+            },
+        )
+        .await
+        .expect("Password change should succeed");
+
+    let response = auth.login(LoginInfo::new(USERNAME, new_password)).await;
+    assert!(matches!(
+        response,
+        Ok(LoginResponse::SecondFactorRequired(_))
+    ));
+
+    assert_eq!(
+        auth.disable_second_factor(session.token(), code_body("000000"))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidSecondFactor
+    );
+
+    auth.disable_second_factor(session.token(), code_body(&code(&secret, 1)))
+        .await
+        .expect("Disabling with a valid code should succeed");
+
+    let response = auth.login(LoginInfo::new(USERNAME, new_password)).await;
+    assert!(matches!(response, Ok(LoginResponse::Session(_))));
+}
+
+#[test]
+fn test_codes_are_accepted_within_one_step_of_drift() {
+    let mut second_factor = SecondFactor::generate();
+    let secret = second_factor.enrollment(USERNAME).secret;
+    let totp = TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        Secret::Encoded(secret).to_bytes().unwrap(),
+        None,
+        String::new(),
+    );
+    let time = 1_700_000_000;
+
+    assert!(!second_factor.verify_code_at(&totp.generate(time - 60), time));
+    assert!(second_factor.verify_code_at(&totp.generate(time - 30), time));
+    assert!(second_factor.verify_code_at(&totp.generate(time + 30), time));
+    assert!(!second_factor.verify_code_at(&totp.generate(time), time));
+    assert!(!second_factor.verify_code_at(&totp.generate(time + 60), time));
+}
+
+#[tokio::test]
+async fn test_wrong_codes_count_as_failed_logins() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let secret = enable_second_factor(&auth).await.secret;
+    let free_attempts = LoginThrottle::default().account.free_attempts;
+
+    // Every challenge allows a few attempts, starting new ones must not allow more guesses
+    let mut challenges = Vec::new();
+    for _ in 0..=free_attempts {
+        challenges.push(login_challenge(&auth).await);
+    }
+
+    for challenge in &challenges[1..] {
+        assert_eq!(
+            auth.verify_second_factor(verification(challenge, "000000"))
+                .await
+                .unwrap_err(),
+            ServiceError::InvalidSecondFactor
+        );
+    }
+
+    assert!(matches!(
+        auth.verify_second_factor(verification(&challenges[0], &code(&secret, 1)))
+            .await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+
+    assert!(matches!(
+        auth.login(LoginInfo::new(USERNAME, PASSWORD)).await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_wrong_codes_of_a_session_count_as_failed_logins() {
+    let auth = Authenticator::with_store(MemoryStore::new());
+    let enabled = enable_second_factor(&auth).await;
+    let free_attempts = LoginThrottle::default().account.free_attempts;
+
+    for _ in 0..free_attempts {
+        assert_eq!(
+            auth.disable_second_factor(enabled.session.token(), code_body("000000"))
+                .await
+                .unwrap_err(),
+            ServiceError::InvalidSecondFactor
+        );
+    }
+
+    assert!(matches!(
+        auth.disable_second_factor(
+            enabled.session.token(),
+            code_body(&enabled.recovery_codes[0])
+        )
+        .await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+
+    // Confirming a new secret shares the limit of the account
+    let session = auth
+        .register(LoginInfo::new("test_other", PASSWORD))
+        .await
+        .unwrap();
+    auth.enroll_second_factor(session.token()).await.unwrap();
+
+    for _ in 0..free_attempts {
+        assert_eq!(
+            auth.confirm_second_factor(session.token(), code_body("000000"))
+                .await
+                .unwrap_err(),
+            ServiceError::InvalidSecondFactor
+        );
+    }
+
+    assert!(matches!(
+        auth.confirm_second_factor(session.token(), code_body("000000"))
+            .await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+}
+
+#[test]
+fn test_code_bodies_require_every_field() {
+    assert!(code_body("123456").validate().is_empty());
+    assert_eq!(code_body("").validate()[0].field, "code");
+
+    let empty = SecondFactorVerification {
+        challenge_token: String::new(),
+        code: String::new(),
+    };
+    let fields = empty
+        .validate()
+        .into_iter()
+        .map(|error| error.field)
+        .collect::<Vec<_>>();
+    assert_eq!(fields, ["challenge_token", "code"]);
+}
+
+#[tokio::test]
+async fn test_second_factor_is_only_replaced_if_unchanged() {
+    let store = MemoryStore::new();
+    let credentials = Credentials::new(&LoginInfo::new(USERNAME, PASSWORD));
+    store.insert_credentials(credentials.clone()).await.unwrap();
+
+    let first = SecondFactor::generate();
+    let second = SecondFactor::generate();
+
+    assert!(store
+        .update_second_factor(USERNAME, None, Some(&first))
+        .await
+        .unwrap());
+    assert!(!store
+        .update_second_factor(USERNAME, None, Some(&second))
+        .await
+        .unwrap());
+
+    // Changing the password leaves the second factor alone
+    store
+        .update_password(&credentials.with_password(
+            &LoginInfo::new(USERNAME, "another password"),
+            &Default::default(),
+        ))
+        .await
+        .unwrap();
+
+    let stored = store.find_credentials(USERNAME).await.unwrap().unwrap();
+    assert_eq!(stored.second_factor(), Some(&first));
+    assert!(stored.matches(&LoginInfo::new(USERNAME, "another password")));
+
+    assert!(store
+        .update_second_factor(USERNAME, Some(&first), None)
+        .await
+        .unwrap());
+    assert!(!store
+        .update_second_factor(USERNAME, Some(&first), None)
+        .await
+        .unwrap());
+}
@@ -0,0 +1,227 @@
+use std::{sync::Arc, time::Duration};
+
+use auth::{
+    db::Authenticator,
+    hash_token,
+    store::{CredentialStore, MemoryStore, SessionStore},
+    ClientInfo, LoginInfo,
+};
+use core_rs::error::ServiceError;
+use dirc_client::UsersApi;
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+const PASSWORD: &str = "correct horse battery staple";
+
+fn client(user_agent: &str, ip: &str) -> ClientInfo {
+    ClientInfo {
+        user_agent: Some(user_agent.to_string()),
+        ip: Some(ip.to_string()),
+    }
+}
+
+async fn get_authenticator() -> Authenticator<MemoryStore> {
+    let auth = Authenticator::with_store(MemoryStore::new());
+
+    auth.register_with_client(
+        LoginInfo::new(USERNAME, PASSWORD),
+        client("laptop", "10.0.0.1"),
+    )
+    .await
+    .expect("Registration should succeed");
+
+    auth
+}
+
+#[tokio::test]
+async fn test_list_sessions_records_metadata() {
+    let auth = get_authenticator().await;
+
+    let phone = auth
+        .login_with_client(
+            LoginInfo::new(USERNAME, PASSWORD),
+            client("phone", "10.0.0.2"),
+        )
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    let sessions = auth.list_sessions(phone.token()).await.unwrap();
+    assert_eq!(sessions.len(), 2);
+
+    let current = sessions.iter().find(|session| session.current).unwrap();
+    assert_eq!(current.user_agent.as_deref(), Some("phone"));
+    assert_eq!(current.client_ip.as_deref(), Some("10.0.0.2"));
+    assert!(current.last_seen >= current.created_at);
+
+    let other = sessions.iter().find(|session| !session.current).unwrap();
+    assert_eq!(other.user_agent.as_deref(), Some("laptop"));
+    assert_ne!(other.id, current.id);
+}
+
+#[tokio::test]
+async fn test_sessions_of_other_users_are_hidden() {
+    let auth = get_authenticator().await;
+
+    let other = auth
+        .register(LoginInfo::new("test_other", PASSWORD))
+        .await
+        .unwrap();
+    let mine = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    let other_sessions = auth.list_sessions(other.token()).await.unwrap();
+    assert_eq!(other_sessions.len(), 1);
+
+    let result = auth
+        .revoke_session(mine.token(), &other_sessions[0].id)
+        .await;
+    assert_eq!(
+        result.unwrap_err(),
+        ServiceError::SessionNotFound(other_sessions[0].id.clone())
+    );
+    assert!(auth.authenticate(other.token()).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_revoke_session() {
+    let auth = get_authenticator().await;
+
+    let laptop = auth
+        .login_with_client(
+            LoginInfo::new(USERNAME, PASSWORD),
+            client("laptop", "10.0.0.1"),
+        )
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+    let phone = auth
+        .login_with_client(
+            LoginInfo::new(USERNAME, PASSWORD),
+            client("phone", "10.0.0.2"),
+        )
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    let laptop_id = auth
+        .list_sessions(laptop.token())
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|session| session.current)
+        .unwrap()
+        .id;
+
+    auth.revoke_session(phone.token(), &laptop_id)
+        .await
+        .expect("Revoking a session should succeed");
+
+    assert_eq!(
+        auth.authenticate(laptop.token()).await.unwrap_err(),
+        ServiceError::AuthenticationError
+    );
+    assert!(auth.authenticate(phone.token()).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_logout_all() {
+    let auth = get_authenticator().await;
+
+    let laptop = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+    let phone = auth
+        .login(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap()
+        .into_session_token()
+        .unwrap();
+
+    let username = auth
+        .logout_all(phone.token())
+        .await
+        .expect("Logging out everywhere should succeed");
+    assert_eq!(username.username.as_str(), USERNAME);
+
+    for token in [laptop.token(), phone.token()] {
+        assert_eq!(
+            auth.authenticate(token).await.unwrap_err(),
+            ServiceError::AuthenticationError
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_sessions_store_only_token_digest() {
+    let store = MemoryStore::new();
+    let auth = Authenticator::with_store(store.clone());
+
+    let token = auth
+        .register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .expect("Registration should succeed");
+
+    let sessions = store.find_user_sessions(USERNAME).await.unwrap();
+
+    assert_eq!(sessions.len(), 1);
+    assert_ne!(sessions[0].token_hash(), token.token());
+    assert_eq!(sessions[0].token_hash(), &hash_token(token.token()));
+
+    // The raw token can't be used to look up a session in the store directly
+    assert!(store.touch_session(token.token()).await.unwrap().is_none());
+    assert!(auth.authenticate(token.token()).await.is_ok());
+}
+
+#[test]
+fn test_session_stores_creation_and_last_seen_separately() {
+    let (mut session, _) = auth::Session::new(USERNAME.to_string(), ClientInfo::default());
+    let created_at = session.created_at();
+
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    session.touch();
+
+    let stored = serde_json::to_value(&session).unwrap();
+    assert_eq!(
+        stored["createdAt"],
+        serde_json::to_value(created_at).unwrap()
+    );
+    assert_eq!(
+        stored["lastSeen"],
+        serde_json::to_value(session.last_seen()).unwrap()
+    );
+    assert_ne!(stored["createdAt"], stored["lastSeen"]);
+}
+
+#[tokio::test]
+async fn test_registration_is_rolled_back_if_the_profile_cant_be_created() {
+    let store = Arc::new(MemoryStore::new());
+
+    // Nothing listens on the discard port
+    let auth = Authenticator::with_store(store.clone()).with_users_api(
+        UsersApi::new("http://127.0.0.1:9").with_timeout(Duration::from_millis(200)),
+    );
+
+    for _ in 0..2 {
+        let err = auth
+            .register(LoginInfo::new("alice", PASSWORD))
+            .await
+            .unwrap_err();
+
+        // Registering again fails the same way instead of finding the username taken
+        assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+    }
+
+    assert!(store.find_credentials("alice").await.unwrap().is_none());
+    assert_eq!(store.count_sessions().await.unwrap(), 0);
+}
@@ -0,0 +1,259 @@
+use std::time::Duration;
+
+use auth::{
+    db::Authenticator,
+    hashing::HashAlgorithm,
+    store::MemoryStore,
+    throttle::{LoginThrottle, ThrottlePolicy},
+    ClientInfo, LoginInfo,
+};
+use core_rs::{config::ThrottleConfig, error::ServiceError};
+
+// Usernames starting with "test" skip creating a profile in the users service
+const USERNAME: &str = "test_user";
+const PASSWORD: &str = "correct horse battery staple";
+
+fn policy(free_attempts: u32) -> ThrottlePolicy {
+    ThrottlePolicy {
+        free_attempts,
+        base_delay: Duration::from_millis(200),
+        max_delay: Duration::from_millis(800),
+    }
+}
+
+// Attempts are timed from when they start, so hashing has to be fast compared to the delays
+fn get_authenticator() -> Authenticator<MemoryStore> {
+    Authenticator::with_store(MemoryStore::new())
+        .with_hash_algorithm(HashAlgorithm::Pbkdf2 { rounds: 1000 })
+        .with_login_throttle(LoginThrottle {
+            account: policy(3),
+            ip: policy(5),
+        })
+}
+
+fn client(ip: &str) -> ClientInfo {
+    ClientInfo {
+        user_agent: None,
+        ip: Some(ip.to_string()),
+    }
+}
+
+#[test]
+fn test_delay_doubles_up_to_the_maximum() {
+    let policy = policy(3);
+
+    assert_eq!(policy.delay(2), Duration::ZERO);
+    assert_eq!(policy.delay(3), Duration::from_millis(200));
+    assert_eq!(policy.delay(4), Duration::from_millis(400));
+    assert_eq!(policy.delay(5), Duration::from_millis(800));
+    assert_eq!(policy.delay(6), Duration::from_millis(800));
+    assert_eq!(policy.delay(u32::MAX), Duration::from_millis(800));
+}
+
+#[test]
+fn test_throttle_is_read_from_the_configuration() {
+    let config = ThrottleConfig {
+        ip_free_attempts: Some(50),
+        max_delay: Some(60),
+        ..ThrottleConfig::default()
+    };
+    let throttle = LoginThrottle::from_config(&config).unwrap();
+    let default = LoginThrottle::default();
+
+    assert_eq!(
+        throttle.account.free_attempts,
+        default.account.free_attempts
+    );
+    assert_eq!(throttle.ip.free_attempts, 50);
+    assert_eq!(throttle.ip.base_delay, default.ip.base_delay);
+    assert_eq!(throttle.account.max_delay, Duration::from_secs(60));
+
+    // The base delay can't be larger than the default maximum either
+    let config = ThrottleConfig {
+        base_delay: Some(3600),
+        ..ThrottleConfig::default()
+    };
+    assert!(LoginThrottle::from_config(&config).is_err());
+}
+
+#[tokio::test]
+async fn test_account_is_throttled_after_failed_logins() {
+    let auth = get_authenticator();
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        assert_eq!(
+            auth.login(LoginInfo::new(USERNAME, "wrong password"))
+                .await
+                .unwrap_err(),
+            ServiceError::InvalidPassword
+        );
+    }
+
+    // Even the correct password is rejected while throttled
+    assert_eq!(
+        auth.login(LoginInfo::new(USERNAME, PASSWORD))
+            .await
+            .unwrap_err(),
+        ServiceError::TooManyAttempts(1)
+    );
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    assert!(auth.login(LoginInfo::new(USERNAME, PASSWORD)).await.is_ok());
+
+    // A successful login starts the count over
+    for _ in 0..2 {
+        auth.login(LoginInfo::new(USERNAME, "wrong password"))
+            .await
+            .unwrap_err();
+    }
+    assert!(auth.login(LoginInfo::new(USERNAME, PASSWORD)).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_login_is_allowed_after_waiting_the_retry_after() {
+    let policy = ThrottlePolicy {
+        free_attempts: 3,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(1),
+    };
+    let auth = Authenticator::with_store(MemoryStore::new())
+        .with_hash_algorithm(HashAlgorithm::Pbkdf2 { rounds: 1000 })
+        .with_login_throttle(LoginThrottle {
+            account: policy.clone(),
+            ip: policy,
+        });
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        auth.login(LoginInfo::new(USERNAME, "wrong password"))
+            .await
+            .unwrap_err();
+    }
+
+    let Err(ServiceError::TooManyAttempts(retry_after)) =
+        auth.login(LoginInfo::new(USERNAME, PASSWORD)).await
+    else {
+        panic!("The login should be throttled");
+    };
+
+    // Attempts rejected while waiting don't restart the delay
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(matches!(
+        auth.login(LoginInfo::new(USERNAME, PASSWORD)).await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+
+    tokio::time::sleep(Duration::from_secs(retry_after) - Duration::from_millis(500)).await;
+
+    assert!(auth.login(LoginInfo::new(USERNAME, PASSWORD)).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_unknown_usernames_are_throttled() {
+    let auth = get_authenticator();
+
+    for _ in 0..3 {
+        assert_eq!(
+            auth.login(LoginInfo::new("test_nobody", PASSWORD))
+                .await
+                .unwrap_err(),
+            ServiceError::UserNotFound("test_nobody".to_string())
+        );
+    }
+
+    assert!(matches!(
+        auth.login(LoginInfo::new("test_nobody", PASSWORD)).await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_client_ip_is_throttled_across_accounts() {
+    let auth = get_authenticator();
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    for i in 0..5 {
+        let username = format!("test_user{}", i);
+
+        assert!(auth
+            .login_with_client(LoginInfo::new(&username, PASSWORD), client("10.0.0.1"))
+            .await
+            .is_err());
+    }
+
+    assert!(matches!(
+        auth.login_with_client(LoginInfo::new(USERNAME, PASSWORD), client("10.0.0.1"))
+            .await,
+        Err(ServiceError::TooManyAttempts(_))
+    ));
+
+    // Other addresses are not affected
+    assert!(auth
+        .login_with_client(LoginInfo::new(USERNAME, PASSWORD), client("10.0.0.2"))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_generic_login_errors_hide_unknown_users() {
+    let auth = get_authenticator().with_generic_login_errors(true);
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        auth.login(LoginInfo::new("test_nobody", PASSWORD))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidCredentials
+    );
+    assert_eq!(
+        auth.login(LoginInfo::new(USERNAME, "wrong password"))
+            .await
+            .unwrap_err(),
+        ServiceError::InvalidCredentials
+    );
+}
+
+#[tokio::test]
+async fn test_parallel_logins_are_throttled() {
+    let auth = get_authenticator();
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    let results = futures::future::join_all(
+        (0..6).map(|_| auth.login(LoginInfo::new(USERNAME, "wrong password"))),
+    )
+    .await;
+
+    let throttled = results
+        .iter()
+        .filter(|result| matches!(result, Err(ServiceError::TooManyAttempts(_))))
+        .count();
+
+    assert_eq!(throttled, 3);
+}
+
+#[tokio::test]
+async fn test_successful_logins_are_not_counted() {
+    let auth = get_authenticator();
+    auth.register(LoginInfo::new(USERNAME, PASSWORD))
+        .await
+        .unwrap();
+
+    for _ in 0..10 {
+        assert!(auth
+            .login_with_client(LoginInfo::new(USERNAME, PASSWORD), client("10.0.0.1"))
+            .await
+            .is_ok());
+    }
+}
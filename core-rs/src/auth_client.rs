@@ -0,0 +1,90 @@
+use std::{future::Future, pin::Pin};
+
+use actix_web::{
+    dev::Payload, error::ParseError, http::header::Header, web, FromRequest, HttpRequest,
+};
+use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
+use async_trait::async_trait;
+
+use crate::{error::ServiceError, username::NormalizedUsername};
+
+/// Returns the token of the `Authorization: Bearer` header of a request
+///
+/// # Errors
+/// `ServiceError::AuthorizationHeaderError` if the header is missing
+/// `ServiceError::AuthenticationError` if the header is not a bearer token
+pub fn extract_bearer_token(req: &HttpRequest) -> Result<String, ServiceError> {
+    let parsed_auth = Authorization::<Bearer>::parse(req);
+
+    match parsed_auth {
+        Ok(auth) => Ok(auth.into_scheme().token().to_string()),
+        Err(ParseError::Header) => Err(ServiceError::AuthorizationHeaderError),
+        Err(_) => Err(ServiceError::AuthenticationError),
+    }
+}
+
+/// Validates bearer tokens, e.g. with `dirc_client::AuthApi`, which asks the auth service about
+/// session tokens and verifies access tokens locally with a
+/// [`TokenVerifier`](crate::token::TokenVerifier)
+#[async_trait]
+pub trait TokenAuthenticator: Send + Sync {
+    /// Returns the user owning a session token or an access token
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is invalid
+    /// `ServiceError::ServiceUnavailable` if the token can't be checked
+    async fn authenticate(&self, token: &str) -> Result<NormalizedUsername, ServiceError>;
+}
+
+/// A user authenticated by the bearer token of the request.
+///
+/// Taking it as a handler argument rejects requests without a valid session or access token.
+/// Requires a [`TokenAuthenticator`] registered as `web::Data<dyn TokenAuthenticator>`, e.g.
+/// with `web::Data::from(Arc::new(authenticator) as Arc<dyn TokenAuthenticator>)`.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub username: NormalizedUsername,
+}
+
+impl AuthenticatedUser {
+    /// Checks that the authenticated user is the given user
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the users differ
+    pub fn require(&self, username: &NormalizedUsername) -> Result<(), ServiceError> {
+        if &self.username == username {
+            Ok(())
+        } else {
+            Err(ServiceError::AuthenticationError)
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            authenticate_request(&req)
+                .await
+                .map_err(|err| err.into_actix_error(&req))
+        })
+    }
+}
+
+async fn authenticate_request(req: &HttpRequest) -> Result<AuthenticatedUser, ServiceError> {
+    let token = extract_bearer_token(req)?;
+    let authenticator = req
+        .app_data::<web::Data<dyn TokenAuthenticator>>()
+        .ok_or_else(|| {
+            log::error!("AuthenticatedUser used without a TokenAuthenticator in the app data");
+            ServiceError::ServiceUnavailable("Authentication is not configured".to_string())
+        })?;
+
+    let username = authenticator.authenticate(&token).await?;
+
+    Ok(AuthenticatedUser { username })
+}
@@ -0,0 +1,109 @@
+use std::net::IpAddr;
+
+use actix_web::{
+    http::header::{self, HeaderName},
+    web, HttpRequest,
+};
+
+/// The proxies allowed to tell the services the address of the client they forward requests
+/// for.
+///
+/// The `Forwarded` and `X-Forwarded-For` headers can be set by any client, so they are only
+/// honoured for requests coming directly from one of these addresses. Every other request is
+/// attributed to the address it was received from. Without any trusted proxies the headers are
+/// ignored.
+///
+/// Proxies append the address they received a request from, so only the entries added by
+/// trusted proxies can be relied on. The client address is the rightmost entry that is not a
+/// trusted proxy, entries to the left of it could have been sent by the client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    /// Creates a new TrustedProxies trusting the given addresses
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        TrustedProxies(proxies)
+    }
+
+    /// Returns the trusted proxies registered as app data, or none if there are none
+    pub fn of(req: &HttpRequest) -> TrustedProxies {
+        req.app_data::<web::Data<TrustedProxies>>()
+            .map(|proxies| proxies.get_ref().clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if requests from the given address may set the client address
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    /// Returns the IP address of the client that sent a request, or `None` if it is unknown
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        let peer = req.peer_addr()?.ip();
+
+        if !self.is_trusted(&peer) {
+            return Some(peer.to_string());
+        }
+
+        let mut hops = forwarded_for(req);
+
+        if hops.is_empty() {
+            hops = header_values(req, &X_FORWARDED_FOR)
+                .flat_map(|value| value.split(','))
+                .map(|hop| hop.trim().to_string())
+                .filter(|hop| !hop.is_empty())
+                .collect();
+        }
+
+        // The leftmost entry is used if every hop is a trusted proxy
+        let client = hops
+            .iter()
+            .rev()
+            .find(|hop| !parse_hop(hop).is_some_and(|ip| self.is_trusted(&ip)))
+            .or(hops.first());
+
+        match client {
+            Some(hop) => Some(parse_hop(hop).map_or_else(|| hop.clone(), |ip| ip.to_string())),
+            None => Some(peer.to_string()),
+        }
+    }
+}
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Returns the values of every header with the given name that are valid strings
+fn header_values<'a>(req: &'a HttpRequest, name: &HeaderName) -> impl Iterator<Item = &'a str> {
+    req.headers()
+        .get_all(name)
+        .filter_map(|value| value.to_str().ok())
+}
+
+/// Returns the `for` parameters of the `Forwarded` headers from left to right
+fn forwarded_for(req: &HttpRequest) -> Vec<String> {
+    header_values(req, &header::FORWARDED)
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .collect()
+}
+
+/// Parses the address of a forwarded hop, which can be quoted and carry a port, e.g.
+/// `192.0.2.60:4711` or `[2001:db8::17]:4711`
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+
+    hop.rsplit_once(':')?.0.parse().ok()
+}
@@ -1,10 +1,20 @@
-use std::result;
+use std::{fmt, result};
 
-use actix_web::{body::BoxBody, http::StatusCode, HttpResponse, Responder};
-use serde::{ser::SerializeMap, Deserialize, Serialize};
+use actix_web::{
+    body::BoxBody,
+    error::InternalError,
+    http::{header, StatusCode},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Responder, ResponseError,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    problem::{accepts_problem_json, ProblemDetails, PROBLEM_JSON},
+    request_id::RequestId,
+};
 
 /// A custom error type for this service.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ServiceError {
     /// The requested resource was not found. Custom 404 response to return a JSON error body
     NotFound,
@@ -18,12 +28,92 @@ pub enum ServiceError {
     DatabaseError(String),
     /// Attempted to login with an invalid password.
     InvalidPassword,
+    /// Attempted to login with an unknown username or an invalid password. Used instead of
+    /// `UserNotFound` and `InvalidPassword` when login errors must not reveal which users exist.
+    InvalidCredentials,
+    /// Too many failed login attempts were made. Holds the number of seconds until the next
+    /// attempt is allowed.
+    TooManyAttempts(u64),
+    /// A service this one depends on could not be reached or returned an unexpected response.
+    /// Holds a description of the failure.
+    ServiceUnavailable(String),
+    /// The client sent too many requests. Holds the number of seconds until the next request is
+    /// allowed.
+    RateLimited(u64),
     /// Failed to verify the user's session token.
     AuthenticationError,
     /// Failed to authorize the user's request.
     AuthorizationError,
     /// Authorization header is missing
     AuthorizationHeaderError,
+    /// The username is not allowed. Holds the reason it was rejected.
+    InvalidUsername(String),
+    /// The password reset token is unknown, expired or was already used.
+    InvalidResetToken,
+    /// The user has no session with the given id.
+    SessionNotFound(String),
+    /// Failed to deliver a notification, such as a password reset token, to the user.
+    NotificationError(String),
+    /// The second factor code or recovery code is wrong, or the login challenge is unknown or
+    /// expired.
+    InvalidSecondFactor,
+    /// Attempted to enroll a second factor while one is already enabled.
+    SecondFactorAlreadyEnabled,
+    /// Attempted to confirm or use a second factor that was never enrolled.
+    SecondFactorNotEnrolled,
+    /// The password does not satisfy the password policy. Lists every rule that was violated.
+    PasswordPolicyViolation(Vec<PolicyViolation>),
+    /// The request body was well-formed but some of its fields are invalid. Lists every invalid
+    /// field.
+    ValidationError(Vec<FieldError>),
+    /// An error of a type this service doesn't know, e.g. returned by a newer version of another
+    /// service. Keeps the type, message and status code it was received with.
+    Unknown {
+        kind: String,
+        message: String,
+        status: u16,
+    },
+}
+
+/// A single invalid field of a request body
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Name of the field as it appears in the request body, e.g. `username`
+    pub field: String,
+    /// A stable identifier for the problem, e.g. `required`
+    pub code: String,
+    /// A human readable description of the problem
+    pub message: String,
+}
+
+impl FieldError {
+    /// Creates a new FieldError from a field name, a problem identifier and a message
+    pub fn new(field: &str, code: &str, message: String) -> Self {
+        FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        }
+    }
+}
+
+/// A single password policy rule that a password failed to satisfy
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyViolation {
+    /// A stable identifier for the rule, e.g. `min_length`
+    pub rule: String,
+    /// A human readable description of the requirement
+    pub message: String,
+}
+
+impl PolicyViolation {
+    /// Creates a new PolicyViolation from a rule identifier and a message
+    pub fn new(rule: &str, message: String) -> Self {
+        PolicyViolation {
+            rule: rule.to_string(),
+            message,
+        }
+    }
 }
 
 impl ServiceError {
@@ -36,9 +126,25 @@ impl ServiceError {
             ServiceError::UsernameTaken(_) => StatusCode::CONFLICT,
             ServiceError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceError::InvalidPassword => StatusCode::UNAUTHORIZED,
+            ServiceError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ServiceError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             ServiceError::AuthenticationError => StatusCode::UNAUTHORIZED,
             ServiceError::AuthorizationHeaderError => StatusCode::BAD_REQUEST,
             ServiceError::AuthorizationError => StatusCode::FORBIDDEN,
+            ServiceError::InvalidUsername(_) => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidResetToken => StatusCode::BAD_REQUEST,
+            ServiceError::SessionNotFound(_) => StatusCode::NOT_FOUND,
+            ServiceError::NotificationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceError::InvalidSecondFactor => StatusCode::UNAUTHORIZED,
+            ServiceError::SecondFactorAlreadyEnabled => StatusCode::CONFLICT,
+            ServiceError::SecondFactorNotEnrolled => StatusCode::BAD_REQUEST,
+            ServiceError::PasswordPolicyViolation(_) => StatusCode::BAD_REQUEST,
+            ServiceError::ValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServiceError::Unknown { status, .. } => {
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+            }
         }
     }
 
@@ -51,11 +157,52 @@ impl ServiceError {
             ServiceError::UsernameTaken(username) => format!("Username '{}' is taken", username),
             ServiceError::DatabaseError(error_str) => error_str.to_owned(),
             ServiceError::InvalidPassword => "Invalid password".to_string(),
+            ServiceError::InvalidCredentials => "Invalid username or password".to_string(),
+            ServiceError::TooManyAttempts(retry_after) => format!(
+                "Too many failed login attempts, try again in {} seconds",
+                retry_after
+            ),
+            ServiceError::ServiceUnavailable(error_str) => error_str.to_owned(),
+            ServiceError::RateLimited(retry_after) => {
+                format!("Too many requests, try again in {} seconds", retry_after)
+            }
             ServiceError::AuthenticationError => "Failed to authenticate user".to_string(),
             ServiceError::AuthorizationHeaderError => {
                 "Authorization `Bearer` header is missing or malformed".to_string()
             }
             ServiceError::AuthorizationError => "Failed to authorize user".to_string(),
+            ServiceError::InvalidUsername(reason) => reason.to_owned(),
+            ServiceError::InvalidResetToken => {
+                "Password reset token is invalid or has expired".to_string()
+            }
+            ServiceError::SessionNotFound(id) => format!("Session '{}' does not exist", id),
+            ServiceError::NotificationError(error_str) => error_str.to_owned(),
+            ServiceError::InvalidSecondFactor => {
+                "Second factor code is invalid or the login has expired".to_string()
+            }
+            ServiceError::SecondFactorAlreadyEnabled => {
+                "Two-factor authentication is already enabled".to_string()
+            }
+            ServiceError::SecondFactorNotEnrolled => {
+                "Two-factor authentication has not been set up".to_string()
+            }
+            ServiceError::PasswordPolicyViolation(violations) => format!(
+                "Password does not meet the requirements: {}",
+                violations
+                    .iter()
+                    .map(|violation| violation.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            ServiceError::ValidationError(fields) => format!(
+                "Invalid request: {}",
+                fields
+                    .iter()
+                    .map(|field| format!("{}: {}", field.field, field.message))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            ServiceError::Unknown { message, .. } => message.to_owned(),
         }
     }
 
@@ -68,11 +215,40 @@ impl ServiceError {
             ServiceError::UsernameTaken(_) => "UsernameTaken".to_string(),
             ServiceError::DatabaseError(_) => "DatabaseError".to_string(),
             ServiceError::InvalidPassword => "InvalidPassword".to_string(),
+            ServiceError::InvalidCredentials => "InvalidCredentials".to_string(),
+            ServiceError::TooManyAttempts(_) => "TooManyAttempts".to_string(),
+            ServiceError::RateLimited(_) => "RateLimited".to_string(),
+            ServiceError::ServiceUnavailable(_) => "ServiceUnavailable".to_string(),
             ServiceError::AuthenticationError => "AuthenticationError".to_string(),
             ServiceError::AuthorizationHeaderError => "AuthorizationHeaderError".to_string(),
             ServiceError::AuthorizationError => "AuthorizationError".to_string(),
+            ServiceError::InvalidUsername(_) => "InvalidUsername".to_string(),
+            ServiceError::InvalidResetToken => "InvalidResetToken".to_string(),
+            ServiceError::SessionNotFound(_) => "SessionNotFound".to_string(),
+            ServiceError::NotificationError(_) => "NotificationError".to_string(),
+            ServiceError::InvalidSecondFactor => "InvalidSecondFactor".to_string(),
+            ServiceError::SecondFactorAlreadyEnabled => "SecondFactorAlreadyEnabled".to_string(),
+            ServiceError::SecondFactorNotEnrolled => "SecondFactorNotEnrolled".to_string(),
+            ServiceError::PasswordPolicyViolation(_) => "PasswordPolicyViolation".to_string(),
+            ServiceError::ValidationError(_) => "ValidationError".to_string(),
+            ServiceError::Unknown { kind, .. } => kind.to_owned(),
         }
     }
+
+    /// Get the number of seconds the client should wait before retrying, if any
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ServiceError::TooManyAttempts(retry_after) => Some(*retry_after),
+            ServiceError::RateLimited(retry_after) => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.error_message())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -80,34 +256,149 @@ pub struct ServiceErrorJSON {
     error: ServiceErrorInner,
 }
 
+impl ServiceErrorJSON {
+    /// Returns the id of the request that failed, to find it in the logs of the service
+    pub fn request_id(&self) -> Option<&str> {
+        self.error.request_id.as_deref()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct ServiceErrorInner {
     #[serde(rename = "type")]
     kind: String,
     message: String,
+    /// HTTP status code of the error, so errors of unknown types keep it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(default, skip_serializing_if = "ErrorDetails::is_empty")]
+    details: ErrorDetails,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    violations: Option<Vec<PolicyViolation>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fields: Option<Vec<FieldError>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    /// Id of the request that failed, only set when the error is sent in response to a request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// Structured values of an error that are only part of its human readable message otherwise
+#[derive(Default, Serialize, Deserialize)]
+struct ErrorDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
+}
+
+impl ErrorDetails {
+    fn is_empty(&self) -> bool {
+        self.username.is_none() && self.session_id.is_none()
+    }
 }
 
 impl ServiceErrorInner {
-    fn get_username(&self) -> String {
-        let start = self.message.find('\'').unwrap();
-        let end = self.message.rfind('\'').unwrap();
+    /// Returns the part of the message between the first and last single quote, for errors
+    /// serialized before they carried their details
+    fn quoted(&self) -> String {
+        match (self.message.find('\''), self.message.rfind('\'')) {
+            (Some(start), Some(end)) if start < end => self.message[start + 1..end].to_string(),
+            _ => self.message.clone(),
+        }
+    }
+
+    fn username(&mut self) -> String {
+        self.details
+            .username
+            .take()
+            .unwrap_or_else(|| self.quoted())
+    }
 
-        self.message[start + 1..end].to_string()
+    fn session_id(&mut self) -> String {
+        self.details
+            .session_id
+            .take()
+            .unwrap_or_else(|| self.quoted())
     }
 }
 
 impl From<ServiceErrorJSON> for ServiceError {
     fn from(error: ServiceErrorJSON) -> Self {
-        match error.error.kind.as_str() {
-            "UserNotFound" => ServiceError::UserNotFound(error.error.get_username()),
-            "JsonParsingError" => ServiceError::JsonParsingError(error.error.message),
+        let mut error = error.error;
+
+        match error.kind.as_str() {
+            "UserNotFound" => ServiceError::UserNotFound(error.username()),
+            "JsonParsingError" => ServiceError::JsonParsingError(error.message),
             "PageNotFound" => ServiceError::NotFound,
-            "UsernameTaken" => ServiceError::UsernameTaken(error.error.get_username()),
-            "DatabaseError" => ServiceError::DatabaseError(error.error.message),
+            "UsernameTaken" => ServiceError::UsernameTaken(error.username()),
+            "DatabaseError" => ServiceError::DatabaseError(error.message),
             "InvalidPassword" => ServiceError::InvalidPassword,
+            "InvalidCredentials" => ServiceError::InvalidCredentials,
+            "TooManyAttempts" => {
+                ServiceError::TooManyAttempts(error.retry_after.unwrap_or_default())
+            }
+            "ServiceUnavailable" => ServiceError::ServiceUnavailable(error.message),
+            "RateLimited" => ServiceError::RateLimited(error.retry_after.unwrap_or_default()),
             "AuthenticationError" => ServiceError::AuthenticationError,
             "AuthorizationHeaderError" => ServiceError::AuthorizationHeaderError,
-            _ => ServiceError::NotFound,
+            "AuthorizationError" => ServiceError::AuthorizationError,
+            "InvalidUsername" => ServiceError::InvalidUsername(error.message),
+            "InvalidResetToken" => ServiceError::InvalidResetToken,
+            "SessionNotFound" => ServiceError::SessionNotFound(error.session_id()),
+            "NotificationError" => ServiceError::NotificationError(error.message),
+            "InvalidSecondFactor" => ServiceError::InvalidSecondFactor,
+            "SecondFactorAlreadyEnabled" => ServiceError::SecondFactorAlreadyEnabled,
+            "SecondFactorNotEnrolled" => ServiceError::SecondFactorNotEnrolled,
+            "PasswordPolicyViolation" => {
+                ServiceError::PasswordPolicyViolation(error.violations.unwrap_or_default())
+            }
+            "ValidationError" => ServiceError::ValidationError(error.fields.unwrap_or_default()),
+            _ => ServiceError::Unknown {
+                status: error
+                    .status
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR.as_u16()),
+                kind: error.kind,
+                message: error.message,
+            },
+        }
+    }
+}
+
+impl From<&ServiceError> for ServiceErrorJSON {
+    fn from(error: &ServiceError) -> Self {
+        ServiceErrorJSON {
+            error: ServiceErrorInner {
+                kind: error.error_type(),
+                message: error.error_message(),
+                status: Some(match error {
+                    // Kept as received, even if it is not a valid status code
+                    ServiceError::Unknown { status, .. } => *status,
+                    _ => error.status_code().as_u16(),
+                }),
+                details: ErrorDetails {
+                    username: match error {
+                        ServiceError::UserNotFound(username)
+                        | ServiceError::UsernameTaken(username) => Some(username.clone()),
+                        _ => None,
+                    },
+                    session_id: match error {
+                        ServiceError::SessionNotFound(id) => Some(id.clone()),
+                        _ => None,
+                    },
+                },
+                violations: match error {
+                    ServiceError::PasswordPolicyViolation(violations) => Some(violations.clone()),
+                    _ => None,
+                },
+                fields: match error {
+                    ServiceError::ValidationError(fields) => Some(fields.clone()),
+                    _ => None,
+                },
+                retry_after: error.retry_after(),
+                request_id: RequestId::current().map(|id| id.to_string()),
+            },
         }
     }
 }
@@ -119,27 +410,35 @@ impl From<ServiceErrorJSON> for ServiceError {
 /// {
 ///     "error": {
 ///         "type": <TYPE>,
-///         "message": <MESSAGE>
+///         "message": <MESSAGE>,
+///         "status": <STATUS CODE>,
+///         "details": { "username": <USERNAME>, "session_id": <ID> }, // only the values the error holds
+///         "violations": [{ "rule": <RULE>, "message": <MESSAGE> }], // only for PasswordPolicyViolation
+///         "fields": [{ "field": <FIELD>, "code": <CODE>, "message": <MESSAGE> }], // only for ValidationError
+///         "retry_after": <SECONDS>, // only for TooManyAttempts and RateLimited
+///         "request_id": <ID> // only while handling a request
 ///     }
 /// }
 ///
 /// ```
+///
+/// Deserializing it again gives back the same error, errors of unknown types become
+/// `ServiceError::Unknown`.
 impl Serialize for ServiceError {
     fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut map = serializer.serialize_map(Some(1))?;
-
-        map.serialize_entry(
-            "error",
-            &ServiceErrorInner {
-                kind: self.error_type(),
-                message: self.error_message(),
-            },
-        )?;
+        ServiceErrorJSON::from(self).serialize(serializer)
+    }
+}
 
-        map.end()
+impl<'de> Deserialize<'de> for ServiceError {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        ServiceErrorJSON::deserialize(deserializer).map(ServiceError::from)
     }
 }
 
@@ -172,10 +471,61 @@ where
 {
     type Body = BoxBody;
 
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
         match self {
             Response::Ok(e) => HttpResponse::Ok().json(e),
-            Response::Err(e) => HttpResponse::build(e.status_code()).json(e),
+            Response::Err(e) => e.response_for(req),
         }
     }
 }
+
+impl ServiceError {
+    /// Builds the response for this error in the format the request accepts: RFC 7807 problem
+    /// details if it prefers `application/problem+json`, the default JSON body otherwise
+    pub fn response_for(&self, req: &HttpRequest) -> HttpResponse {
+        if accepts_problem_json(req) {
+            self.problem_response(Some(req.path()))
+        } else {
+            self.error_response()
+        }
+    }
+
+    /// Builds a response with this error as RFC 7807 problem details, see [`ProblemDetails`]
+    pub fn problem_response(&self, instance: Option<&str>) -> HttpResponse {
+        let body = ProblemDetails::new(self, instance);
+
+        self.response_builder()
+            .content_type(PROBLEM_JSON)
+            .body(serde_json::to_string(&body).unwrap_or_default())
+    }
+
+    /// Converts the error into an actix error responding in the format the request accepts, for
+    /// extractors and handlers that can't return a [`Response`]
+    pub fn into_actix_error(self, req: &HttpRequest) -> actix_web::Error {
+        let response = self.response_for(req);
+
+        InternalError::from_response(self, response).into()
+    }
+
+    fn response_builder(&self) -> HttpResponseBuilder {
+        let mut response = HttpResponse::build(ServiceError::status_code(self));
+
+        if let Some(retry_after) = self.retry_after() {
+            response.insert_header((header::RETRY_AFTER, retry_after));
+        }
+
+        response
+    }
+}
+
+/// Lets a ServiceError be returned from extractors and middleware. Always responds with the default
+/// JSON body since the request isn't known, see [`ServiceError::into_actix_error`].
+impl ResponseError for ServiceError {
+    fn status_code(&self) -> StatusCode {
+        ServiceError::status_code(self)
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        self.response_builder().json(self)
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{rt::time::timeout, web, HttpResponse};
+use async_trait::async_trait;
+use mongodb::{bson::doc, Client};
+use serde::{Deserialize, Serialize};
+
+/// Path of the liveness endpoint, answering as long as the process serves requests
+pub const HEALTH_PATH: &str = "/healthz";
+
+/// Path of the readiness endpoint, answering once every dependency is available
+pub const READY_PATH: &str = "/readyz";
+
+/// How long a single check can take by default before it counts as failed
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a [`BackgroundSetup`] waits before trying again by default
+pub const DEFAULT_SETUP_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A dependency a service needs to handle requests, like its database or another service
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Checks whether the dependency is available
+    ///
+    /// # Errors
+    /// Describes why the dependency is unavailable
+    async fn check(&self) -> Result<(), String>;
+}
+
+/// Whether a service or one of its dependencies is available
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// The outcome of a single check
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub status: HealthStatus,
+    /// How long the check took in milliseconds
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of every check of a service, which is up if every check succeeded.
+///
+/// Serialized as
+/// ```json
+/// {
+///     "status": "down",
+///     "checks": {
+///         "mongodb": { "status": "up", "duration_ms": 1 },
+///         "users": { "status": "down", "duration_ms": 2000, "error": "Timed out after 2s" }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+impl HealthReport {
+    /// Returns whether every check succeeded
+    pub fn is_up(&self) -> bool {
+        self.status == HealthStatus::Up
+    }
+
+    /// Returns the report with `200 OK` if the service is up and `503 Service Unavailable`
+    /// otherwise
+    pub fn response(&self) -> HttpResponse {
+        if self.is_up() {
+            HttpResponse::Ok().json(self)
+        } else {
+            HttpResponse::ServiceUnavailable().json(self)
+        }
+    }
+}
+
+/// Registry of the checks deciding whether a service is ready, see [`readyz`].
+///
+/// Checks run concurrently, each limited to a timeout. Clones share the same checks.
+#[derive(Clone)]
+pub struct HealthChecks {
+    checks: Vec<(String, Arc<dyn HealthCheck>)>,
+    timeout: Duration,
+}
+
+impl HealthChecks {
+    /// Creates a registry without any checks, which is always up
+    pub fn new() -> Self {
+        HealthChecks {
+            checks: Vec::new(),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Adds a check reported under the given name
+    pub fn with_check(mut self, name: &str, check: impl HealthCheck + 'static) -> Self {
+        self.checks.push((name.to_string(), Arc::new(check)));
+        self
+    }
+
+    /// Sets how long a single check can take before it counts as failed
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the names of the checks in the order they were added
+    pub fn names(&self) -> Vec<&str> {
+        self.checks.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Runs every check and reports their outcome
+    pub async fn run(&self) -> HealthReport {
+        let results = futures::future::join_all(
+            self.checks
+                .iter()
+                .map(|(name, check)| self.run_check(name, check.as_ref())),
+        )
+        .await;
+
+        let status = if results
+            .iter()
+            .all(|(_, result)| result.status == HealthStatus::Up)
+        {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        };
+
+        HealthReport {
+            status,
+            checks: results.into_iter().collect(),
+        }
+    }
+
+    async fn run_check(&self, name: &str, check: &dyn HealthCheck) -> (String, CheckResult) {
+        let started = Instant::now();
+
+        let error = match timeout(self.timeout, check.check()).await {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err),
+            Err(_) => Some(format!("Timed out after {:?}", self.timeout)),
+        };
+
+        if let Some(err) = &error {
+            log::warn!("Health check {} failed: {}", name, err);
+        }
+
+        let result = CheckResult {
+            status: if error.is_none() {
+                HealthStatus::Up
+            } else {
+                HealthStatus::Down
+            },
+            duration_ms: started.elapsed().as_millis() as u64,
+            error,
+        };
+
+        (name.to_string(), result)
+    }
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the setup of a dependency in the background, such as creating the indexes of a
+/// database, retrying it until it succeeds.
+///
+/// Lets a service start while the dependency is unavailable and report it as not ready instead
+/// of failing at startup. As a check it fails with the last error until the setup succeeded.
+#[derive(Clone, Debug)]
+pub struct BackgroundSetup {
+    state: Arc<Mutex<Result<(), String>>>,
+}
+
+impl BackgroundSetup {
+    /// Spawns a task running `setup` until it succeeds, waiting `retry_interval` between tries.
+    /// Has to be called from within the actix runtime.
+    pub fn spawn<F, Fut>(name: &str, retry_interval: Duration, setup: F) -> Self
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + 'static,
+    {
+        let state = Arc::new(Mutex::new(Err("Not set up yet".to_string())));
+        let background = BackgroundSetup {
+            state: state.clone(),
+        };
+        let name = name.to_string();
+
+        actix_web::rt::spawn(async move {
+            loop {
+                match setup().await {
+                    Ok(()) => {
+                        log::info!("Set up {}", name);
+                        *state.lock().unwrap() = Ok(());
+
+                        return;
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to set up {}, retrying: {}", name, err);
+                        *state.lock().unwrap() = Err(format!("Not set up yet: {}", err));
+                    }
+                }
+
+                actix_web::rt::time::sleep(retry_interval).await;
+            }
+        });
+
+        background
+    }
+}
+
+#[async_trait]
+impl HealthCheck for BackgroundSetup {
+    async fn check(&self) -> Result<(), String> {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// Checks that MongoDB answers a `ping` command
+#[derive(Clone, Debug)]
+pub struct MongoPing {
+    client: Client,
+}
+
+impl MongoPing {
+    /// Creates a check pinging the deployment of the given client
+    pub fn new(client: Client) -> Self {
+        MongoPing { client }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for MongoPing {
+    async fn check(&self) -> Result<(), String> {
+        self.client
+            .database("admin")
+            .run_command(doc! { "ping": 1 }, None)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Checks that a url, like the liveness endpoint of another service, answers with a success
+/// status
+#[derive(Clone, Debug)]
+pub struct HttpCheck {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpCheck {
+    /// Creates a check requesting the given url
+    pub fn new(url: &str) -> Self {
+        HttpCheck {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+
+    /// Creates a check requesting the liveness endpoint of the service at the given base url.
+    ///
+    /// Only liveness is checked, so services depending on each other don't wait for each other
+    /// to become ready.
+    pub fn service(base_url: &str) -> Self {
+        Self::new(&format!(
+            "{}{}",
+            base_url.trim_end_matches('/'),
+            HEALTH_PATH
+        ))
+    }
+}
+
+#[async_trait]
+impl HealthCheck for HttpCheck {
+    async fn check(&self) -> Result<(), String> {
+        let response = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("{} answered {}", self.url, response.status()))
+        }
+    }
+}
+
+/// Liveness handler at [`HEALTH_PATH`], answering `200 OK` without checking any dependency
+pub async fn healthz() -> HttpResponse {
+    HealthChecks::new().run().await.response()
+}
+
+/// Readiness handler at [`READY_PATH`], running the checks of the app data
+pub async fn readyz(checks: web::Data<HealthChecks>) -> HttpResponse {
+    checks.run().await.response()
+}
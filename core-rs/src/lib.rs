@@ -1,30 +1,47 @@
-use actix_web::{web, HttpResponse};
+use actix_web::web;
 use error::ServiceError;
 use serde::{Deserialize, Serialize};
+use username::NormalizedUsername;
 
+pub mod auth_client;
+pub mod client_ip;
+pub mod config;
 pub mod error;
+pub mod health;
+pub mod metrics;
+pub mod problem;
+pub mod rate_limit;
+pub mod request_id;
+pub mod telemetry;
+pub mod token;
+pub mod token_cache;
+pub mod username;
+pub mod validation;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Username {
-    pub username: String,
+    pub username: NormalizedUsername,
 }
 
-impl From<String> for Username {
-    fn from(username: String) -> Self {
+impl From<NormalizedUsername> for Username {
+    fn from(username: NormalizedUsername) -> Self {
         Username { username }
     }
 }
 
-pub fn create_json_cfg() -> web::JsonConfig {
+/// Creates the JSON config of a service, accepting bodies up to `limit` bytes and answering
+/// malformed ones with `ServiceError::JsonParsingError`, see
+/// [`ServerConfig::json_limit`](config::ServerConfig::json_limit)
+pub fn create_json_cfg(limit: usize) -> web::JsonConfig {
     web::JsonConfig::default()
-        .limit(4096)
+        .limit(limit)
         .content_type(|mime| mime == mime::TEXT_PLAIN || mime == mime::APPLICATION_JSON)
-        .error_handler(|err, _req| {
+        .error_handler(|err, req| {
             let error_str = err.to_string();
 
             actix_web::error::InternalError::from_response(
                 err,
-                HttpResponse::BadRequest().json(ServiceError::JsonParsingError(error_str)),
+                ServiceError::JsonParsingError(error_str).response_for(req),
             )
             .into()
         })
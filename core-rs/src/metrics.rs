@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Future, Ready},
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use actix_web::{
+    dev::{forward_ready, Server, Service, ServiceRequest, ServiceResponse, Transform},
+    http::StatusCode,
+    web, App, Error, HttpResponse, HttpServer,
+};
+use mongodb::event::command::{
+    CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent,
+};
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+/// Path the metrics are served at
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Route label of requests not matching any route, so unknown paths don't create new series
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// The Prometheus metrics of a service.
+///
+/// Holds the HTTP request and MongoDB command metrics every service shares, recorded by
+/// [`RecordMetrics`] and [`Metrics::mongodb_event_handler`]. Services register their own metrics
+/// with [`Metrics::register`]. Clones share the same metrics.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    mongodb_command_duration: HistogramVec,
+}
+
+impl Metrics {
+    /// Creates the shared metrics in a new registry
+    pub fn new() -> Self {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "Number of handled HTTP requests"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "How long handling HTTP requests took",
+                )
+                .buckets(LATENCY_BUCKETS.to_vec()),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            mongodb_command_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "mongodb_command_duration_seconds",
+                    "How long MongoDB commands took, including the network round trip",
+                )
+                .buckets(LATENCY_BUCKETS.to_vec()),
+                &["command", "collection", "result"],
+            )
+            .unwrap(),
+        };
+
+        // The names are fixed and distinct, so registering them in a new registry can't fail
+        metrics
+            .register(metrics.http_requests.clone())
+            .and_then(|_| metrics.register(metrics.http_request_duration.clone()))
+            .and_then(|_| metrics.register(metrics.mongodb_command_duration.clone()))
+            .unwrap();
+
+        metrics
+    }
+
+    /// Adds metrics of a service to the registry
+    ///
+    /// # Errors
+    /// Fails if a metric with the same name is already registered
+    pub fn register<C: Collector + 'static>(&self, collector: C) -> prometheus::Result<()> {
+        self.registry.register(Box::new(collector))
+    }
+
+    /// Records a handled HTTP request
+    pub fn observe_request(&self, method: &str, route: &str, status: StatusCode, seconds: f64) {
+        let status = status.as_str();
+
+        self.http_requests
+            .with_label_values(&[method, route, status])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route, status])
+            .observe(seconds);
+    }
+
+    /// Returns a handler for the MongoDB client options recording how long commands take
+    pub fn mongodb_event_handler(&self) -> Arc<dyn CommandEventHandler> {
+        Arc::new(MongoMetrics {
+            duration: self.mongodb_command_duration.clone(),
+            collections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+
+        // Encoding into memory only fails for invalid metrics, which the registry rejects
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            log::error!("Failed to encode metrics: {}", err);
+        }
+
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Returns the response to a scrape of the metrics
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(TextEncoder::new().format_type())
+            .body(self.render())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handler serving the metrics of the app data at [`METRICS_PATH`]
+pub async fn metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    metrics.response()
+}
+
+/// Starts a server serving nothing but the metrics at [`METRICS_PATH`], so they can be kept off
+/// the public address of the service. Runs until the returned server is stopped or dropped.
+///
+/// # Errors
+/// Fails if the address can't be bound
+pub fn serve(metrics: Metrics, address: SocketAddr) -> io::Result<Server> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(metrics.clone()))
+            .route(METRICS_PATH, web::get().to(self::metrics))
+    })
+    .workers(1)
+    .bind(address)?
+    .run();
+
+    Ok(server)
+}
+
+/// Records the duration of MongoDB commands, remembering the collection of every started
+/// command until it completes
+struct MongoMetrics {
+    duration: HistogramVec,
+    collections: Mutex<HashMap<i32, String>>,
+}
+
+impl MongoMetrics {
+    fn observe(&self, request_id: i32, command: &str, result: &str, seconds: f64) {
+        let collection = self
+            .collections
+            .lock()
+            .unwrap()
+            .remove(&request_id)
+            .unwrap_or_default();
+
+        self.duration
+            .with_label_values(&[command, &collection, result])
+            .observe(seconds);
+    }
+}
+
+impl CommandEventHandler for MongoMetrics {
+    fn handle_command_started_event(&self, event: CommandStartedEvent) {
+        // CRUD commands name their collection in their first field, e.g. `{ find: "users" }`
+        let collection = event
+            .command
+            .get_str(&event.command_name)
+            .unwrap_or_default()
+            .to_string();
+
+        self.collections
+            .lock()
+            .unwrap()
+            .insert(event.request_id, collection);
+    }
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.observe(
+            event.request_id,
+            &event.command_name,
+            "success",
+            event.duration.as_secs_f64(),
+        );
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        self.observe(
+            event.request_id,
+            &event.command_name,
+            "failure",
+            event.duration.as_secs_f64(),
+        );
+    }
+}
+
+/// Middleware counting requests and timing them per method, route and status.
+///
+/// Routes are the patterns like `/{username}/info`, so the number of series doesn't grow with
+/// the number of users. Should wrap the rate limiter so rejected requests are counted too.
+#[derive(Clone, Debug)]
+pub struct RecordMetrics {
+    metrics: Metrics,
+}
+
+impl RecordMetrics {
+    /// Creates a middleware recording requests into the given metrics
+    pub fn new(metrics: Metrics) -> Self {
+        RecordMetrics { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RecordMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RecordMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordMetricsMiddleware {
+            service: Rc::new(service),
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+/// The service created by [`RecordMetrics`] for every worker
+pub struct RecordMetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let metrics = self.metrics.clone();
+        let method = req.method().to_string();
+        let started = Instant::now();
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+            let seconds = started.elapsed().as_secs_f64();
+
+            match &result {
+                Ok(response) => {
+                    let route = response
+                        .request()
+                        .match_pattern()
+                        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+                    metrics.observe_request(&method, &route, response.status(), seconds);
+                }
+                Err(err) => {
+                    // The request never reached a handler, so it has no route
+                    let status = err.as_response_error().status_code();
+
+                    metrics.observe_request(&method, UNMATCHED_ROUTE, status, seconds);
+                }
+            }
+
+            result
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use actix_web::{
+    http::header::{self, Header, Quality},
+    HttpRequest,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{FieldError, PolicyViolation, ServiceError},
+    request_id::RequestId,
+};
+
+/// Media type of RFC 7807 problem details
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Prefix of the problem type URIs, followed by the error type, e.g. `urn:dirc:error:UserNotFound`
+pub const PROBLEM_TYPE_PREFIX: &str = "urn:dirc:error:";
+
+/// A [`ServiceError`] in the RFC 7807 problem details format, sent instead of the default error
+/// body to clients accepting `application/problem+json`.
+///
+/// The structured values of the error are added as extension members.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    /// URI identifying the error type, see [`PROBLEM_TYPE_PREFIX`]
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the error type, the same for every occurrence
+    pub title: String,
+    pub status: u16,
+    /// Explanation of this occurrence of the error
+    pub detail: String,
+    /// Path of the request that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub violations: Option<Vec<PolicyViolation>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldError>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// Id of the request that failed, see [`RequestId`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ProblemDetails {
+    /// Creates the problem details of an error that occurred handling a request to `instance`
+    pub fn new(error: &ServiceError, instance: Option<&str>) -> Self {
+        let kind = error.error_type();
+
+        ProblemDetails {
+            problem_type: format!("{}{}", PROBLEM_TYPE_PREFIX, kind),
+            title: title(&kind),
+            status: error.status_code().as_u16(),
+            detail: error.error_message(),
+            instance: instance.map(str::to_string),
+            username: match error {
+                ServiceError::UserNotFound(username) | ServiceError::UsernameTaken(username) => {
+                    Some(username.clone())
+                }
+                _ => None,
+            },
+            session_id: match error {
+                ServiceError::SessionNotFound(id) => Some(id.clone()),
+                _ => None,
+            },
+            violations: match error {
+                ServiceError::PasswordPolicyViolation(violations) => Some(violations.clone()),
+                _ => None,
+            },
+            fields: match error {
+                ServiceError::ValidationError(fields) => Some(fields.clone()),
+                _ => None,
+            },
+            retry_after: error.retry_after(),
+            request_id: RequestId::current().map(|id| id.to_string()),
+        }
+    }
+}
+
+/// Turns an error type like `UserNotFound` into a title like `User not found`
+fn title(kind: &str) -> String {
+    let mut title = String::with_capacity(kind.len() + 4);
+
+    for (i, c) in kind.chars().enumerate() {
+        if i == 0 {
+            title.push(c);
+        } else if c.is_uppercase() {
+            title.push(' ');
+            title.extend(c.to_lowercase());
+        } else {
+            title.push(c);
+        }
+    }
+
+    title
+}
+
+/// Returns whether a request prefers `application/problem+json` over `application/json` in its
+/// `Accept` header. Requests without a preference get the default error format.
+pub fn accepts_problem_json(req: &HttpRequest) -> bool {
+    let Ok(accept) = header::Accept::parse(req) else {
+        return false;
+    };
+
+    let quality = |essence: &str| {
+        accept
+            .iter()
+            .filter(|item| item.item.essence_str() == essence)
+            .map(|item| item.quality)
+            .max()
+            .unwrap_or(Quality::ZERO)
+    };
+
+    let problem = quality(PROBLEM_JSON);
+
+    problem > Quality::ZERO && problem >= quality(mime::APPLICATION_JSON.essence_str())
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::error::ServiceError;
+
+use super::{RateLimit, RateLimitStore};
+
+/// Number of buckets above which full buckets are dropped, since they are the same as missing
+/// ones
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// How often buckets are pruned at most, so requests don't each pay for scanning every bucket
+/// while most of them are still in use
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keeps rate limit buckets in process memory.
+///
+/// Limits are not shared between instances of a service, so this is only suitable when a single
+/// instance is running.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRateLimitStore {
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    last_pruned: Option<Instant>,
+}
+
+impl Buckets {
+    /// Drops the full buckets if there are too many and they were not pruned recently
+    fn prune(&mut self, now: Instant) {
+        let pruned_recently = self
+            .last_pruned
+            .is_some_and(|last_pruned| now.duration_since(last_pruned) < PRUNE_INTERVAL);
+
+        if self.buckets.len() <= PRUNE_THRESHOLD || pruned_recently {
+            return;
+        }
+
+        self.buckets
+            .retain(|_, bucket| bucket.tokens_at(now) < bucket.burst);
+        self.last_pruned = Some(now);
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    burst: f64,
+    refill_rate: f64,
+}
+
+impl Bucket {
+    /// Returns the tokens in the bucket at the given time
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        (self.tokens + elapsed * self.refill_rate).min(self.burst)
+    }
+}
+
+impl MemoryRateLimitStore {
+    /// Creates a new empty MemoryRateLimitStore
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<Option<Duration>, ServiceError> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+
+        buckets.prune(now);
+
+        let bucket = buckets
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: limit.burst as f64,
+                updated: now,
+                burst: limit.burst as f64,
+                refill_rate: limit.refill_rate(),
+            });
+
+        bucket.tokens = bucket.tokens_at(now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / bucket.refill_rate,
+            )))
+        }
+    }
+}
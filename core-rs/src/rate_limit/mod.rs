@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    Error, Responder,
+};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client_ip::TrustedProxies,
+    config::RateLimitsConfig,
+    error::{Response, ServiceError},
+};
+
+pub mod memory;
+pub mod mongo;
+
+pub use memory::MemoryRateLimitStore;
+pub use mongo::MongoRateLimitStore;
+
+/// What requests share a rate limit bucket
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// One bucket per client IP address
+    #[default]
+    Ip,
+    /// One bucket per bearer token, falling back to the client IP address for requests without
+    /// one
+    BearerToken,
+    /// One bucket shared by every client
+    Route,
+}
+
+/// A token bucket limit: up to `burst` requests at once, refilled evenly over `period`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// How many requests can be made at once with a full bucket
+    burst: u32,
+    /// How long it takes an empty bucket to fill up again
+    period: Duration,
+    /// What requests share a bucket
+    key: RateLimitKey,
+}
+
+impl RateLimit {
+    /// Creates a new RateLimit allowing `burst` requests per `period`, keyed by client IP
+    ///
+    /// # Panics
+    /// If `burst` or `period` is zero, since a bucket that never holds or never regains a token
+    /// can't tell when the next request is allowed
+    pub fn new(burst: u32, period: Duration) -> Self {
+        assert!(burst > 0, "The burst of a rate limit must be larger than 0");
+        assert!(
+            !period.is_zero(),
+            "The period of a rate limit must be longer than 0"
+        );
+
+        RateLimit {
+            burst,
+            period,
+            key: RateLimitKey::Ip,
+        }
+    }
+
+    /// Creates a new RateLimit allowing `burst` requests per second, keyed by client IP
+    pub fn per_second(burst: u32) -> Self {
+        RateLimit::new(burst, Duration::from_secs(1))
+    }
+
+    /// Creates a new RateLimit allowing `burst` requests per minute, keyed by client IP
+    pub fn per_minute(burst: u32) -> Self {
+        RateLimit::new(burst, Duration::from_secs(60))
+    }
+
+    /// Sets what requests share a bucket
+    pub fn keyed_by(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Returns how many requests can be made at once with a full bucket
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// Returns how long it takes an empty bucket to fill up again
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns how many requests are added back to a bucket per second
+    pub fn refill_rate(&self) -> f64 {
+        self.burst as f64 / self.period.as_secs_f64()
+    }
+}
+
+/// Returns a limit of the given requests per minute, keyed like the given limit if any
+fn with_per_minute(limit: Option<&RateLimit>, per_minute: u32) -> RateLimit {
+    let key = limit.map_or(RateLimitKey::default(), |limit| limit.key);
+
+    RateLimit::per_minute(per_minute).keyed_by(key)
+}
+
+/// Shared state of the rate limit buckets
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket with the given key, creating a full bucket if it doesn't
+    /// exist. Returns how long until a token is available if the bucket is empty.
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn acquire(&self, key: &str, limit: &RateLimit)
+        -> Result<Option<Duration>, ServiceError>;
+}
+
+#[async_trait]
+impl<T: RateLimitStore + ?Sized> RateLimitStore for Arc<T> {
+    async fn acquire(
+        &self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<Option<Duration>, ServiceError> {
+        (**self).acquire(key, limit).await
+    }
+}
+
+/// Middleware rejecting requests over their rate limit with `ServiceError::RateLimited`.
+///
+/// Limits are configured per route pattern as registered with actix, e.g. `/{username}/exists`.
+/// Requests to routes without a limit of their own use the default limit, if any, with separate
+/// buckets for every route.
+///
+/// The client IP address only honours the `Forwarded` and `X-Forwarded-For` headers for requests
+/// from one of the trusted proxies, see [`RateLimiter::trusted_proxies`]. If the store fails,
+/// requests are let through.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    routes: Arc<HashMap<String, Option<RateLimit>>>,
+    default_limit: Option<RateLimit>,
+    trusted_proxies: TrustedProxies,
+}
+
+impl RateLimiter {
+    /// Creates a new RateLimiter without any limits, keeping its buckets in the given store
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter {
+            store,
+            routes: Arc::new(HashMap::new()),
+            default_limit: None,
+            trusted_proxies: TrustedProxies::default(),
+        }
+    }
+
+    /// Sets the limit of the route with the given pattern
+    pub fn route(mut self, pattern: &str, limit: RateLimit) -> Self {
+        Arc::make_mut(&mut self.routes).insert(pattern.to_string(), Some(limit));
+        self
+    }
+
+    /// Excludes the route with the given pattern from every limit, including the default one,
+    /// e.g. for health checks polled by the orchestrator
+    pub fn exempt(mut self, pattern: &str) -> Self {
+        Arc::make_mut(&mut self.routes).insert(pattern.to_string(), None);
+        self
+    }
+
+    /// Sets the limit of every route without a limit of its own
+    pub fn default_limit(mut self, limit: RateLimit) -> Self {
+        self.default_limit = Some(limit);
+        self
+    }
+
+    /// Applies the limits of the configuration on top of the ones set so far. Overridden limits
+    /// keep what requests share a bucket, exempt routes become limited by client IP.
+    pub fn with_config(mut self, config: &RateLimitsConfig) -> Self {
+        if let Some(per_minute) = config.default {
+            self.default_limit = Some(with_per_minute(self.default_limit.as_ref(), per_minute));
+        }
+
+        for (pattern, &per_minute) in &config.routes {
+            let limit = self.routes.get(pattern).and_then(Option::as_ref);
+            let limit = with_per_minute(limit, per_minute);
+
+            self = self.route(pattern, limit);
+        }
+
+        self
+    }
+
+    /// Sets the proxies allowed to set the client IP address requests are limited by
+    pub fn trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Returns the limit of a request together with the key of its bucket, if it is limited
+    fn bucket(&self, req: &ServiceRequest) -> Option<(String, &RateLimit)> {
+        // Requests not matching any route share a bucket, so varying the path doesn't help
+        let pattern = req.match_pattern();
+        let route = pattern.as_deref().unwrap_or("*");
+        let limit = match self.routes.get(route) {
+            Some(limit) => limit.as_ref()?,
+            None => self.default_limit.as_ref()?,
+        };
+
+        let client = match limit.key {
+            RateLimitKey::Ip => self.client_ip(req),
+            RateLimitKey::BearerToken => bearer_token(req)
+                .map(|token| format!("token:{}", hex::encode(Sha256::digest(token))))
+                .unwrap_or_else(|| self.client_ip(req)),
+            RateLimitKey::Route => "*".to_string(),
+        };
+
+        Some((format!("{} {}", route, client), limit))
+    }
+
+    fn client_ip(&self, req: &ServiceRequest) -> String {
+        format!(
+            "ip:{}",
+            self.trusted_proxies
+                .client_ip(req.request())
+                .as_deref()
+                .unwrap_or("unknown")
+        )
+    }
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+/// The service created by [`RateLimiter`] for every worker
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            if let Some((key, limit)) = limiter.bucket(&req) {
+                match limiter.store.acquire(&key, limit).await {
+                    Ok(Some(retry_after)) => {
+                        let error =
+                            ServiceError::RateLimited(retry_after.as_secs_f64().ceil() as u64);
+                        let response = Response::<()>::Err(error).respond_to(req.request());
+
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                    Ok(None) => {}
+                    Err(err) => log::warn!("Failed to check rate limit of {}: {}", key, err),
+                }
+            }
+
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    Collection, Database, IndexModel,
+};
+use serde::Deserialize;
+
+use crate::error::ServiceError;
+
+use super::{RateLimit, RateLimitStore};
+
+/// Keeps rate limit buckets in a MongoDB collection, so limits are shared between every instance
+/// of a service using the same database.
+///
+/// Buckets are removed by a TTL index once they would have filled up again.
+#[derive(Clone, Debug)]
+pub struct MongoRateLimitStore {
+    buckets: Collection<StoredBucket>,
+}
+
+/// The fields of a bucket read back after taking a token
+#[derive(Debug, Deserialize)]
+struct StoredBucket {
+    tokens: f64,
+    allowed: bool,
+}
+
+impl MongoRateLimitStore {
+    /// Creates a new MongoRateLimitStore using the `rate_limits` collection of a database
+    ///
+    /// # Errors
+    /// Construction will fail if a database error occurs
+    pub async fn new(database: &Database) -> anyhow::Result<Self> {
+        let store = Self::with_database(database);
+
+        store.setup().await?;
+
+        Ok(store)
+    }
+
+    /// Creates a new MongoRateLimitStore like [`MongoRateLimitStore::new`] without sending
+    /// anything to MongoDB, so it succeeds while it is unavailable. The store has to be set up
+    /// with [`MongoRateLimitStore::setup`] once it is.
+    pub fn with_database(database: &Database) -> Self {
+        Self {
+            buckets: database.collection::<StoredBucket>("rate_limits"),
+        }
+    }
+
+    /// Creates the indexes of the collection. Can be retried if it fails.
+    ///
+    /// # Errors
+    /// Fails if a database error occurs
+    pub async fn setup(&self) -> anyhow::Result<()> {
+        let key_options = IndexOptions::builder().unique(true).build();
+        let key_model = IndexModel::builder()
+            .keys(doc! {"key": 1})
+            .options(key_options)
+            .build();
+
+        let expiry_options = IndexOptions::builder()
+            .expire_after(Duration::from_secs(0))
+            .build();
+        let expiry_model = IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(expiry_options)
+            .build();
+
+        self.buckets
+            .create_indexes([key_model, expiry_model], None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MongoRateLimitStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        limit: &RateLimit,
+    ) -> Result<Option<Duration>, ServiceError> {
+        let now = DateTime::now();
+        let expires_at =
+            DateTime::from_millis(now.timestamp_millis() + limit.period.as_millis() as i64);
+        let burst = limit.burst as f64;
+        let refill_rate = limit.refill_rate();
+
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        // Refills the bucket for the time since it was last updated, then takes a token if there
+        // is one. Both happen in one update so concurrent requests can't take the same token.
+        let bucket = self
+            .buckets
+            .find_one_and_update(
+                doc! { "key": key },
+                vec![
+                    doc! { "$set": {
+                        "tokens": { "$min": [burst, { "$add": [
+                            { "$ifNull": ["$tokens", burst] },
+                            { "$multiply": [
+                                { "$divide": [
+                                    { "$subtract": [now, { "$ifNull": ["$updated_at", now] }] },
+                                    1000,
+                                ] },
+                                refill_rate,
+                            ] },
+                        ] }] },
+                        "updated_at": now,
+                        "expires_at": expires_at,
+                    } },
+                    doc! { "$set": {
+                        "allowed": { "$gte": ["$tokens", 1] },
+                        "tokens": { "$cond": [
+                            { "$gte": ["$tokens", 1] },
+                            { "$subtract": ["$tokens", 1] },
+                            "$tokens",
+                        ] },
+                    } },
+                ],
+                options,
+            )
+            .await?
+            .ok_or_else(|| {
+                ServiceError::DatabaseError("Failed to update a rate limit bucket".to_string())
+            })?;
+
+        if bucket.allowed {
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / refill_rate,
+            )))
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fmt,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Logger,
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use rand::Rng;
+
+/// Header carrying the id of a request, both on incoming requests and responses and on requests
+/// to other services
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Maximum length of a request id accepted from a client
+pub const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// The id of the request being handled, used to correlate log lines and errors across services.
+///
+/// Can be taken as a handler argument, it is empty outside of [`AssignRequestId`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Generates a new random request id of 32 hex digits
+    pub fn generate() -> Self {
+        RequestId(hex::encode(rand::thread_rng().gen::<[u8; 16]>()))
+    }
+
+    /// Accepts a request id sent by a client if it is short and only made of letters, digits,
+    /// `-`, `_` and `.`, so it is safe to log and to send on
+    pub fn parse(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_REQUEST_ID_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+        valid.then(|| RequestId(value.to_string()))
+    }
+
+    /// Returns the id of the request being handled by the current task, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(RequestId::clone).ok()
+    }
+
+    /// Returns the request id as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_default()))
+    }
+}
+
+/// Access log format of the services, see [`access_logger`]
+pub const ACCESS_LOG_FORMAT: &str =
+    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{request_id}xi"#;
+
+/// Creates the access logger of a service, logging the [`RequestId`] of every request.
+///
+/// Must be wrapped by [`AssignRequestId`], the response header is not set yet when the line is
+/// written.
+pub fn access_logger() -> Logger {
+    Logger::new(ACCESS_LOG_FORMAT).custom_request_replace("request_id", |req| {
+        req.extensions()
+            .get::<RequestId>()
+            .map_or_else(|| "-".to_string(), RequestId::to_string)
+    })
+}
+
+/// Middleware giving every request a [`RequestId`].
+///
+/// The id is taken from the `X-Request-Id` header if the client sent a valid one and generated
+/// otherwise. It is returned in the `X-Request-Id` response header, added to every `ServiceError`
+/// body and sent on to other services by the auth and users clients. Should wrap every other
+/// middleware so their errors and log lines carry the id too.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AssignRequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for AssignRequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AssignRequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AssignRequestIdMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// The service created by [`AssignRequestId`] for every worker
+pub struct AssignRequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AssignRequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        let request_id = req
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(RequestId::parse)
+            .unwrap_or_else(RequestId::generate);
+
+        req.extensions_mut().insert(request_id.clone());
+
+        Box::pin(CURRENT.scope(request_id.clone(), async move {
+            let mut response = service.call(req).await?;
+
+            // Only valid header characters are accepted or generated
+            if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+                response
+                    .headers_mut()
+                    .insert(REQUEST_ID_HEADER.clone(), value);
+            }
+
+            Ok(response)
+        }))
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::{ready, Future, Ready},
+    io::Write,
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderMap,
+    Error,
+};
+use anyhow::anyhow;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{SpanKind, TraceContextExt, TracerProvider},
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+    Resource,
+};
+use tracing::{field::Empty, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::{
+    config::{TelemetryConfig, TraceExporterKind},
+    request_id::RequestId,
+};
+
+/// Collector spans are exported to over OTLP if no endpoint is configured
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4317";
+
+/// Where the spans of a service are exported to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceExporter {
+    /// Spans are not exported, only log lines are written
+    None,
+    /// Spans are written to stdout when they end, for local runs
+    Stdout,
+    /// Spans are exported in batches to an OpenTelemetry collector over OTLP/gRPC
+    Otlp(String),
+}
+
+impl TraceExporter {
+    /// Returns the exporter of the configuration, defaulting to OTLP if an endpoint is set and to
+    /// none otherwise, see [`Config`](crate::config::Config)
+    pub fn from_config(config: &TelemetryConfig) -> Self {
+        let endpoint = config.otlp_endpoint.clone();
+
+        match config.exporter {
+            Some(TraceExporterKind::Otlp) => {
+                TraceExporter::Otlp(endpoint.unwrap_or_else(|| DEFAULT_OTLP_ENDPOINT.to_string()))
+            }
+            Some(TraceExporterKind::Stdout) => TraceExporter::Stdout,
+            Some(TraceExporterKind::None) => TraceExporter::None,
+            None => endpoint.map_or(TraceExporter::None, TraceExporter::Otlp),
+        }
+    }
+}
+
+impl fmt::Display for TraceExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceExporter::None => f.write_str("none"),
+            TraceExporter::Stdout => f.write_str("stdout"),
+            TraceExporter::Otlp(endpoint) => write!(f, "otlp ({})", endpoint),
+        }
+    }
+}
+
+/// The tracing setup of a service, flushing the remaining spans when shut down
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Installs the global `tracing` subscriber of a service.
+    ///
+    /// Log lines, including those of the `log` crate, are written to stdout filtered by
+    /// `RUST_LOG`, defaulting to `info`, and carry the fields of the spans they are in such as
+    /// the request id. Spans are exported with the given exporter and the W3C trace context is
+    /// used to continue traces of other services, see [`TraceRequests`].
+    ///
+    /// Must be called within the Tokio runtime of the service for OTLP export.
+    ///
+    /// # Errors
+    /// Fails if the exporter can't be created or a subscriber was already installed
+    pub fn init(service_name: &str, exporter: TraceExporter) -> anyhow::Result<Self> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let resource = Resource::builder()
+            .with_service_name(service_name.to_string())
+            .build();
+
+        let provider = match exporter {
+            TraceExporter::None => None,
+            TraceExporter::Stdout => Some(
+                SdkTracerProvider::builder()
+                    .with_resource(resource)
+                    .with_simple_exporter(StdoutExporter)
+                    .build(),
+            ),
+            TraceExporter::Otlp(endpoint) => {
+                let exporter = opentelemetry_otlp::SpanExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(endpoint)
+                    .build()
+                    .map_err(|err| anyhow!("Failed to create the OTLP exporter: {}", err))?;
+
+                Some(
+                    SdkTracerProvider::builder()
+                        .with_resource(resource)
+                        .with_batch_exporter(exporter)
+                        .build(),
+                )
+            }
+        };
+
+        let otel_layer = provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name.to_string()))
+        });
+
+        tracing_subscriber::registry()
+            .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+            .with(tracing_subscriber::fmt::layer())
+            .with(otel_layer)
+            .try_init()
+            .map_err(|err| anyhow!("Failed to install the tracing subscriber: {}", err))?;
+
+        if let Some(provider) = &provider {
+            global::set_tracer_provider(provider.clone());
+        }
+
+        Ok(Telemetry { provider })
+    }
+
+    /// Exports the spans that have not been exported yet
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Failed to export the remaining spans: {}", err);
+            }
+        }
+    }
+}
+
+/// Exporter writing one line per span to stdout
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut stdout = std::io::stdout().lock();
+
+        for span in batch {
+            let duration = span
+                .end_time
+                .duration_since(span.start_time)
+                .unwrap_or_default();
+
+            let mut line = format!(
+                "span {} trace_id={} span_id={} parent_id={} kind={} duration={:?} status={:?}",
+                span.name,
+                span.span_context.trace_id(),
+                span.span_context.span_id(),
+                span.parent_span_id,
+                kind(&span.span_kind),
+                duration,
+                span.status
+            );
+
+            for attribute in &span.attributes {
+                line.push_str(&format!(" {}={}", attribute.key, attribute.value));
+            }
+
+            // Losing a span on a closed stdout is not worth failing the export
+            let _ = writeln!(stdout, "{}", line);
+        }
+
+        Ok(())
+    }
+}
+
+fn kind(kind: &SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Client => "client",
+        SpanKind::Server => "server",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    }
+}
+
+/// Reads the trace context of an incoming request from its headers
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Returns the W3C trace context headers of a span, such as `traceparent`, to send on a request
+/// to another service so its spans join the trace. Empty if spans are not exported.
+pub fn trace_context_headers(span: &Span) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut headers)
+    });
+
+    headers
+}
+
+/// Creates the span of a request to another service, to be entered while it is sent
+pub fn client_span(method: &str, url: &str, path: &str) -> Span {
+    tracing::info_span!(
+        "HTTP request",
+        otel.name = %format!("{} {}", method, path),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        http.request.method = %method,
+        url.full = %url,
+        http.response.status_code = Empty,
+    )
+}
+
+/// Creates the span of a MongoDB operation on a collection, e.g. `find_one` on `sessions`, to be
+/// entered while it runs
+pub fn mongodb_span(collection: &str, operation: &str) -> Span {
+    tracing::info_span!(
+        "mongodb",
+        otel.name = %format!("mongodb {} {}", operation, collection),
+        otel.kind = "client",
+        db.system = "mongodb",
+        db.collection.name = %collection,
+        db.operation.name = %operation,
+    )
+}
+
+/// Records the outcome of a request to another service on its span
+pub fn record_client_response(span: &Span, status: Option<u16>) {
+    match status {
+        Some(status) => {
+            span.record("http.response.status_code", i64::from(status));
+
+            if status >= 500 {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        None => {
+            span.record("otel.status_code", "ERROR");
+        }
+    }
+}
+
+/// Middleware creating a span for every request, continuing the trace of the caller if the
+/// request has a `traceparent` header.
+///
+/// Log lines and spans of the handlers, like those of database calls, are part of it. Should
+/// be wrapped by [`AssignRequestId`] so the span carries the request id.
+///
+/// [`AssignRequestId`]: crate::request_id::AssignRequestId
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceRequests;
+
+impl<S, B> Transform<S, ServiceRequest> for TraceRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TraceRequestsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TraceRequestsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// The service created by [`TraceRequests`] for every worker
+pub struct TraceRequestsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for TraceRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+
+        let span = tracing::info_span!(
+            "HTTP request",
+            otel.name = %req.method(),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            http.request.method = %req.method(),
+            http.route = Empty,
+            url.path = %req.path(),
+            http.response.status_code = Empty,
+            request_id = Empty,
+        );
+
+        if let Some(request_id) = RequestId::current() {
+            span.record("request_id", request_id.as_str());
+        }
+
+        // Fails only if spans are not exported, in which case there is no trace to continue
+        let _ = span.set_parent(parent);
+
+        let future = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let result = future.await;
+                let span = Span::current();
+
+                match &result {
+                    Ok(response) => {
+                        let status = response.status();
+
+                        // The route is only known once the request was routed, which is after
+                        // the span was started
+                        if let Some(route) = response.request().match_pattern() {
+                            span.context().span().update_name(format!(
+                                "{} {}",
+                                response.request().method(),
+                                route
+                            ));
+                            span.record("http.route", route);
+                        }
+
+                        span.record("http.response.status_code", i64::from(status.as_u16()));
+
+                        if status.is_server_error() {
+                            span.record("otel.status_code", "ERROR");
+                        }
+                    }
+                    Err(err) => {
+                        let status = err.as_response_error().status_code();
+
+                        span.record("http.response.status_code", i64::from(status.as_u16()));
+                        span.record("otel.status_code", "ERROR");
+                    }
+                }
+
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
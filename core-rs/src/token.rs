@@ -0,0 +1,131 @@
+use std::{collections::HashMap, fmt};
+
+use anyhow::bail;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, EllipticCurve, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ServiceError, username::NormalizedUsername, Username};
+
+/// Issuer of every access token signed by the auth service
+pub const ISSUER: &str = "dirc-auth";
+
+/// Path under which the auth service publishes its public keys
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+
+/// The claims of an access token signed by the auth service
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessClaims {
+    /// The username the token was issued to
+    pub sub: String,
+    /// The id of the session the token was issued for
+    pub sid: String,
+    /// The issuer, always [`ISSUER`]
+    pub iss: String,
+    /// Issue time in seconds since the Unix epoch
+    pub iat: u64,
+    /// Expiry time in seconds since the Unix epoch
+    pub exp: u64,
+}
+
+impl AccessClaims {
+    /// Returns the user the token was issued to
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the subject is not a valid username
+    pub fn username(&self) -> Result<Username, ServiceError> {
+        NormalizedUsername::parse(&self.sub)
+            .map(Username::from)
+            .map_err(|_| ServiceError::AuthenticationError)
+    }
+}
+
+/// Returns true if a bearer token is an access token rather than a session token. Access tokens
+/// are JWTs, made of three dot separated parts, session tokens are alphanumeric.
+pub fn is_access_token(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+/// Verifies access tokens locally against the public keys of the auth service.
+///
+/// Access tokens are Ed25519 signed JWTs. They can't be revoked, so they are short-lived and
+/// clients get a new one from the auth service with their refresh token. The auth service
+/// publishes its keys under [`JWKS_PATH`].
+#[derive(Clone)]
+pub struct TokenVerifier {
+    keys: HashMap<String, DecodingKey>,
+    validation: Validation,
+}
+
+impl fmt::Debug for TokenVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenVerifier")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl TokenVerifier {
+    /// Creates a new TokenVerifier trusting the Ed25519 keys of a JWK set
+    ///
+    /// # Errors
+    /// Fails if the set contains no usable Ed25519 key with a key id
+    pub fn new(jwks: &JwkSet) -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+
+        for jwk in &jwks.keys {
+            let (Some(key_id), AlgorithmParameters::OctetKeyPair(params)) =
+                (&jwk.common.key_id, &jwk.algorithm)
+            else {
+                continue;
+            };
+
+            if params.curve != EllipticCurve::Ed25519 {
+                continue;
+            }
+
+            keys.insert(key_id.clone(), DecodingKey::from_jwk(jwk)?);
+        }
+
+        if keys.is_empty() {
+            bail!("The JWK set contains no Ed25519 key");
+        }
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[ISSUER]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+
+        Ok(TokenVerifier { keys, validation })
+    }
+
+    /// Returns true if the token names one of the trusted keys as its signing key. A token
+    /// naming another key may be signed with a key the auth service added since the keys were
+    /// fetched.
+    pub fn knows_key_of(&self, token: &str) -> bool {
+        decode_header(token)
+            .ok()
+            .and_then(|header| header.kid)
+            .is_some_and(|key_id| self.keys.contains_key(&key_id))
+    }
+
+    /// Verifies the signature, issuer and expiry of an access token and returns its claims
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is malformed, expired or not signed by a
+    /// trusted key
+    pub fn verify(&self, token: &str) -> Result<AccessClaims, ServiceError> {
+        let header = decode_header(token).map_err(|_| ServiceError::AuthenticationError)?;
+
+        let key = header
+            .kid
+            .and_then(|key_id| self.keys.get(&key_id))
+            .ok_or(ServiceError::AuthenticationError)?;
+
+        decode::<AccessClaims>(token, key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|_| ServiceError::AuthenticationError)
+    }
+}
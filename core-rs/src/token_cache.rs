@@ -0,0 +1,299 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use prometheus::{IntCounter, IntCounterVec, IntGauge, Opts};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{metrics::Metrics, username::NormalizedUsername};
+
+/// How long a valid token is cached by default. This is also how long a token revoked without
+/// going through the caching client is still accepted, as the auth service doesn't announce
+/// revoked sessions.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+/// How long an invalid token is cached by default
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// How many tokens are cached at most by default
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// A bounded cache of session token validation results.
+///
+/// Valid tokens are cached with the user owning them, invalid ones are cached for a shorter time
+/// so repeated requests with a revoked token don't reach the auth service either. Once full, the
+/// oldest entries are evicted first.
+///
+/// The auth service doesn't announce revoked sessions, so a token logged out or revoked
+/// anywhere but through [`TokenCache::invalidate`] stays accepted until its entry expires. The
+/// TTL of valid tokens is the longest a revoked session can still be used and should be kept
+/// short.
+///
+/// Only SHA-256 digests of the tokens are kept.
+#[derive(Debug)]
+pub struct TokenCache {
+    ttl: Duration,
+    negative_ttl: Duration,
+    capacity: usize,
+    state: Mutex<CacheState>,
+    metrics: CacheMetrics,
+}
+
+/// Result of looking up a token in a [`TokenCache`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CachedToken {
+    /// The token is valid and belongs to the user
+    Valid(NormalizedUsername),
+    /// The token was rejected by the auth service
+    Invalid,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<String, Entry>,
+    /// Token digests in insertion order, together with the time they were inserted so entries
+    /// that were replaced since can be told apart
+    order: VecDeque<(String, Instant)>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    result: CachedToken,
+    inserted: Instant,
+    expires: Instant,
+}
+
+/// Counters of a [`TokenCache`], exported by [`TokenCache::register`]
+#[derive(Debug)]
+struct CacheMetrics {
+    lookups: IntCounterVec,
+    evictions: IntCounter,
+    invalidations: IntCounter,
+    entries: IntGauge,
+}
+
+impl Default for CacheMetrics {
+    fn default() -> Self {
+        // The options are fixed and valid, so creating the metrics can't fail
+        CacheMetrics {
+            lookups: IntCounterVec::new(
+                Opts::new(
+                    "token_cache_lookups_total",
+                    "Number of session token lookups in the cache by result",
+                ),
+                &["result"],
+            )
+            .unwrap(),
+            evictions: IntCounter::new(
+                "token_cache_evictions_total",
+                "Number of cached session tokens removed to make room for new ones",
+            )
+            .unwrap(),
+            invalidations: IntCounter::new(
+                "token_cache_invalidations_total",
+                "Number of cached session tokens removed explicitly, e.g. on logout",
+            )
+            .unwrap(),
+            entries: IntGauge::new(
+                "token_cache_entries",
+                "Number of session tokens currently cached",
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// A snapshot of the counters of a [`TokenCache`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Lookups answered with a cached valid token
+    pub hits: u64,
+    /// Lookups answered with a cached invalid token
+    pub negative_hits: u64,
+    /// Lookups that had to ask the auth service
+    pub misses: u64,
+    /// Entries removed to make room for new ones
+    pub evictions: u64,
+    /// Entries removed explicitly, e.g. on logout
+    pub invalidations: u64,
+    /// Entries currently cached
+    pub size: u64,
+}
+
+impl CacheStats {
+    /// Returns the share of lookups answered from the cache, between 0 and 1
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.hits + self.negative_hits;
+        let lookups = hits + self.misses;
+
+        if lookups == 0 {
+            0.0
+        } else {
+            hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl Default for TokenCache {
+    fn default() -> Self {
+        TokenCache::new(DEFAULT_TTL, DEFAULT_NEGATIVE_TTL, DEFAULT_CAPACITY)
+    }
+}
+
+impl TokenCache {
+    /// Creates a new empty TokenCache caching valid tokens for `ttl`, invalid tokens for
+    /// `negative_ttl` and at most `capacity` tokens
+    pub fn new(ttl: Duration, negative_ttl: Duration, capacity: usize) -> Self {
+        TokenCache {
+            ttl,
+            negative_ttl,
+            capacity,
+            state: Mutex::new(CacheState::default()),
+            metrics: CacheMetrics::default(),
+        }
+    }
+
+    /// Returns the cached result for a token, if it is cached and not expired
+    pub fn get(&self, token: &str) -> Option<CachedToken> {
+        let key = digest(token);
+        let mut state = self.state.lock().unwrap();
+
+        let result = match state.entries.get(&key) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.result.clone()),
+            Some(_) => {
+                state.entries.remove(&key);
+                None
+            }
+            None => None,
+        };
+
+        self.metrics.entries.set(state.entries.len() as i64);
+        self.metrics
+            .lookups
+            .with_label_values(&[lookup_result(&result)])
+            .inc();
+
+        result
+    }
+
+    /// Caches the result of validating a token, evicting the oldest entries if the cache is full
+    pub fn insert(&self, token: &str, result: CachedToken) {
+        let ttl = match result {
+            CachedToken::Valid(_) => self.ttl,
+            CachedToken::Invalid => self.negative_ttl,
+        };
+
+        if ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+
+        let key = digest(token);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        while state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            let Some((oldest, inserted)) = state.order.pop_front() else {
+                break;
+            };
+
+            if state
+                .entries
+                .get(&oldest)
+                .is_some_and(|entry| entry.inserted == inserted)
+            {
+                state.entries.remove(&oldest);
+                self.metrics.evictions.inc();
+            }
+        }
+
+        state.entries.insert(
+            key.clone(),
+            Entry {
+                result,
+                inserted: now,
+                expires: now + ttl,
+            },
+        );
+        state.order.push_back((key, now));
+        self.metrics.entries.set(state.entries.len() as i64);
+
+        // Entries removed by lookups or invalidation leave stale keys behind
+        if state.order.len() > self.capacity * 2 {
+            let CacheState { entries, order } = &mut *state;
+            order.retain(|(key, inserted)| {
+                entries
+                    .get(key)
+                    .is_some_and(|entry| entry.inserted == *inserted)
+            });
+        }
+    }
+
+    /// Removes a token from the cache, e.g. after it was used to log out through the caching client
+    pub fn invalidate(&self, token: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.entries.remove(&digest(token)).is_some() {
+            self.metrics.invalidations.inc();
+            self.metrics.entries.set(state.entries.len() as i64);
+        }
+    }
+
+    /// Removes every token of a user from the cache, e.g. after they logged out everywhere through
+    /// the caching client
+    pub fn invalidate_user(&self, username: &NormalizedUsername) {
+        let mut state = self.state.lock().unwrap();
+        let before = state.entries.len();
+
+        state.entries.retain(
+            |_, entry| !matches!(&entry.result, CachedToken::Valid(user) if user == username),
+        );
+
+        let removed = before - state.entries.len();
+        self.metrics.invalidations.inc_by(removed as u64);
+        self.metrics.entries.set(state.entries.len() as i64);
+    }
+
+    /// Returns a snapshot of the cache counters
+    pub fn stats(&self) -> CacheStats {
+        let lookups = |result| self.metrics.lookups.with_label_values(&[result]).get();
+
+        CacheStats {
+            hits: lookups("hit"),
+            negative_hits: lookups("negative_hit"),
+            misses: lookups("miss"),
+            evictions: self.metrics.evictions.get(),
+            invalidations: self.metrics.invalidations.get(),
+            size: self.state.lock().unwrap().entries.len() as u64,
+        }
+    }
+
+    /// Adds the counters of the cache to the metrics of the service, as
+    /// `token_cache_lookups_total{result}` with the results `hit`, `negative_hit` and `miss`,
+    /// `token_cache_evictions_total`, `token_cache_invalidations_total` and
+    /// `token_cache_entries`
+    ///
+    /// # Errors
+    /// Fails if metrics with the same names are already registered
+    pub fn register(&self, metrics: &Metrics) -> prometheus::Result<()> {
+        metrics.register(self.metrics.lookups.clone())?;
+        metrics.register(self.metrics.evictions.clone())?;
+        metrics.register(self.metrics.invalidations.clone())?;
+        metrics.register(self.metrics.entries.clone())
+    }
+}
+
+/// Returns the `result` label of a lookup
+fn lookup_result(result: &Option<CachedToken>) -> &'static str {
+    match result {
+        Some(CachedToken::Valid(_)) => "hit",
+        Some(CachedToken::Invalid) => "negative_hit",
+        None => "miss",
+    }
+}
+
+fn digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token))
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use caseless::default_case_fold_str;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::ServiceError;
+
+/// Minimum number of characters in a username
+pub const MIN_USERNAME_LENGTH: usize = 3;
+/// Maximum number of characters in a username
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// A validated username in its normalized form.
+///
+/// Normalization trims surrounding whitespace, applies Unicode NFKC normalization and case folding,
+/// so that e.g. `"Alice"`, `"alice"` and `"alice "` all refer to the same user. The normalized
+/// username may only contain letters, digits, `_`, `-` and `.`, must start with a letter or digit
+/// and must be between [`MIN_USERNAME_LENGTH`] and [`MAX_USERNAME_LENGTH`] characters long, which
+/// also makes it safe to use as a URL path segment.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NormalizedUsername(String);
+
+impl NormalizedUsername {
+    /// Validates and normalizes a raw username
+    ///
+    /// # Errors
+    /// `ServiceError::InvalidUsername` describing why the username is not allowed
+    pub fn parse(raw: &str) -> Result<Self, ServiceError> {
+        let nfkc = raw.trim().nfkc().collect::<String>();
+        let normalized = default_case_fold_str(&nfkc).nfkc().collect::<String>();
+
+        let length = normalized.chars().count();
+
+        if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+            return Err(ServiceError::InvalidUsername(format!(
+                "Username must be between {} and {} characters long",
+                MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+            )));
+        }
+
+        if !normalized.starts_with(char::is_alphanumeric) {
+            return Err(ServiceError::InvalidUsername(
+                "Username must start with a letter or digit".to_string(),
+            ));
+        }
+
+        if !normalized
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+        {
+            return Err(ServiceError::InvalidUsername(
+                "Username may only contain letters, digits, '_', '-' and '.'".to_string(),
+            ));
+        }
+
+        Ok(NormalizedUsername(normalized))
+    }
+
+    /// Returns the normalized username as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for NormalizedUsername {
+    type Error = ServiceError;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        NormalizedUsername::parse(&raw)
+    }
+}
+
+impl From<NormalizedUsername> for String {
+    fn from(username: NormalizedUsername) -> Self {
+        username.0
+    }
+}
+
+impl AsRef<str> for NormalizedUsername {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for NormalizedUsername {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for NormalizedUsername {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for NormalizedUsername {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    error::{FieldError, ServiceError},
+    ProfilePicture,
+};
+
+/// Maximum length of a profile picture url
+pub const MAX_PROFILE_PICTURE_URL_LENGTH: usize = 2048;
+
+/// Checks a deserialized request body beyond what its types ensure
+pub trait Validate {
+    /// Returns every invalid field of the value, or an empty list if it is valid
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+/// A JSON request body that is validated after deserialization.
+///
+/// Works like `web::Json`, honouring the `JsonConfig` of the app such as [`create_json_cfg`],
+/// but rejects bodies failing [`Validate::validate`] with `ServiceError::ValidationError`.
+///
+/// [`create_json_cfg`]: crate::create_json_cfg
+#[derive(Debug)]
+pub struct ValidJson<T>(pub T);
+
+impl<T> ValidJson<T> {
+    /// Returns the validated value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for ValidJson<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidJson<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        let req = req.clone();
+
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            let errors = value.validate();
+
+            if errors.is_empty() {
+                Ok(ValidJson(value))
+            } else {
+                Err(ServiceError::ValidationError(errors).into_actix_error(&req))
+            }
+        })
+    }
+}
+
+impl Validate for ProfilePicture {
+    fn validate(&self) -> Vec<FieldError> {
+        let url = self.profile_picture.trim();
+        let mut errors = Vec::new();
+
+        if url.is_empty() {
+            errors.push(FieldError::new(
+                "profilePicture",
+                "required",
+                "Profile picture is required".to_string(),
+            ));
+        } else if url.len() > MAX_PROFILE_PICTURE_URL_LENGTH {
+            errors.push(FieldError::new(
+                "profilePicture",
+                "too_long",
+                format!(
+                    "Profile picture url must be at most {} characters long",
+                    MAX_PROFILE_PICTURE_URL_LENGTH
+                ),
+            ));
+        } else if !(url.starts_with("https://") || url.starts_with("http://")) {
+            errors.push(FieldError::new(
+                "profilePicture",
+                "invalid_url",
+                "Profile picture must be an http or https url".to_string(),
+            ));
+        }
+
+        errors
+    }
+}
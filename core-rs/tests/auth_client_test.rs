@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use actix_web::{
+    get,
+    http::{header, StatusCode},
+    test, web, App, HttpResponse,
+};
+use async_trait::async_trait;
+use core_rs::{
+    auth_client::{AuthenticatedUser, TokenAuthenticator},
+    error::ServiceError,
+    username::NormalizedUsername,
+};
+
+/// Stand-in for the auth service, answering based on the token
+struct FakeAuthenticator;
+
+#[async_trait]
+impl TokenAuthenticator for FakeAuthenticator {
+    async fn authenticate(&self, token: &str) -> Result<NormalizedUsername, ServiceError> {
+        match token {
+            "valid" => Ok(NormalizedUsername::parse("alice").unwrap()),
+            "down" => Err(ServiceError::ServiceUnavailable(
+                "Auth service is unavailable".to_string(),
+            )),
+            _ => Err(ServiceError::AuthenticationError),
+        }
+    }
+}
+
+#[get("/me")]
+async fn me(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().body(user.username.to_string())
+}
+
+fn authenticator() -> web::Data<dyn TokenAuthenticator> {
+    web::Data::from(Arc::new(FakeAuthenticator) as Arc<dyn TokenAuthenticator>)
+}
+
+fn request(token: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri("/me")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+#[actix_web::test]
+async fn test_extractor_authenticates_user() {
+    let app = test::init_service(App::new().app_data(authenticator()).service(me)).await;
+
+    let response = test::call_service(&app, request("valid").to_request()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(test::read_body(response).await, "alice");
+}
+
+#[actix_web::test]
+async fn test_extractor_rejects_invalid_and_missing_tokens() {
+    let app = test::init_service(App::new().app_data(authenticator()).service(me)).await;
+
+    let response = test::call_service(&app, request("invalid").to_request()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["error"]["type"], "AuthenticationError");
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/me").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["error"]["type"], "AuthorizationHeaderError");
+}
+
+#[actix_web::test]
+async fn test_extractor_errors_honour_accept_header() {
+    let app = test::init_service(App::new().app_data(authenticator()).service(me)).await;
+
+    let req = request("invalid")
+        .insert_header((header::ACCEPT, "application/problem+json"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["type"], "urn:dirc:error:AuthenticationError");
+    assert_eq!(body["instance"], "/me");
+}
+
+#[actix_web::test]
+async fn test_unavailable_auth_service_is_reported() {
+    let app = test::init_service(App::new().app_data(authenticator()).service(me)).await;
+
+    let response = test::call_service(&app, request("down").to_request()).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["error"]["type"], "ServiceUnavailable");
+
+    // Without an authenticator no request can be authenticated
+    let app = test::init_service(App::new().service(me)).await;
+
+    let response = test::call_service(&app, request("valid").to_request()).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
@@ -0,0 +1,74 @@
+use actix_web::test;
+use core_rs::client_ip::TrustedProxies;
+
+fn request(peer: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .peer_addr(format!("{}:12345", peer).parse().unwrap())
+        .insert_header(("X-Forwarded-For", "203.0.113.7"))
+}
+
+#[actix_web::test]
+async fn test_forwarded_address_is_ignored_by_default() {
+    let req = request("198.51.100.1").to_http_request();
+
+    assert_eq!(
+        TrustedProxies::default().client_ip(&req).as_deref(),
+        Some("198.51.100.1")
+    );
+}
+
+#[actix_web::test]
+async fn test_forwarded_address_is_only_honoured_from_trusted_proxies() {
+    let proxies = TrustedProxies::new(vec!["10.0.0.2".parse().unwrap()]);
+
+    let req = request("10.0.0.2").to_http_request();
+    assert_eq!(proxies.client_ip(&req).as_deref(), Some("203.0.113.7"));
+
+    let req = request("10.0.0.3").to_http_request();
+    assert_eq!(proxies.client_ip(&req).as_deref(), Some("10.0.0.3"));
+}
+
+#[actix_web::test]
+async fn test_trusted_proxies_are_read_from_app_data() {
+    let proxies = TrustedProxies::new(vec!["10.0.0.2".parse().unwrap()]);
+    let req = request("10.0.0.2")
+        .app_data(actix_web::web::Data::new(proxies.clone()))
+        .to_http_request();
+
+    assert_eq!(TrustedProxies::of(&req), proxies);
+    assert_eq!(
+        TrustedProxies::of(&request("10.0.0.2").to_http_request()),
+        TrustedProxies::default()
+    );
+}
+
+#[actix_web::test]
+async fn test_spoofed_entries_left_of_the_trusted_proxies_are_ignored() {
+    let proxies = TrustedProxies::new(vec![
+        "10.0.0.2".parse().unwrap(),
+        "10.0.0.3".parse().unwrap(),
+    ]);
+
+    // The client sent its own X-Forwarded-For, the proxies appended the address they saw
+    let req = test::TestRequest::get()
+        .peer_addr("10.0.0.2:12345".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "1.2.3.4, 203.0.113.7, 10.0.0.3"))
+        .to_http_request();
+    assert_eq!(proxies.client_ip(&req).as_deref(), Some("203.0.113.7"));
+
+    let req = test::TestRequest::get()
+        .peer_addr("10.0.0.2:12345".parse().unwrap())
+        .insert_header((
+            "Forwarded",
+            "for=1.2.3.4, for=\"[2001:db8::17]:4711\";proto=https, for=10.0.0.3",
+        ))
+        .to_http_request();
+    assert_eq!(proxies.client_ip(&req).as_deref(), Some("2001:db8::17"));
+
+    // Without any untrusted hop the leftmost one is the client
+    let req = test::TestRequest::get()
+        .peer_addr("10.0.0.2:12345".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "10.0.0.3"))
+        .to_http_request();
+    assert_eq!(proxies.client_ip(&req).as_deref(), Some("10.0.0.3"));
+}
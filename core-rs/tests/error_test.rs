@@ -0,0 +1,239 @@
+use actix_web::{body::to_bytes, http::StatusCode, test::TestRequest, Responder};
+use core_rs::error::{FieldError, PolicyViolation, Response, ServiceError, ServiceErrorJSON};
+use proptest::prelude::*;
+
+#[test]
+fn test_too_many_attempts_round_trips_retry_after() {
+    let json = serde_json::to_value(ServiceError::TooManyAttempts(30)).unwrap();
+
+    assert_eq!(json["error"]["type"], "TooManyAttempts");
+    assert_eq!(json["error"]["retry_after"], 30);
+
+    let parsed: ServiceErrorJSON = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        ServiceError::from(parsed),
+        ServiceError::TooManyAttempts(30)
+    );
+}
+
+#[test]
+fn test_retry_after_is_only_serialized_when_set() {
+    let json = serde_json::to_value(ServiceError::InvalidCredentials).unwrap();
+
+    assert_eq!(json["error"]["type"], "InvalidCredentials");
+    assert!(json["error"].get("retry_after").is_none());
+}
+
+#[actix_web::test]
+async fn test_too_many_attempts_sets_retry_after_header() {
+    let req = TestRequest::default().to_http_request();
+    let response = Response::<()>::Err(ServiceError::TooManyAttempts(12)).respond_to(&req);
+
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers().get("Retry-After").unwrap(), "12");
+
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["retry_after"], 12);
+}
+
+#[actix_web::test]
+async fn test_other_errors_have_no_retry_after_header() {
+    let req = TestRequest::default().to_http_request();
+    let response = Response::<()>::Err(ServiceError::InvalidCredentials).respond_to(&req);
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get("Retry-After").is_none());
+}
+
+#[test]
+fn test_usernames_are_kept_in_details() {
+    let error = ServiceError::UserNotFound("o'brien".to_string());
+    let json = serde_json::to_value(&error).unwrap();
+
+    assert_eq!(json["error"]["details"]["username"], "o'brien");
+    assert_eq!(json["error"]["status"], 404);
+    assert_eq!(serde_json::from_value::<ServiceError>(json).unwrap(), error);
+}
+
+#[test]
+fn test_errors_without_details_are_still_understood() {
+    let json = serde_json::json!({
+        "error": { "type": "UsernameTaken", "message": "Username 'alice' is taken" }
+    });
+    assert_eq!(
+        serde_json::from_value::<ServiceError>(json).unwrap(),
+        ServiceError::UsernameTaken("alice".to_string())
+    );
+
+    // A message without quotes is used as it is instead of panicking
+    let json = serde_json::json!({
+        "error": { "type": "SessionNotFound", "message": "gone" }
+    });
+    assert_eq!(
+        serde_json::from_value::<ServiceError>(json).unwrap(),
+        ServiceError::SessionNotFound("gone".to_string())
+    );
+}
+
+#[test]
+fn test_unknown_types_keep_type_message_and_status() {
+    let json = serde_json::json!({
+        "error": { "type": "QuotaExceeded", "message": "Out of quota", "status": 402 }
+    });
+    let error = serde_json::from_value::<ServiceError>(json).unwrap();
+
+    assert_eq!(
+        error,
+        ServiceError::Unknown {
+            kind: "QuotaExceeded".to_string(),
+            message: "Out of quota".to_string(),
+            status: 402,
+        }
+    );
+    assert_eq!(error.status_code(), StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(error.error_type(), "QuotaExceeded");
+
+    // Without a status it is treated as an internal error
+    let json = serde_json::json!({ "error": { "type": "QuotaExceeded", "message": "" } });
+    let error = serde_json::from_value::<ServiceError>(json).unwrap();
+
+    assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+fn service_error() -> impl Strategy<Value = ServiceError> {
+    let violation = (".*", ".*").prop_map(|(rule, message)| PolicyViolation::new(&rule, message));
+    let field = (".*", ".*", ".*")
+        .prop_map(|(field, code, message)| FieldError::new(&field, &code, message));
+
+    prop_oneof![
+        Just(ServiceError::NotFound),
+        ".*".prop_map(ServiceError::JsonParsingError),
+        ".*".prop_map(ServiceError::UserNotFound),
+        ".*".prop_map(ServiceError::UsernameTaken),
+        ".*".prop_map(ServiceError::DatabaseError),
+        Just(ServiceError::InvalidPassword),
+        Just(ServiceError::InvalidCredentials),
+        any::<u64>().prop_map(ServiceError::TooManyAttempts),
+        ".*".prop_map(ServiceError::ServiceUnavailable),
+        any::<u64>().prop_map(ServiceError::RateLimited),
+        Just(ServiceError::AuthenticationError),
+        Just(ServiceError::AuthorizationError),
+        Just(ServiceError::AuthorizationHeaderError),
+        ".*".prop_map(ServiceError::InvalidUsername),
+        Just(ServiceError::InvalidResetToken),
+        ".*".prop_map(ServiceError::SessionNotFound),
+        ".*".prop_map(ServiceError::NotificationError),
+        Just(ServiceError::InvalidSecondFactor),
+        Just(ServiceError::SecondFactorAlreadyEnabled),
+        Just(ServiceError::SecondFactorNotEnrolled),
+        prop::collection::vec(violation, 0..4).prop_map(ServiceError::PasswordPolicyViolation),
+        prop::collection::vec(field, 0..4).prop_map(ServiceError::ValidationError),
+        // No known type starts with "Custom"
+        ("Custom[A-Za-z]{0,12}", ".*", any::<u16>()).prop_map(|(kind, message, status)| {
+            ServiceError::Unknown {
+                kind,
+                message,
+                status,
+            }
+        }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn test_json_round_trip_is_lossless(error in service_error()) {
+        let json = serde_json::to_string(&error).unwrap();
+
+        prop_assert_eq!(serde_json::from_str::<ServiceError>(&json).unwrap(), error);
+    }
+}
+
+fn request_accepting(accept: &str) -> actix_web::HttpRequest {
+    TestRequest::default()
+        .uri("/alice/info")
+        .insert_header(("Accept", accept))
+        .to_http_request()
+}
+
+#[actix_web::test]
+async fn test_problem_json_is_sent_when_accepted() {
+    let req = request_accepting("application/problem+json");
+    let response =
+        Response::<()>::Err(ServiceError::UserNotFound("alice".to_string())).respond_to(&req);
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "type": "urn:dirc:error:UserNotFound",
+            "title": "User not found",
+            "status": 404,
+            "detail": "User 'alice' does not exist",
+            "instance": "/alice/info",
+            "username": "alice",
+        })
+    );
+}
+
+#[actix_web::test]
+async fn test_problem_json_keeps_extension_members_and_headers() {
+    let req = request_accepting("application/problem+json");
+    let response = Response::<()>::Err(ServiceError::RateLimited(7)).respond_to(&req);
+
+    assert_eq!(response.headers().get("Retry-After").unwrap(), "7");
+
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(json["type"], "urn:dirc:error:RateLimited");
+    assert_eq!(json["title"], "Rate limited");
+    assert_eq!(json["retry_after"], 7);
+}
+
+#[actix_web::test]
+async fn test_legacy_format_is_the_default() {
+    for accept in [
+        "*/*",
+        "application/json",
+        "application/problem+json;q=0.5, application/json",
+        "application/problem+json;q=0",
+    ] {
+        let req = request_accepting(accept);
+        let response = Response::<()>::Err(ServiceError::InvalidPassword).respond_to(&req);
+
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/json",
+            "Accept: {}",
+            accept
+        );
+
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["type"], "InvalidPassword");
+    }
+
+    let req = TestRequest::default().to_http_request();
+    let response = Response::<()>::Err(ServiceError::InvalidPassword).respond_to(&req);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+
+    // Preferring problem details over plain JSON selects them
+    let req = request_accepting("application/json;q=0.5, application/problem+json");
+    let response = Response::<()>::Err(ServiceError::InvalidPassword).respond_to(&req);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+}
@@ -0,0 +1,207 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use actix_web::{http::StatusCode, test, web, App, HttpResponse, HttpServer};
+use async_trait::async_trait;
+use core_rs::health::{
+    self, BackgroundSetup, HealthCheck, HealthChecks, HealthReport, HealthStatus, HttpCheck,
+    HEALTH_PATH, READY_PATH,
+};
+
+/// Check with a fixed outcome, taking the given time
+struct FixedCheck {
+    delay: Duration,
+    result: Result<(), String>,
+}
+
+impl FixedCheck {
+    fn up() -> Self {
+        FixedCheck {
+            delay: Duration::ZERO,
+            result: Ok(()),
+        }
+    }
+
+    fn down(error: &str) -> Self {
+        FixedCheck {
+            delay: Duration::ZERO,
+            result: Err(error.to_string()),
+        }
+    }
+
+    fn slow(delay: Duration) -> Self {
+        FixedCheck {
+            delay,
+            result: Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for FixedCheck {
+    async fn check(&self) -> Result<(), String> {
+        actix_web::rt::time::sleep(self.delay).await;
+
+        self.result.clone()
+    }
+}
+
+macro_rules! app {
+    ($checks:expr) => {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new($checks))
+                .route(HEALTH_PATH, web::get().to(health::healthz))
+                .route(READY_PATH, web::get().to(health::readyz)),
+        )
+        .await
+    };
+}
+
+#[actix_web::test]
+async fn test_ready_if_every_check_succeeds() {
+    let app = app!(HealthChecks::new()
+        .with_check("mongodb", FixedCheck::up())
+        .with_check("users", FixedCheck::up()));
+
+    let req = test::TestRequest::get().uri(READY_PATH).to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let report: HealthReport = test::read_body_json(response).await;
+    assert_eq!(report.status, HealthStatus::Up);
+    assert_eq!(report.checks.len(), 2);
+    assert_eq!(report.checks["mongodb"].status, HealthStatus::Up);
+    assert_eq!(report.checks["mongodb"].error, None);
+}
+
+#[actix_web::test]
+async fn test_not_ready_if_a_check_fails() {
+    let app = app!(HealthChecks::new()
+        .with_check("mongodb", FixedCheck::down("connection refused"))
+        .with_check("users", FixedCheck::up()));
+
+    let req = test::TestRequest::get().uri(READY_PATH).to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let report: HealthReport = test::read_body_json(response).await;
+    assert_eq!(report.status, HealthStatus::Down);
+    assert_eq!(report.checks["mongodb"].status, HealthStatus::Down);
+    assert_eq!(
+        report.checks["mongodb"].error.as_deref(),
+        Some("connection refused")
+    );
+    assert_eq!(report.checks["users"].status, HealthStatus::Up);
+}
+
+#[actix_web::test]
+async fn test_slow_checks_time_out() {
+    let checks = HealthChecks::new()
+        .with_timeout(Duration::from_millis(50))
+        .with_check("slow", FixedCheck::slow(Duration::from_secs(5)))
+        .with_check("fast", FixedCheck::up());
+
+    let report = checks.run().await;
+
+    assert_eq!(report.status, HealthStatus::Down);
+    assert_eq!(report.checks["slow"].status, HealthStatus::Down);
+    assert!(report.checks["slow"].duration_ms < 1000);
+    assert_eq!(report.checks["fast"].status, HealthStatus::Up);
+}
+
+#[actix_web::test]
+async fn test_live_without_checking_dependencies() {
+    let app = app!(HealthChecks::new().with_check("mongodb", FixedCheck::down("down")));
+
+    let req = test::TestRequest::get().uri(HEALTH_PATH).to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = test::read_body(response).await;
+    assert_eq!(body, r#"{"status":"up","checks":{}}"#);
+}
+
+fn start_peer(status: StatusCode) -> SocketAddr {
+    let server = HttpServer::new(move || {
+        App::new().route(
+            HEALTH_PATH,
+            web::get().to(move || async move { HttpResponse::build(status).finish() }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+
+    actix_web::rt::spawn(server.run());
+
+    addr
+}
+
+#[actix_web::test]
+async fn test_http_check_requests_the_liveness_endpoint() {
+    let live = start_peer(StatusCode::OK);
+    let failing = start_peer(StatusCode::INTERNAL_SERVER_ERROR);
+
+    assert_eq!(
+        HttpCheck::service(&format!("http://{}/", live))
+            .check()
+            .await,
+        Ok(())
+    );
+    assert!(HttpCheck::service(&format!("http://{}", failing))
+        .check()
+        .await
+        .unwrap_err()
+        .contains("500"));
+
+    // Nothing listens on port 9 (discard) locally
+    assert!(HttpCheck::service("http://127.0.0.1:9")
+        .check()
+        .await
+        .is_err());
+}
+
+#[actix_web::test]
+async fn test_background_setup_is_retried_until_it_succeeds() {
+    let tries = Arc::new(AtomicUsize::new(0));
+    let counter = tries.clone();
+
+    let setup = BackgroundSetup::spawn("database", Duration::from_millis(20), move || {
+        let try_number = counter.fetch_add(1, Ordering::SeqCst);
+
+        async move {
+            if try_number < 2 {
+                anyhow::bail!("connection refused");
+            }
+
+            Ok(())
+        }
+    });
+
+    let err = setup.check().await.unwrap_err();
+    assert!(err.starts_with("Not set up yet"));
+
+    let app = app!(HealthChecks::new().with_check("database", setup.clone()));
+
+    let req = test::TestRequest::get().uri(READY_PATH).to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(setup.check().await, Ok(()));
+    assert_eq!(tries.load(Ordering::SeqCst), 3);
+
+    let req = test::TestRequest::get().uri(READY_PATH).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,127 @@
+use std::net::TcpListener;
+
+use actix_web::{get, http::StatusCode, test, web, App, HttpResponse};
+use core_rs::metrics::{self, Metrics, RecordMetrics, METRICS_PATH};
+use prometheus::IntCounter;
+
+#[get("/users/{username}")]
+async fn user() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[get("/fail")]
+async fn fail() -> HttpResponse {
+    HttpResponse::InternalServerError().finish()
+}
+
+macro_rules! app {
+    ($metrics:expr) => {
+        test::init_service(
+            App::new()
+                .wrap(RecordMetrics::new($metrics.clone()))
+                .app_data(web::Data::new($metrics.clone()))
+                .route(METRICS_PATH, web::get().to(metrics::metrics))
+                .service(user)
+                .service(fail),
+        )
+        .await
+    };
+}
+
+macro_rules! scrape {
+    ($app:expr) => {{
+        let req = test::TestRequest::get().uri(METRICS_PATH).to_request();
+
+        String::from_utf8(test::call_and_read_body($app, req).await.to_vec()).unwrap()
+    }};
+}
+
+#[actix_web::test]
+async fn test_requests_are_counted_per_route_and_status() {
+    let metrics = Metrics::new();
+    let app = app!(metrics);
+
+    for uri in ["/users/alice", "/users/bob", "/fail", "/unknown"] {
+        test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+    }
+
+    let body = scrape!(&app);
+
+    assert!(body
+        .contains(r#"http_requests_total{method="GET",route="/users/{username}",status="200"} 2"#));
+    assert!(body.contains(r#"http_requests_total{method="GET",route="/fail",status="500"} 1"#));
+    assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+    assert!(body.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/users/{username}",status="200"} 2"#
+    ));
+    assert!(!body.contains("alice"));
+}
+
+#[actix_web::test]
+async fn test_metrics_are_served_in_the_text_format() {
+    let metrics = Metrics::new();
+    let app = app!(metrics);
+
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get().uri(METRICS_PATH).to_request(),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "text/plain; version=0.0.4"
+    );
+}
+
+#[actix_web::test]
+async fn test_services_can_register_their_own_metrics() {
+    let metrics = Metrics::new();
+    let logins = IntCounter::new("logins_total", "Number of logins").unwrap();
+
+    metrics.register(logins.clone()).unwrap();
+    assert!(metrics.register(logins.clone()).is_err());
+
+    logins.inc();
+
+    assert!(metrics.render().contains("logins_total 1"));
+}
+
+#[actix_web::test]
+async fn test_metrics_are_served_on_their_own_address() {
+    let metrics = Metrics::new();
+    metrics.observe_request("GET", "/users/{username}", StatusCode::OK, 0.01);
+
+    // Finds a free port for the server to take over
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let server = metrics::serve(metrics, address).unwrap();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let client = reqwest::Client::new();
+
+    let body = client
+        .get(format!("http://{}{}", address, METRICS_PATH))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("http_requests_total"));
+
+    // Nothing else is served there
+    let response = client
+        .get(format!("http://{}/users/alice", address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+
+    handle.stop(false).await;
+}
@@ -0,0 +1,298 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{
+    get,
+    http::{header, StatusCode},
+    test, web, App, HttpResponse,
+};
+use core_rs::{
+    client_ip::TrustedProxies,
+    config::RateLimitsConfig,
+    rate_limit::{MemoryRateLimitStore, RateLimit, RateLimitKey, RateLimitStore, RateLimiter},
+};
+
+#[get("/{username}/exists")]
+async fn exists() -> HttpResponse {
+    HttpResponse::Ok().json(true)
+}
+
+#[get("/other")]
+async fn other() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn get_request(path: &str, ip: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(path)
+        .peer_addr(format!("{}:12345", ip).parse().unwrap())
+}
+
+#[tokio::test]
+async fn test_memory_store_refills_bucket() {
+    let store = MemoryRateLimitStore::new();
+    let limit = RateLimit::new(2, Duration::from_millis(200));
+
+    assert_eq!(store.acquire("key", &limit).await.unwrap(), None);
+    assert_eq!(store.acquire("key", &limit).await.unwrap(), None);
+
+    let retry_after = store
+        .acquire("key", &limit)
+        .await
+        .unwrap()
+        .expect("Bucket should be empty");
+    assert!(retry_after <= Duration::from_millis(100));
+
+    // Other keys have their own bucket
+    assert_eq!(store.acquire("other", &limit).await.unwrap(), None);
+
+    tokio::time::sleep(Duration::from_millis(110)).await;
+
+    assert_eq!(store.acquire("key", &limit).await.unwrap(), None);
+}
+
+#[actix_web::test]
+async fn test_limits_route_per_ip() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .route("/{username}/exists", RateLimit::per_minute(2));
+    let app = test::init_service(App::new().wrap(limiter).service(exists).service(other)).await;
+
+    for username in ["alice", "bob"] {
+        let req = get_request(&format!("/{}/exists", username), "10.0.0.1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    let req = get_request("/carol/exists", "10.0.0.1").to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["error"]["type"], "RateLimited");
+    assert_eq!(body["error"]["retry_after"], 30);
+
+    // Other clients and routes without a limit are not affected
+    let req = get_request("/carol/exists", "10.0.0.2").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    for _ in 0..5 {
+        let req = get_request("/other", "10.0.0.1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
+
+#[actix_web::test]
+async fn test_default_limit_applies_to_routes_without_their_own() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .route("/{username}/exists", RateLimit::per_minute(5))
+        .default_limit(RateLimit::per_minute(1));
+    let app = test::init_service(App::new().wrap(limiter).service(exists).service(other)).await;
+
+    let req = get_request("/other", "10.0.0.1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = get_request("/other", "10.0.0.1").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let req = get_request("/alice/exists", "10.0.0.1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_limits_by_bearer_token() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new())).route(
+        "/other",
+        RateLimit::per_minute(1).keyed_by(RateLimitKey::BearerToken),
+    );
+    let app = test::init_service(App::new().wrap(limiter).service(other)).await;
+
+    let with_token = |token: &str| {
+        get_request("/other", "10.0.0.1")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    assert_eq!(
+        test::call_service(&app, with_token("first")).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        test::call_service(&app, with_token("first")).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        test::call_service(&app, with_token("second"))
+            .await
+            .status(),
+        StatusCode::OK
+    );
+
+    // Requests without a token fall back to the client IP address
+    let req = get_request("/other", "10.0.0.1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_route_key_is_shared_by_every_client() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new())).route(
+        "/other",
+        RateLimit::per_minute(1).keyed_by(RateLimitKey::Route),
+    );
+    let app = test::init_service(App::new().wrap(limiter).service(other)).await;
+
+    let req = get_request("/other", "10.0.0.1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = get_request("/other", "10.0.0.2").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[actix_web::test]
+async fn test_configured_limits_override_the_ones_of_the_service() {
+    let config = RateLimitsConfig {
+        default: Some(2),
+        routes: [("/other".to_string(), 1)].into_iter().collect(),
+    };
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .route(
+            "/other",
+            RateLimit::per_minute(5).keyed_by(RateLimitKey::Route),
+        )
+        .default_limit(RateLimit::per_minute(5))
+        .with_config(&config);
+    let app = test::init_service(App::new().wrap(limiter).service(exists).service(other)).await;
+
+    // The overridden route is still shared by every client
+    let req = get_request("/other", "10.0.0.1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = get_request("/other", "10.0.0.2").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    for _ in 0..2 {
+        let req = get_request("/alice/exists", "10.0.0.1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    let req = get_request("/alice/exists", "10.0.0.1").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[actix_web::test]
+async fn test_unmatched_requests_are_not_limited_without_default() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .route("/other", RateLimit::per_minute(1));
+    let app = test::init_service(
+        App::new()
+            .wrap(limiter)
+            .service(other)
+            .default_service(web::route().to(HttpResponse::NotFound)),
+    )
+    .await;
+
+    for _ in 0..3 {
+        let req = get_request("/missing", "10.0.0.1").to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
+
+#[actix_web::test]
+async fn test_unmatched_requests_share_the_default_bucket() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .default_limit(RateLimit::per_minute(1));
+    let app = test::init_service(
+        App::new()
+            .wrap(limiter)
+            .default_service(web::route().to(HttpResponse::NotFound)),
+    )
+    .await;
+
+    let req = get_request("/missing", "10.0.0.1").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let req = get_request("/another/missing", "10.0.0.1").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[actix_web::test]
+async fn test_exempt_routes_are_not_limited() {
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .exempt("/other")
+        .default_limit(RateLimit::per_minute(1));
+    let app = test::init_service(App::new().wrap(limiter).service(exists).service(other)).await;
+
+    for _ in 0..3 {
+        let req = get_request("/other", "10.0.0.1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    let req = get_request("/alice/exists", "10.0.0.1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = get_request("/alice/exists", "10.0.0.1").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn test_empty_limits_are_rejected() {
+    assert!(std::panic::catch_unwind(|| RateLimit::new(0, Duration::from_secs(60))).is_err());
+    assert!(std::panic::catch_unwind(|| RateLimit::new(10, Duration::ZERO)).is_err());
+}
+
+#[actix_web::test]
+async fn test_forwarded_ip_is_only_honoured_from_trusted_proxies() {
+    let forwarded = |peer: &str, client: &str| {
+        get_request("/carol/exists", peer)
+            .insert_header(("X-Forwarded-For", client))
+            .to_request()
+    };
+
+    // Clients can't get a fresh bucket by making up an address
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .route("/{username}/exists", RateLimit::per_minute(1));
+    let app = test::init_service(App::new().wrap(limiter).service(exists)).await;
+
+    let req = forwarded("10.0.0.1", "203.0.113.1");
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = forwarded("10.0.0.1", "203.0.113.2");
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Behind a trusted proxy every client has its own bucket
+    let limiter = RateLimiter::new(Arc::new(MemoryRateLimitStore::new()))
+        .route("/{username}/exists", RateLimit::per_minute(1))
+        .trusted_proxies(TrustedProxies::new(vec!["10.0.0.1".parse().unwrap()]));
+    let app = test::init_service(App::new().wrap(limiter).service(exists)).await;
+
+    for client in ["203.0.113.1", "203.0.113.2"] {
+        let req = forwarded("10.0.0.1", client);
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{
+    get,
+    http::{header, StatusCode},
+    test, web, App, HttpResponse,
+};
+use async_trait::async_trait;
+use core_rs::{
+    auth_client::{AuthenticatedUser, TokenAuthenticator},
+    error::{Response, ServiceError, ServiceErrorJSON},
+    request_id::{AssignRequestId, RequestId, MAX_REQUEST_ID_LENGTH, REQUEST_ID_HEADER},
+    username::NormalizedUsername,
+};
+
+/// Stand-in for the auth service, remembering the request id it was called with
+#[derive(Default)]
+struct FakeAuthenticator {
+    received: Mutex<Option<RequestId>>,
+}
+
+#[async_trait]
+impl TokenAuthenticator for FakeAuthenticator {
+    async fn authenticate(&self, _token: &str) -> Result<NormalizedUsername, ServiceError> {
+        *self.received.lock().unwrap() = RequestId::current();
+
+        Err(ServiceError::AuthenticationError)
+    }
+}
+
+#[get("/id")]
+async fn id(request_id: RequestId) -> HttpResponse {
+    assert_eq!(RequestId::current(), Some(request_id.clone()));
+
+    HttpResponse::Ok().body(request_id.to_string())
+}
+
+#[get("/fail")]
+async fn fail() -> Response<()> {
+    Response::Err(ServiceError::UserNotFound("alice".to_string()))
+}
+
+#[get("/me")]
+async fn me(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().body(user.username.to_string())
+}
+
+macro_rules! app {
+    () => {
+        test::init_service(App::new().wrap(AssignRequestId).service(id).service(fail)).await
+    };
+}
+
+#[actix_web::test]
+async fn test_request_ids_are_generated() {
+    let app = app!();
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/id").to_request()).await;
+    let header = response.headers().get(&REQUEST_ID_HEADER).unwrap().clone();
+    let body = test::read_body(response).await;
+
+    assert_eq!(header.as_bytes(), &body[..]);
+    assert_eq!(body.len(), 32);
+    assert!(body.iter().all(u8::is_ascii_hexdigit));
+
+    let other = test::call_service(&app, test::TestRequest::get().uri("/id").to_request()).await;
+    assert_ne!(other.headers().get(&REQUEST_ID_HEADER).unwrap(), &header);
+}
+
+#[actix_web::test]
+async fn test_valid_request_ids_are_accepted() {
+    let app = app!();
+
+    let req = test::TestRequest::get()
+        .uri("/id")
+        .insert_header((REQUEST_ID_HEADER, "support-ticket_42.1"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(
+        response.headers().get(&REQUEST_ID_HEADER).unwrap(),
+        "support-ticket_42.1"
+    );
+    assert_eq!(test::read_body(response).await, "support-ticket_42.1");
+
+    let too_long = "a".repeat(MAX_REQUEST_ID_LENGTH + 1);
+
+    for invalid in ["", "with space", "quote\"d", too_long.as_str()] {
+        let req = test::TestRequest::get()
+            .uri("/id")
+            .insert_header((REQUEST_ID_HEADER, invalid))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+
+        assert_ne!(body, invalid);
+        assert_eq!(body.len(), 32);
+    }
+}
+
+#[actix_web::test]
+async fn test_errors_carry_the_request_id() {
+    let app = app!();
+
+    let req = test::TestRequest::get()
+        .uri("/fail")
+        .insert_header((REQUEST_ID_HEADER, "abc"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers().get(&REQUEST_ID_HEADER).unwrap(), "abc");
+
+    let body: ServiceErrorJSON = test::read_body_json(response).await;
+    assert_eq!(body.request_id(), Some("abc"));
+    assert_eq!(
+        ServiceError::from(body),
+        ServiceError::UserNotFound("alice".to_string())
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/fail")
+        .insert_header((REQUEST_ID_HEADER, "abc"))
+        .insert_header((header::ACCEPT, "application/problem+json"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(body["request_id"], "abc");
+
+    // Errors serialized outside of a request have no id
+    let body = serde_json::to_value(ServiceError::NotFound).unwrap();
+    assert!(body["error"].get("request_id").is_none());
+}
+
+#[actix_web::test]
+async fn test_request_ids_are_available_to_the_authenticator() {
+    let authenticator = Arc::new(FakeAuthenticator::default());
+    let app = test::init_service(
+        App::new()
+            .wrap(AssignRequestId)
+            .app_data(web::Data::from(
+                authenticator.clone() as Arc<dyn TokenAuthenticator>
+            ))
+            .service(me),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/me")
+        .insert_header((REQUEST_ID_HEADER, "trace-me"))
+        .insert_header((header::AUTHORIZATION, "Bearer expired"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        authenticator
+            .received
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(RequestId::as_str),
+        Some("trace-me")
+    );
+
+    let body: ServiceErrorJSON = test::read_body_json(response).await;
+    assert_eq!(body.request_id(), Some("trace-me"));
+}
@@ -0,0 +1,158 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{get, http::StatusCode, test, App, HttpResponse};
+use core_rs::{
+    request_id::{AssignRequestId, REQUEST_ID_HEADER},
+    telemetry::{mongodb_span, trace_context_headers, TraceRequests},
+};
+use opentelemetry::{
+    global,
+    trace::{SpanKind, TracerProvider},
+    Value,
+};
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+};
+use tracing::{subscriber::DefaultGuard, Instrument, Span};
+use tracing_subscriber::layer::SubscriberExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+/// Exporter keeping the spans in memory
+#[derive(Clone, Debug, Default)]
+struct MemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for MemoryExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        self.0.lock().unwrap().extend(batch);
+
+        Ok(())
+    }
+}
+
+impl MemoryExporter {
+    fn spans(&self) -> Vec<SpanData> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Exports the spans of the current thread to memory until the guard is dropped
+fn trace_to_memory() -> (MemoryExporter, DefaultGuard) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let exporter = MemoryExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    (exporter, tracing::subscriber::set_default(subscriber))
+}
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .map(|attribute| &attribute.value)
+}
+
+#[get("/users/{username}")]
+async fn user() -> HttpResponse {
+    let headers = trace_context_headers(&Span::current());
+
+    HttpResponse::Ok().body(headers.get("traceparent").cloned().unwrap_or_default())
+}
+
+#[get("/fail")]
+async fn fail() -> HttpResponse {
+    HttpResponse::InternalServerError().finish()
+}
+
+#[actix_web::test]
+async fn test_requests_continue_the_trace_of_the_caller() {
+    let (exporter, _guard) = trace_to_memory();
+
+    let app = test::init_service(
+        App::new()
+            .wrap(TraceRequests)
+            .wrap(AssignRequestId)
+            .service(user)
+            .service(fail),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/users/alice")
+        .insert_header(("traceparent", format!("00-{}-{}-01", TRACE_ID, PARENT_ID)))
+        .insert_header((REQUEST_ID_HEADER, "abc"))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let traceparent = String::from_utf8(body.to_vec()).unwrap();
+
+    // The handler runs in the span of the request, which is a child of the caller's span
+    assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+    assert!(!traceparent.contains(PARENT_ID));
+
+    let spans = exporter.spans();
+    assert_eq!(spans.len(), 1);
+
+    let span = &spans[0];
+    assert_eq!(span.name, "GET /users/{username}");
+    assert_eq!(span.span_kind, SpanKind::Server);
+    assert_eq!(span.span_context.trace_id().to_string(), TRACE_ID);
+    assert_eq!(span.parent_span_id.to_string(), PARENT_ID);
+    assert_eq!(
+        attribute(span, "http.route"),
+        Some(&Value::from("/users/{username}"))
+    );
+    assert_eq!(
+        attribute(span, "http.response.status_code"),
+        Some(&Value::I64(200))
+    );
+    assert_eq!(attribute(span, "request_id"), Some(&Value::from("abc")));
+}
+
+#[actix_web::test]
+async fn test_requests_without_trace_context_start_a_trace() {
+    let (exporter, _guard) = trace_to_memory();
+
+    let app = test::init_service(App::new().wrap(TraceRequests).service(fail)).await;
+
+    let response =
+        test::call_service(&app, test::TestRequest::get().uri("/fail").to_request()).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let spans = exporter.spans();
+    assert_eq!(spans.len(), 1);
+    assert!(spans[0].span_context.is_valid());
+    assert_eq!(spans[0].status, opentelemetry::trace::Status::error(""));
+}
+
+#[actix_web::test]
+async fn test_mongodb_spans_describe_the_operation() {
+    let (exporter, _guard) = trace_to_memory();
+
+    async {}
+        .instrument(mongodb_span("sessions", "find_one"))
+        .await;
+
+    let spans = exporter.spans();
+    assert_eq!(spans.len(), 1);
+
+    let span = &spans[0];
+    assert_eq!(span.name, "mongodb find_one sessions");
+    assert_eq!(span.span_kind, SpanKind::Client);
+    assert_eq!(
+        attribute(span, "db.collection.name"),
+        Some(&Value::from("sessions"))
+    );
+    assert_eq!(
+        attribute(span, "db.operation.name"),
+        Some(&Value::from("find_one"))
+    );
+}
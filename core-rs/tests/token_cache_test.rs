@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use core_rs::{
+    metrics::Metrics,
+    token_cache::{CachedToken, TokenCache},
+    username::NormalizedUsername,
+};
+
+fn valid(username: &str) -> CachedToken {
+    CachedToken::Valid(NormalizedUsername::parse(username).unwrap())
+}
+
+#[test]
+fn test_evicts_oldest_entries_when_full() {
+    let cache = TokenCache::new(Duration::from_secs(30), Duration::from_secs(5), 2);
+
+    cache.insert("first", valid("alice"));
+    cache.insert("second", CachedToken::Invalid);
+    // Replacing an entry doesn't need room
+    cache.insert("first", valid("alice"));
+    cache.insert("third", valid("bob"));
+
+    assert_eq!(cache.get("first"), Some(valid("alice")));
+    assert_eq!(cache.get("second"), None);
+    assert_eq!(cache.get("third"), Some(valid("bob")));
+
+    let stats = cache.stats();
+    assert_eq!((stats.size, stats.evictions), (2, 1));
+    assert_eq!((stats.hits, stats.misses), (2, 1));
+}
+
+#[test]
+fn test_entries_expire() {
+    let cache = TokenCache::new(Duration::from_millis(50), Duration::ZERO, 10);
+
+    cache.insert("valid", valid("alice"));
+    // A TTL of 0 disables caching
+    cache.insert("invalid", CachedToken::Invalid);
+
+    assert_eq!(cache.get("valid"), Some(valid("alice")));
+    assert_eq!(cache.get("invalid"), None);
+
+    std::thread::sleep(Duration::from_millis(60));
+
+    assert_eq!(cache.get("valid"), None);
+    assert_eq!(cache.stats().size, 0);
+}
+
+#[test]
+fn test_invalidate_user_keeps_other_users() {
+    let cache = TokenCache::default();
+
+    cache.insert("alice-1", valid("alice"));
+    cache.insert("alice-2", valid("alice"));
+    cache.insert("bob", valid("bob"));
+
+    cache.invalidate_user(&NormalizedUsername::parse("alice").unwrap());
+
+    assert_eq!(cache.get("alice-1"), None);
+    assert_eq!(cache.get("alice-2"), None);
+    assert_eq!(cache.get("bob"), Some(valid("bob")));
+    assert_eq!(cache.stats().invalidations, 2);
+    assert_eq!(cache.stats().hit_rate(), 1.0 / 3.0);
+}
+
+#[test]
+fn test_counters_are_exported() {
+    let cache = TokenCache::default();
+    let metrics = Metrics::new();
+    cache.register(&metrics).unwrap();
+
+    cache.insert("alice", valid("alice"));
+    cache.get("alice");
+    cache.get("unknown");
+    cache.invalidate("alice");
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("token_cache_lookups_total{result=\"hit\"} 1"));
+    assert!(rendered.contains("token_cache_lookups_total{result=\"miss\"} 1"));
+    assert!(rendered.contains("token_cache_invalidations_total 1"));
+    assert!(rendered.contains("token_cache_entries 0"));
+}
@@ -0,0 +1,70 @@
+use core_rs::{error::ServiceError, username::NormalizedUsername, Username};
+
+fn normalize(raw: &str) -> String {
+    NormalizedUsername::parse(raw)
+        .unwrap_or_else(|err| panic!("'{}' should be valid: {:?}", raw, err))
+        .to_string()
+}
+
+#[test]
+fn test_case_and_whitespace_are_normalized() {
+    assert_eq!(normalize("Alice"), "alice");
+    assert_eq!(normalize("alice "), "alice");
+    assert_eq!(normalize("  ALICE"), "alice");
+}
+
+#[test]
+fn test_unicode_is_normalized() {
+    // Fullwidth letters and ligatures are NFKC normalized
+    assert_eq!(normalize("Ａｌｉｃｅ"), "alice");
+    assert_eq!(normalize("ﬁona"), "fiona");
+    // Case folding handles characters without a simple lowercase mapping
+    assert_eq!(normalize("STRASSE"), normalize("straße"));
+    // Composed and decomposed forms are equal
+    assert_eq!(normalize("Zoe\u{0308}"), normalize("Zoë"));
+}
+
+#[test]
+fn test_allowed_characters() {
+    assert_eq!(normalize("bob_the-builder.2"), "bob_the-builder.2");
+    assert_eq!(normalize("нікіта"), "нікіта");
+}
+
+#[test]
+fn test_invalid_usernames_are_rejected() {
+    for raw in [
+        "",
+        "ab",
+        "  ab  ",
+        "a/b/c",
+        "alice bob",
+        "..",
+        ".alice",
+        "-alice",
+        "al?ce",
+    ] {
+        assert!(
+            matches!(
+                NormalizedUsername::parse(raw),
+                Err(ServiceError::InvalidUsername(_))
+            ),
+            "'{}' should be rejected",
+            raw
+        );
+    }
+
+    assert!(NormalizedUsername::parse(&"a".repeat(33)).is_err());
+    assert!(NormalizedUsername::parse(&"a".repeat(32)).is_ok());
+}
+
+#[test]
+fn test_username_json_is_validated() {
+    let username: Username = serde_json::from_str(r#"{"username": "Alice"}"#).unwrap();
+    assert_eq!(username.username, "alice");
+    assert_eq!(
+        serde_json::to_string(&username).unwrap(),
+        r#"{"username":"alice"}"#
+    );
+
+    assert!(serde_json::from_str::<Username>(r#"{"username": "a/b"}"#).is_err());
+}
@@ -0,0 +1,150 @@
+use actix_web::{
+    http::{header, StatusCode},
+    post, test, App, HttpResponse,
+};
+use core_rs::{
+    config::DEFAULT_JSON_LIMIT,
+    create_json_cfg,
+    error::{FieldError, ServiceError},
+    validation::{ValidJson, Validate},
+    ProfilePicture,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Signup {
+    name: String,
+    age: u32,
+}
+
+impl Validate for Signup {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if self.name.is_empty() {
+            errors.push(FieldError::new(
+                "name",
+                "required",
+                "Name is required".to_string(),
+            ));
+        }
+
+        if self.age < 18 {
+            errors.push(FieldError::new(
+                "age",
+                "too_young",
+                "Must be 18".to_string(),
+            ));
+        }
+
+        errors
+    }
+}
+
+#[post("/signup")]
+async fn signup(signup: ValidJson<Signup>) -> HttpResponse {
+    HttpResponse::Ok().body(signup.into_inner().name)
+}
+
+fn post_json(body: serde_json::Value) -> test::TestRequest {
+    test::TestRequest::post().uri("/signup").set_json(body)
+}
+
+#[actix_web::test]
+async fn test_valid_bodies_are_accepted() {
+    let app = test::init_service(
+        App::new()
+            .app_data(create_json_cfg(DEFAULT_JSON_LIMIT))
+            .service(signup),
+    )
+    .await;
+
+    let req = post_json(serde_json::json!({ "name": "alice", "age": 30 })).to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(test::read_body(response).await, "alice");
+}
+
+#[actix_web::test]
+async fn test_invalid_fields_are_listed() {
+    let app = test::init_service(
+        App::new()
+            .app_data(create_json_cfg(DEFAULT_JSON_LIMIT))
+            .service(signup),
+    )
+    .await;
+
+    let req = post_json(serde_json::json!({ "name": "", "age": 12 })).to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let body: ServiceError = test::read_body_json(response).await;
+    assert_eq!(
+        body,
+        ServiceError::ValidationError(vec![
+            FieldError::new("name", "required", "Name is required".to_string()),
+            FieldError::new("age", "too_young", "Must be 18".to_string()),
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn test_malformed_bodies_are_still_parsing_errors() {
+    let app = test::init_service(
+        App::new()
+            .app_data(create_json_cfg(DEFAULT_JSON_LIMIT))
+            .service(signup),
+    )
+    .await;
+
+    let req = post_json(serde_json::json!({ "name": "alice" })).to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["error"]["type"], "JsonParsingError");
+}
+
+#[actix_web::test]
+async fn test_validation_errors_as_problem_details() {
+    let app = test::init_service(
+        App::new()
+            .app_data(create_json_cfg(DEFAULT_JSON_LIMIT))
+            .service(signup),
+    )
+    .await;
+
+    let req = post_json(serde_json::json!({ "name": "", "age": 30 }))
+        .insert_header((header::ACCEPT, "application/problem+json"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let body: serde_json::Value = test::read_body_json(response).await;
+    assert_eq!(body["type"], "urn:dirc:error:ValidationError");
+    assert_eq!(body["fields"][0]["field"], "name");
+    assert_eq!(body["fields"][0]["code"], "required");
+}
+
+#[actix_web::test]
+async fn test_profile_picture_must_be_a_url() {
+    let codes = |url: &str| {
+        ProfilePicture::from(url.to_string())
+            .validate()
+            .into_iter()
+            .map(|error| error.code)
+            .collect::<Vec<_>>()
+    };
+
+    assert!(codes("https://example.com/alice.png").is_empty());
+    assert_eq!(codes(" "), ["required"]);
+    assert_eq!(codes("javascript:alert(1)"), ["invalid_url"]);
+    assert_eq!(
+        codes(&format!("https://{}", "a".repeat(2048))),
+        ["too_long"]
+    );
+}
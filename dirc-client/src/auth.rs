@@ -0,0 +1,448 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use core_rs::{
+    auth_client::TokenAuthenticator,
+    config::Config,
+    error::ServiceError,
+    metrics::Metrics,
+    token::{self, TokenVerifier, JWKS_PATH},
+    token_cache::{CacheStats, CachedToken, TokenCache},
+    username::NormalizedUsername,
+    Username,
+};
+use jsonwebtoken::jwk::JwkSet;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::http::HttpClient;
+
+pub use core_rs::config::DEFAULT_AUTH_URL;
+
+/// How long the keys of the auth service are used before a token signed with an unknown key
+/// makes them be fetched again, so forged key ids can't make every request fetch them
+pub const KEYS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Body of a login or registration
+#[derive(Serialize)]
+struct LoginInfo<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+/// A signed short-lived access token issued together with a session
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessToken {
+    pub access_token: String,
+    pub token_type: String,
+    /// Lifetime of the token in seconds
+    pub expires_in: u64,
+}
+
+/// A session of a logged in user. The token is sent as bearer token to authenticate requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionToken {
+    pub username: String,
+    pub token: String,
+    /// The access token issued with the session, if the auth service signs them
+    #[serde(flatten)]
+    pub access_token: Option<AccessToken>,
+}
+
+/// Returned by a login with a correct password when the user has two-factor authentication
+/// enabled
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecondFactorChallenge {
+    pub second_factor_required: bool,
+    pub challenge_token: String,
+    /// Lifetime of the challenge token in seconds
+    pub expires_in: u64,
+}
+
+/// The result of a successful password check on login
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    /// The user is logged in
+    Session(SessionToken),
+    /// The user has to provide a second factor to log in
+    SecondFactorRequired(SecondFactorChallenge),
+}
+
+impl LoginResponse {
+    /// Returns the session token if no second factor is required
+    pub fn into_session_token(self) -> Option<SessionToken> {
+        match self {
+            LoginResponse::Session(session_token) => Some(session_token),
+            LoginResponse::SecondFactorRequired(_) => None,
+        }
+    }
+}
+
+/// Typed client for the HTTP API of the auth service.
+///
+/// Token validation results can be kept in a [`TokenCache`] shared by every clone of the
+/// client. The auth service doesn't announce revoked sessions, so only logouts sent through the
+/// same client invalidate its entries. A session ended any other way, e.g. by a user logging out
+/// directly against the auth service, stays accepted until its cache entry expires, for up to
+/// [`DEFAULT_TTL`](core_rs::token_cache::DEFAULT_TTL) unless configured otherwise.
+///
+/// Access tokens are verified locally against the public keys of the auth service, which are
+/// fetched when the first access token is seen and again when a token is signed with a key
+/// that is not known yet.
+#[derive(Clone, Debug)]
+pub struct AuthApi {
+    http: HttpClient,
+    cache: Option<Arc<TokenCache>>,
+    keys: Arc<Mutex<VerificationKeys>>,
+}
+
+/// The keys access tokens are verified with, shared by every clone of the client
+#[derive(Debug, Default)]
+struct VerificationKeys {
+    verifier: Option<TokenVerifier>,
+    fetched_at: Option<Instant>,
+}
+
+impl AuthApi {
+    /// Creates a new AuthApi for the auth service at the given base url, e.g. `http://auth:8080`,
+    /// without a token cache
+    pub fn new(base_url: &str) -> Self {
+        AuthApi {
+            http: HttpClient::new(base_url),
+            cache: None,
+            keys: Arc::default(),
+        }
+    }
+
+    /// Creates a new AuthApi from the `services` and `token_cache` sections of the
+    /// configuration. A cache capacity of 0 disables the cache.
+    pub fn from_config(config: &Config) -> Self {
+        let services = &config.services;
+        let cache = &config.token_cache;
+
+        let api = AuthApi::new(&services.auth_url)
+            .with_timeout(Duration::from_secs(services.auth_timeout))
+            .with_retries(services.auth_retries);
+
+        if cache.capacity == 0 {
+            api
+        } else {
+            api.with_cache(TokenCache::new(
+                Duration::from_secs(cache.ttl),
+                Duration::from_secs(cache.negative_ttl),
+                cache.capacity,
+            ))
+        }
+    }
+
+    /// Loads the configuration, see [`Config::load`], and creates a new AuthApi from it
+    ///
+    /// # Errors
+    /// Fails if the configuration is invalid
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(AuthApi::from_config(&Config::load()?))
+    }
+
+    /// Sets the base url of the auth service, e.g. from the configuration of the calling service
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.http = self.http.with_base_url(base_url);
+        self
+    }
+
+    /// Sets how long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http = self.http.with_timeout(timeout);
+        self
+    }
+
+    /// Sets how often a failed `GET` request is retried
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.http = self.http.with_retries(retries);
+        self
+    }
+
+    /// Sets the cache of token validation results
+    pub fn with_cache(mut self, cache: TokenCache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Disables caching, validating every token with the auth service
+    pub fn without_cache(mut self) -> Self {
+        self.cache = None;
+        self
+    }
+
+    /// Returns the base url of the auth service
+    pub fn base_url(&self) -> &String {
+        self.http.base_url()
+    }
+
+    /// Returns the counters of the token cache, if caching is enabled
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Adds the counters of the token cache to the metrics of the service, if caching is
+    /// enabled, see [`TokenCache::register`]
+    ///
+    /// # Errors
+    /// Fails if metrics with the same names are already registered
+    pub fn register_cache_metrics(&self, metrics: &Metrics) -> prometheus::Result<()> {
+        match &self.cache {
+            Some(cache) => cache.register(metrics),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes a token from the cache, e.g. when the service learned of its logout some other way
+    pub fn invalidate(&self, token: &str) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(token);
+        }
+    }
+
+    /// Removes every token of a user from the cache
+    pub fn invalidate_user(&self, username: &NormalizedUsername) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_user(username);
+        }
+    }
+
+    /// Logs in a user, `POST /login`
+    ///
+    /// # Errors
+    /// `ServiceError::UserNotFound`, `ServiceError::InvalidPassword` or
+    /// `ServiceError::InvalidCredentials` if the username or password is wrong
+    /// `ServiceError::TooManyAttempts` if too many logins failed recently
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn login(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<LoginResponse, ServiceError> {
+        let request = self.http.json_request(
+            Method::POST,
+            "/login",
+            None,
+            &LoginInfo { username, password },
+        )?;
+
+        self.http.send(request).await
+    }
+
+    /// Registers a new user and logs them in, `PUT /register`
+    ///
+    /// # Errors
+    /// `ServiceError::UsernameTaken` if the username is already in use
+    /// `ServiceError::InvalidUsername` if the username is not allowed
+    /// `ServiceError::PasswordPolicyViolation` if the password is too weak
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn register(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<SessionToken, ServiceError> {
+        let request = self.http.json_request(
+            Method::PUT,
+            "/register",
+            None,
+            &LoginInfo { username, password },
+        )?;
+
+        self.http.send(request).await
+    }
+
+    /// Returns the user owning a session token, `GET /authenticate`, from the cache if possible.
+    /// Access tokens are verified locally instead, see [`AuthApi::verify_access_token`].
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is invalid
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn authenticate(&self, token: &str) -> Result<NormalizedUsername, ServiceError> {
+        if token::is_access_token(token) {
+            return self.verify_access_token(token).await;
+        }
+
+        match self.cache.as_ref().and_then(|cache| cache.get(token)) {
+            Some(CachedToken::Valid(username)) => return Ok(username),
+            Some(CachedToken::Invalid) => return Err(ServiceError::AuthenticationError),
+            None => {}
+        }
+
+        let request = self.http.request(Method::GET, "/authenticate", Some(token));
+
+        let result = self
+            .http
+            .send(request)
+            .await
+            .map(|Username { username }| username);
+
+        if let Some(cache) = &self.cache {
+            // Only the token being rejected is cached, other errors are likely to go away
+            match &result {
+                Ok(username) => cache.insert(token, CachedToken::Valid(username.clone())),
+                Err(ServiceError::AuthenticationError) => cache.insert(token, CachedToken::Invalid),
+                Err(_) => {}
+            }
+        }
+
+        result
+    }
+
+    /// Verifies an access token against the public keys of the auth service and returns the
+    /// user it was issued to, fetching the keys if needed, see [`AuthApi::jwks`]
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is malformed, expired or not signed by
+    /// the auth service
+    /// `ServiceError::ServiceUnavailable` if the keys can't be fetched
+    pub async fn verify_access_token(
+        &self,
+        token: &str,
+    ) -> Result<NormalizedUsername, ServiceError> {
+        let mut keys = self.keys.lock().await;
+
+        let known = matches!(&keys.verifier, Some(verifier) if verifier.knows_key_of(token));
+        let recently_fetched = keys
+            .fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() < KEYS_REFRESH_INTERVAL);
+
+        if !known && !recently_fetched {
+            let jwks = self.jwks().await?;
+
+            keys.verifier = Some(TokenVerifier::new(&jwks).map_err(|err| {
+                ServiceError::ServiceUnavailable(format!(
+                    "Unusable keys of the auth service: {}",
+                    err
+                ))
+            })?);
+            keys.fetched_at = Some(Instant::now());
+        }
+
+        let verifier = keys
+            .verifier
+            .as_ref()
+            .ok_or(ServiceError::AuthenticationError)?;
+
+        Ok(verifier.verify(token)?.username()?.username)
+    }
+
+    /// Returns the public keys access tokens are signed with, `GET /.well-known/jwks.json`
+    ///
+    /// # Errors
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn jwks(&self) -> Result<JwkSet, ServiceError> {
+        let request = self.http.request(Method::GET, JWKS_PATH, None);
+
+        self.http.send(request).await
+    }
+
+    /// Checks that a session token belongs to the given user, `POST /authorize`. Access tokens
+    /// are verified locally instead.
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is invalid
+    /// `ServiceError::AuthorizationError` if the token belongs to another user
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn authorize(
+        &self,
+        token: &str,
+        username: &NormalizedUsername,
+    ) -> Result<NormalizedUsername, ServiceError> {
+        if token::is_access_token(token) {
+            let token_username = self.verify_access_token(token).await?;
+
+            return if &token_username == username {
+                Ok(token_username)
+            } else {
+                Err(ServiceError::AuthorizationError)
+            };
+        }
+
+        let request = self.http.json_request(
+            Method::POST,
+            "/authorize",
+            Some(token),
+            &Username::from(username.clone()),
+        )?;
+
+        let Username { username } = self.http.send(request).await?;
+
+        Ok(username)
+    }
+
+    /// Ends the session of a token, `GET /logout`, and removes it from the cache
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is invalid
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn logout(&self, token: &str) -> Result<(), ServiceError> {
+        let request = self.http.request(Method::GET, "/logout", Some(token));
+
+        let result = self.http.send(request).await;
+
+        // Invalid tokens aren't cached as valid either
+        self.invalidate(token);
+
+        result
+    }
+
+    /// Ends every session of the user owning a token, `GET /logout_all`, and removes them from
+    /// the cache. The user is taken from the response, as the cache may still hold a token that
+    /// was already logged out.
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is invalid
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn logout_all(&self, token: &str) -> Result<NormalizedUsername, ServiceError> {
+        let request = self.http.request(Method::GET, "/logout_all", Some(token));
+
+        let result = self
+            .http
+            .send(request)
+            .await
+            .map(|Username { username }| username);
+
+        match &result {
+            Ok(username) => self.invalidate_user(username),
+            // Invalid tokens aren't cached as valid either
+            Err(_) => self.invalidate(token),
+        }
+
+        result
+    }
+
+    /// Returns whether a user exists, `GET /user_exists`
+    ///
+    /// # Errors
+    /// `ServiceError::ServiceUnavailable` if the auth service can't be reached
+    /// Any other error returned by the auth service
+    pub async fn user_exists(&self, username: &str) -> Result<bool, ServiceError> {
+        let request = self
+            .http
+            .request(Method::GET, "/user_exists", None)
+            .query(&[("username", username)]);
+
+        self.http.send(request).await
+    }
+}
+
+#[async_trait]
+impl TokenAuthenticator for AuthApi {
+    async fn authenticate(&self, token: &str) -> Result<NormalizedUsername, ServiceError> {
+        AuthApi::authenticate(self, token).await
+    }
+}
@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use core_rs::{
+    error::{ServiceError, ServiceErrorJSON},
+    request_id::{RequestId, REQUEST_ID_HEADER},
+    telemetry,
+};
+use reqwest::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION},
+    Method, Request, RequestBuilder, StatusCode,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::Instrument;
+
+pub use core_rs::config::{DEFAULT_RETRIES, DEFAULT_TIMEOUT};
+
+/// Delay before the first retry, doubled for every further one
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The connection to one service, shared by the typed API clients.
+///
+/// `GET` requests failing to connect, timing out or getting a 5xx response without a JSON error
+/// body are retried with exponential backoff. Other requests are sent once, as they might have
+/// taken effect.
+#[derive(Clone, Debug)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    base_url: String,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl HttpClient {
+    pub(crate) fn new(base_url: &str) -> Self {
+        HttpClient {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+        }
+    }
+
+    pub(crate) fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub(crate) fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub(crate) fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub(crate) fn base_url(&self) -> &String {
+        &self.base_url
+    }
+
+    /// Starts a request to a path of the service, authenticated with a session token if given.
+    /// Carries the id of the request being handled, if any.
+    pub(crate) fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+    ) -> RequestBuilder {
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path))
+            .timeout(self.timeout);
+
+        if let Some(request_id) = RequestId::current() {
+            request = request.header(REQUEST_ID_HEADER.as_str(), request_id.as_str());
+        }
+
+        match token {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    /// Starts a request with a JSON body
+    pub(crate) fn json_request<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: &B,
+    ) -> Result<RequestBuilder, ServiceError> {
+        let body = serde_json::to_vec(body)
+            .map_err(|err| ServiceError::JsonParsingError(err.to_string()))?;
+
+        Ok(self
+            .request(method, path, token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body))
+    }
+
+    /// Sends a request and decodes the JSON body of the response, retrying `GET` requests if
+    /// they fail
+    ///
+    /// # Errors
+    /// The error returned by the service, decoded from its JSON body
+    /// `ServiceError::ServiceUnavailable` if the service can't be reached or returns an
+    /// unexpected response
+    pub(crate) async fn send<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T, ServiceError> {
+        let request = request.build().map_err(|err| {
+            ServiceError::ServiceUnavailable(format!(
+                "Request to {} failed: {}",
+                self.base_url, err
+            ))
+        })?;
+
+        let retries = if request.method() == Method::GET {
+            self.retries
+        } else {
+            0
+        };
+        let mut attempt = 0;
+
+        loop {
+            // Bodies of GET requests are never streamed, so they can always be cloned
+            let next = request.try_clone().expect("Request body can't be cloned");
+
+            match self.try_send(next).await {
+                Err(Attempt::Retryable(reason)) if attempt < retries => {
+                    tracing::warn!("Request to {} failed, retrying: {}", self.base_url, reason);
+
+                    tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                Err(Attempt::Retryable(reason)) => {
+                    return Err(ServiceError::ServiceUnavailable(format!(
+                        "Request to {} failed: {}",
+                        self.base_url, reason
+                    )))
+                }
+                Err(Attempt::Failed(err)) => return Err(err),
+                Ok(response) => return Ok(response),
+            }
+        }
+    }
+
+    /// Sends a request once in a span continued by the service and decodes the JSON body of the
+    /// response
+    async fn try_send<T: DeserializeOwned>(&self, mut request: Request) -> Result<T, Attempt> {
+        let span = telemetry::client_span(
+            request.method().as_str(),
+            request.url().as_str(),
+            request.url().path(),
+        );
+
+        for (name, value) in telemetry::trace_context_headers(&span) {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                request.headers_mut().insert(name, value);
+            }
+        }
+
+        let response = self.client.execute(request).instrument(span.clone()).await;
+
+        telemetry::record_client_response(
+            &span,
+            response
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16()),
+        );
+
+        let response = response.map_err(|err| Attempt::Retryable(err.to_string()))?;
+
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| Attempt::Retryable(err.to_string()))?;
+
+        if status == StatusCode::OK {
+            return serde_json::from_slice(&body).map_err(|err| {
+                Attempt::Failed(ServiceError::ServiceUnavailable(format!(
+                    "Unexpected response from {}: {}",
+                    self.base_url, err
+                )))
+            });
+        }
+
+        match serde_json::from_slice::<ServiceErrorJSON>(&body) {
+            Ok(error) => Err(Attempt::Failed(error.into())),
+            Err(_) if status.is_server_error() => Err(Attempt::Retryable(format!(
+                "{} responded with {}",
+                self.base_url, status
+            ))),
+            Err(_) => Err(Attempt::Failed(ServiceError::ServiceUnavailable(format!(
+                "Unexpected response from {}: {}",
+                self.base_url, status
+            )))),
+        }
+    }
+}
+
+/// Outcome of a failed request
+enum Attempt {
+    /// The request might succeed if it is tried again
+    Retryable(String),
+    /// The request failed for good
+    Failed(ServiceError),
+}
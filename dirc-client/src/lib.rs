@@ -0,0 +1,60 @@
+//! Typed async clients for the HTTP APIs of the auth and users services.
+//!
+//! Error responses are decoded into [`ServiceError`](core_rs::error::ServiceError), so callers
+//! can match on the same errors the services return. Requests that can't be sent, time out or
+//! get a response that isn't understood fail with `ServiceError::ServiceUnavailable`. Failed
+//! `GET` requests are retried first.
+
+use std::time::Duration;
+
+use core_rs::config::Config;
+
+pub mod auth;
+mod http;
+pub mod users;
+
+pub use auth::{AccessToken, AuthApi, LoginResponse, SecondFactorChallenge, SessionToken};
+pub use http::{DEFAULT_RETRIES, DEFAULT_TIMEOUT};
+pub use users::{User, UsersApi};
+
+/// Clients for both the auth and the users service
+#[derive(Clone, Debug)]
+pub struct DircClient {
+    pub auth: AuthApi,
+    pub users: UsersApi,
+}
+
+impl DircClient {
+    /// Creates a new DircClient for the services at the given base urls
+    pub fn new(auth_url: &str, users_url: &str) -> Self {
+        DircClient {
+            auth: AuthApi::new(auth_url),
+            users: UsersApi::new(users_url),
+        }
+    }
+
+    /// Creates new clients for both services from the configuration, see
+    /// [`AuthApi::from_config`] and [`UsersApi::from_config`]
+    pub fn from_config(config: &Config) -> Self {
+        DircClient {
+            auth: AuthApi::from_config(config),
+            users: UsersApi::from_config(config),
+        }
+    }
+
+    /// Loads the configuration, see [`Config::load`], and creates new clients from it
+    ///
+    /// # Errors
+    /// Fails if the configuration is invalid
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(DircClient::from_config(&Config::load()?))
+    }
+
+    /// Sets how long a single request to either service can take
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        DircClient {
+            auth: self.auth.with_timeout(timeout),
+            users: self.users.with_timeout(timeout),
+        }
+    }
+}
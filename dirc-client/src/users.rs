@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use core_rs::{config::Config, error::ServiceError, username::NormalizedUsername, ProfilePicture};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::http::HttpClient;
+
+pub use core_rs::config::DEFAULT_USERS_URL;
+
+/// The public profile of a user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub username: String,
+    pub profile_picture: String,
+}
+
+/// Typed client for the HTTP API of the users service
+#[derive(Clone, Debug)]
+pub struct UsersApi {
+    http: HttpClient,
+}
+
+impl UsersApi {
+    /// Creates a new UsersApi for the users service at the given base url, e.g.
+    /// `http://users:8080`
+    pub fn new(base_url: &str) -> Self {
+        UsersApi {
+            http: HttpClient::new(base_url),
+        }
+    }
+
+    /// Creates a new UsersApi from the `services` section of the configuration
+    pub fn from_config(config: &Config) -> Self {
+        let services = &config.services;
+
+        UsersApi::new(&services.users_url)
+            .with_timeout(Duration::from_secs(services.users_timeout))
+            .with_retries(services.users_retries)
+    }
+
+    /// Loads the configuration, see [`Config::load`], and creates a new UsersApi from it
+    ///
+    /// # Errors
+    /// Fails if the configuration is invalid
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(UsersApi::from_config(&Config::load()?))
+    }
+
+    /// Sets the base url of the users service, e.g. from the configuration of the calling service
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.http = self.http.with_base_url(base_url);
+        self
+    }
+
+    /// Sets how long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http = self.http.with_timeout(timeout);
+        self
+    }
+
+    /// Sets how often a failed `GET` request is retried
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.http = self.http.with_retries(retries);
+        self
+    }
+
+    /// Returns the base url of the users service
+    pub fn base_url(&self) -> &String {
+        self.http.base_url()
+    }
+
+    /// Returns whether a user has a profile, `GET /{username}/exists`
+    ///
+    /// # Errors
+    /// `ServiceError::ServiceUnavailable` if the users service can't be reached
+    /// Any other error returned by the users service
+    pub async fn exists(&self, username: &NormalizedUsername) -> Result<bool, ServiceError> {
+        let request = self
+            .http
+            .request(Method::GET, &format!("/{}/exists", username), None);
+
+        self.http.send(request).await
+    }
+
+    /// Returns the profile of a user, `GET /{username}/info`
+    ///
+    /// # Errors
+    /// `ServiceError::UserNotFound` if the user has no profile
+    /// `ServiceError::ServiceUnavailable` if the users service can't be reached
+    /// Any other error returned by the users service
+    pub async fn info(&self, username: &NormalizedUsername) -> Result<User, ServiceError> {
+        let request = self
+            .http
+            .request(Method::GET, &format!("/{}/info", username), None);
+
+        self.http.send(request).await
+    }
+
+    /// Sets the profile picture of a user, authenticated with their session token,
+    /// `POST /{username}/info`
+    ///
+    /// # Errors
+    /// `ServiceError::AuthenticationError` if the token is invalid or belongs to another user
+    /// `ServiceError::ServiceUnavailable` if the users service can't be reached
+    /// Any other error returned by the users service
+    pub async fn save_info(
+        &self,
+        token: &str,
+        username: &NormalizedUsername,
+        profile_picture: &ProfilePicture,
+    ) -> Result<(), ServiceError> {
+        let request = self.http.json_request(
+            Method::POST,
+            &format!("/{}/info", username),
+            Some(token),
+            profile_picture,
+        )?;
+
+        self.http.send(request).await
+    }
+
+    /// Creates the profile of a newly registered user, `PUT /{username}/info`
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if the profile can't be stored
+    /// `ServiceError::ServiceUnavailable` if the users service can't be reached
+    /// Any other error returned by the users service
+    pub async fn create_info(
+        &self,
+        username: &NormalizedUsername,
+        profile_picture: &ProfilePicture,
+    ) -> Result<(), ServiceError> {
+        let request = self.http.json_request(
+            Method::PUT,
+            &format!("/{}/info", username),
+            None,
+            profile_picture,
+        )?;
+
+        self.http.send(request).await
+    }
+}
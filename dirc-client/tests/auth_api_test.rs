@@ -0,0 +1,299 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer};
+use core_rs::{auth_client::extract_bearer_token, error::ServiceError, token_cache::TokenCache};
+use dirc_client::AuthApi;
+use opentelemetry::{
+    global,
+    trace::{SpanKind, TraceContextExt, TracerProvider},
+    Value,
+};
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+};
+use tracing::{subscriber::DefaultGuard, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// What the fake auth service saw, shared with the tests
+#[derive(Default)]
+struct Received {
+    calls: AtomicUsize,
+    traceparent: Mutex<Option<String>>,
+}
+
+/// Stand-in for the auth service, answering based on the token
+#[get("/authenticate")]
+async fn fake_authenticate(req: HttpRequest, received: web::Data<Received>) -> HttpResponse {
+    let call = received.calls.fetch_add(1, Ordering::SeqCst);
+
+    *received.traceparent.lock().unwrap() = req
+        .headers()
+        .get("traceparent")
+        .map(|value| value.to_str().unwrap().to_string());
+
+    match extract_bearer_token(&req).unwrap().as_str() {
+        "valid" => HttpResponse::Ok().json(serde_json::json!({ "username": "alice" })),
+        "flaky" if call == 0 => HttpResponse::ServiceUnavailable().body("starting up"),
+        "flaky" => HttpResponse::Ok().json(serde_json::json!({ "username": "bob" })),
+        "broken" => HttpResponse::InternalServerError().body("oops"),
+        "garbage" => HttpResponse::Ok().body("not json"),
+        _ => HttpResponse::Unauthorized().json(ServiceError::AuthenticationError),
+    }
+}
+
+#[get("/logout")]
+async fn fake_logout(received: web::Data<Received>) -> HttpResponse {
+    received.calls.fetch_add(1, Ordering::SeqCst);
+
+    HttpResponse::Ok().json(())
+}
+
+#[get("/logout_all")]
+async fn fake_logout_all(req: HttpRequest, received: web::Data<Received>) -> HttpResponse {
+    received.calls.fetch_add(1, Ordering::SeqCst);
+
+    match extract_bearer_token(&req).unwrap().as_str() {
+        "valid" | "other" => HttpResponse::Ok().json(serde_json::json!({ "username": "alice" })),
+        _ => HttpResponse::Unauthorized().json(ServiceError::AuthenticationError),
+    }
+}
+
+/// Starts the fake auth service, returning its address and what it received
+fn start_fake_auth() -> (SocketAddr, web::Data<Received>) {
+    let received = web::Data::new(Received::default());
+    let data = received.clone();
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .service(fake_authenticate)
+            .service(fake_logout)
+            .service(fake_logout_all)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+
+    actix_web::rt::spawn(server.run());
+
+    (addr, received)
+}
+
+fn client(addr: SocketAddr) -> AuthApi {
+    AuthApi::new(&format!("http://{}/", addr))
+        .with_timeout(Duration::from_secs(2))
+        .with_cache(TokenCache::default())
+}
+
+fn calls(received: &Received) -> usize {
+    received.calls.load(Ordering::SeqCst)
+}
+
+#[actix_web::test]
+async fn test_client_retries_server_errors() {
+    let (addr, received) = start_fake_auth();
+
+    let username = client(addr).authenticate("flaky").await.unwrap();
+
+    assert_eq!(username.to_string(), "bob");
+    assert_eq!(calls(&received), 2);
+
+    let err = client(addr)
+        .with_retries(1)
+        .authenticate("broken")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+    assert_eq!(calls(&received), 4);
+}
+
+#[actix_web::test]
+async fn test_client_maps_unexpected_responses() {
+    let (addr, received) = start_fake_auth();
+
+    let err = client(addr).authenticate("garbage").await.unwrap_err();
+
+    assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+    assert_eq!(calls(&received), 1, "Should not be retried");
+}
+
+#[actix_web::test]
+async fn test_unreachable_auth_service_is_unavailable() {
+    // Nothing listens on the discard port
+    let client = AuthApi::new("http://127.0.0.1:9")
+        .with_timeout(Duration::from_millis(500))
+        .with_retries(1);
+
+    let err = client.authenticate("valid").await.unwrap_err();
+
+    assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+}
+
+#[actix_web::test]
+async fn test_client_caches_valid_tokens() {
+    let (addr, received) = start_fake_auth();
+    let client = client(addr);
+
+    for _ in 0..3 {
+        let username = client.authenticate("valid").await.unwrap();
+        assert_eq!(username.to_string(), "alice");
+    }
+
+    assert_eq!(calls(&received), 1);
+
+    let stats = client.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.size), (2, 1, 1));
+    assert!((stats.hit_rate() - 2.0 / 3.0).abs() < f64::EPSILON);
+
+    // Clones share the cache
+    client.clone().authenticate("valid").await.unwrap();
+    assert_eq!(calls(&received), 1);
+}
+
+#[actix_web::test]
+async fn test_client_caches_invalid_tokens_briefly() {
+    let (addr, received) = start_fake_auth();
+    let client = client(addr).with_cache(TokenCache::new(
+        Duration::from_secs(30),
+        Duration::from_millis(100),
+        10,
+    ));
+
+    for _ in 0..2 {
+        let err = client.authenticate("invalid").await.unwrap_err();
+        assert!(matches!(err, ServiceError::AuthenticationError));
+    }
+
+    assert_eq!(calls(&received), 1);
+    assert_eq!(client.cache_stats().unwrap().negative_hits, 1);
+
+    actix_web::rt::time::sleep(Duration::from_millis(150)).await;
+
+    client.authenticate("invalid").await.unwrap_err();
+    assert_eq!(calls(&received), 2);
+}
+
+#[actix_web::test]
+async fn test_client_does_not_cache_unavailable_auth_service() {
+    let (addr, received) = start_fake_auth();
+    let client = client(addr).with_retries(0);
+
+    for _ in 0..2 {
+        let err = client.authenticate("broken").await.unwrap_err();
+        assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+    }
+
+    assert_eq!(calls(&received), 2);
+    assert_eq!(client.cache_stats().unwrap().size, 0);
+}
+
+#[actix_web::test]
+async fn test_logout_invalidates_cached_tokens() {
+    let (addr, received) = start_fake_auth();
+    let client = client(addr);
+
+    client.authenticate("valid").await.unwrap();
+    client.logout("valid").await.unwrap();
+    assert_eq!(client.cache_stats().unwrap().size, 0);
+
+    client.authenticate("valid").await.unwrap();
+    assert_eq!(calls(&received), 3);
+
+    // Logging out everywhere drops all tokens of the user named by the auth service, even when
+    // the token used is not cached
+    client.logout_all("other").await.unwrap();
+    assert_eq!(calls(&received), 4);
+
+    let stats = client.cache_stats().unwrap();
+    assert_eq!((stats.size, stats.invalidations), (0, 2));
+}
+
+#[actix_web::test]
+async fn test_client_without_cache_always_asks_auth_service() {
+    let (addr, received) = start_fake_auth();
+    let client = client(addr).without_cache();
+
+    client.authenticate("valid").await.unwrap();
+    client.authenticate("valid").await.unwrap();
+
+    assert_eq!(calls(&received), 2);
+    assert_eq!(client.cache_stats(), None);
+}
+
+/// Exporter keeping the spans in memory
+#[derive(Clone, Debug, Default)]
+struct MemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for MemoryExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        self.0.lock().unwrap().extend(batch);
+
+        Ok(())
+    }
+}
+
+/// Exports the spans of the current thread to memory until the guard is dropped
+fn trace_to_memory() -> (MemoryExporter, DefaultGuard) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let exporter = MemoryExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    (exporter, tracing::subscriber::set_default(subscriber))
+}
+
+#[actix_web::test]
+async fn test_client_propagates_the_trace() {
+    let (addr, received) = start_fake_auth();
+    let (exporter, _guard) = trace_to_memory();
+
+    let client = client(addr).without_cache();
+
+    let parent = tracing::info_span!("register");
+    let trace_id = parent.context().span().span_context().trace_id();
+
+    let err = client
+        .authenticate("expired")
+        .instrument(parent)
+        .await
+        .unwrap_err();
+
+    assert_eq!(err, ServiceError::AuthenticationError);
+
+    let traceparent = received.traceparent.lock().unwrap().clone().unwrap();
+    assert!(traceparent.starts_with(&format!("00-{}-", trace_id)));
+
+    // The request has its own client span, which is the parent on the auth side
+    let spans = exporter.0.lock().unwrap().clone();
+    let client_span = spans
+        .iter()
+        .find(|span| span.span_kind == SpanKind::Client)
+        .unwrap();
+
+    assert_eq!(client_span.name, "GET /authenticate");
+    assert!(traceparent.contains(&client_span.span_context.span_id().to_string()));
+
+    let status_code = client_span
+        .attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == "http.response.status_code")
+        .map(|attribute| &attribute.value);
+    assert_eq!(status_code, Some(&Value::I64(401)));
+}
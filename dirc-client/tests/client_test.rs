@@ -0,0 +1,319 @@
+use std::{net::SocketAddr, time::Duration};
+
+use actix_web::{get, post, put, test, web, App, HttpRequest, HttpResponse, HttpServer};
+use core_rs::{
+    auth_client::extract_bearer_token,
+    config::Config,
+    error::ServiceError,
+    request_id::{AssignRequestId, REQUEST_ID_HEADER},
+    username::NormalizedUsername,
+    ProfilePicture, Username,
+};
+use dirc_client::{AccessToken, DircClient, LoginResponse, SessionToken, User};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct LoginInfo {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct UserExistsParams {
+    username: String,
+}
+
+/// Stand-in for the auth service login, requiring a second factor for users starting with "2fa"
+#[post("/login")]
+async fn fake_login(info: web::Json<LoginInfo>) -> HttpResponse {
+    if info.password != "secret" {
+        return HttpResponse::Unauthorized().json(ServiceError::InvalidPassword);
+    }
+
+    if info.username.starts_with("2fa") {
+        return HttpResponse::Ok().json(serde_json::json!({
+            "second_factor_required": true,
+            "challenge_token": "challenge",
+            "expires_in": 300,
+        }));
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "username": info.username,
+        "token": "session",
+        "access_token": "jwt",
+        "token_type": "Bearer",
+        "expires_in": 900,
+    }))
+}
+
+#[put("/register")]
+async fn fake_register(info: web::Json<LoginInfo>) -> HttpResponse {
+    HttpResponse::BadRequest().json(ServiceError::UsernameTaken(info.username.clone()))
+}
+
+#[get("/authenticate")]
+async fn fake_authenticate(req: HttpRequest) -> HttpResponse {
+    match extract_bearer_token(&req).as_deref() {
+        Ok("session") => HttpResponse::Ok().json(serde_json::json!({ "username": "alice" })),
+        _ => HttpResponse::Unauthorized().json(ServiceError::AuthenticationError),
+    }
+}
+
+#[post("/authorize")]
+async fn fake_authorize(username: web::Json<Username>) -> HttpResponse {
+    if username.username.as_str() == "alice" {
+        HttpResponse::Ok().json(username.into_inner())
+    } else {
+        HttpResponse::Forbidden().json(ServiceError::AuthorizationError)
+    }
+}
+
+#[get("/logout")]
+async fn fake_logout() -> HttpResponse {
+    HttpResponse::Ok().json(())
+}
+
+#[get("/user_exists")]
+async fn fake_user_exists(params: web::Query<UserExistsParams>) -> HttpResponse {
+    HttpResponse::Ok().json(params.username == "alice")
+}
+
+#[get("/{username}/info")]
+async fn fake_info(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+    let username = path.into_inner();
+
+    if username == "slow" {
+        actix_web::rt::time::sleep(Duration::from_secs(2)).await;
+    }
+
+    // Answers with the request id it got as the profile picture
+    if username == "trace" {
+        let request_id = req
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+
+        return HttpResponse::Ok().json(serde_json::json!({
+            "username": username,
+            "profilePicture": request_id,
+        }));
+    }
+
+    if username == "alice" {
+        HttpResponse::Ok().json(serde_json::json!({
+            "username": username,
+            "profilePicture": "alice.png",
+        }))
+    } else {
+        HttpResponse::NotFound().json(ServiceError::UserNotFound(username))
+    }
+}
+
+#[post("/{username}/info")]
+async fn fake_save_info(req: HttpRequest, picture: web::Json<ProfilePicture>) -> HttpResponse {
+    match extract_bearer_token(&req) {
+        Ok(token) if token == "session" && picture.profile_picture == "new.png" => {
+            HttpResponse::Ok().json(())
+        }
+        Ok(_) => HttpResponse::Unauthorized().json(ServiceError::AuthenticationError),
+        Err(err) => HttpResponse::BadRequest().json(err),
+    }
+}
+
+/// Starts a fake of both services on one address
+fn start_fake_services() -> SocketAddr {
+    let server = HttpServer::new(|| {
+        App::new()
+            .service(fake_login)
+            .service(fake_register)
+            .service(fake_authenticate)
+            .service(fake_authorize)
+            .service(fake_logout)
+            .service(fake_user_exists)
+            .service(fake_info)
+            .service(fake_save_info)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+
+    actix_web::rt::spawn(server.run());
+
+    addr
+}
+
+fn client(addr: SocketAddr) -> DircClient {
+    let url = format!("http://{}/", addr);
+
+    DircClient::new(&url, &url).with_timeout(Duration::from_secs(1))
+}
+
+fn alice() -> NormalizedUsername {
+    NormalizedUsername::parse("alice").unwrap()
+}
+
+#[actix_web::test]
+async fn test_clients_are_built_from_the_configuration() {
+    let mut config = Config::default();
+    config.services.auth_url = "http://127.0.0.1:8081/".to_string();
+
+    let client = DircClient::from_config(&config);
+    assert_eq!(client.auth.base_url(), "http://127.0.0.1:8081");
+    assert_eq!(client.users.base_url(), "http://users:8080");
+    assert!(client.auth.cache_stats().is_some());
+
+    // A capacity of 0 disables the token cache
+    config.token_cache.capacity = 0;
+    assert_eq!(DircClient::from_config(&config).auth.cache_stats(), None);
+}
+
+#[actix_web::test]
+async fn test_login_decodes_sessions_and_challenges() {
+    let client = client(start_fake_services());
+
+    let response = client.auth.login("alice", "secret").await.unwrap();
+
+    assert_eq!(
+        response.into_session_token(),
+        Some(SessionToken {
+            username: "alice".to_string(),
+            token: "session".to_string(),
+            access_token: Some(AccessToken {
+                access_token: "jwt".to_string(),
+                token_type: "Bearer".to_string(),
+                expires_in: 900,
+            }),
+        })
+    );
+
+    let response = client.auth.login("2fa-bob", "secret").await.unwrap();
+
+    match response {
+        LoginResponse::SecondFactorRequired(challenge) => {
+            assert_eq!(challenge.challenge_token, "challenge")
+        }
+        LoginResponse::Session(_) => panic!("Expected a second factor challenge"),
+    }
+}
+
+#[actix_web::test]
+async fn test_error_bodies_are_decoded() {
+    let client = client(start_fake_services());
+
+    let err = client.auth.login("alice", "wrong").await.unwrap_err();
+    assert!(matches!(err, ServiceError::InvalidPassword));
+
+    let err = client.auth.register("alice", "secret").await.unwrap_err();
+    assert!(matches!(err, ServiceError::UsernameTaken(username) if username == "alice"));
+
+    let err = client.auth.authenticate("expired").await.unwrap_err();
+    assert!(matches!(err, ServiceError::AuthenticationError));
+
+    let bob = NormalizedUsername::parse("bob").unwrap();
+
+    let err = client.auth.authorize("session", &bob).await.unwrap_err();
+    assert!(matches!(err, ServiceError::AuthorizationError));
+
+    let err = client.users.info(&bob).await.unwrap_err();
+    assert!(matches!(err, ServiceError::UserNotFound(username) if username == "bob"));
+
+    // The fake has no exists route, so its default 404 is not a service error
+    let err = client.users.exists(&bob).await.unwrap_err();
+    assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+}
+
+#[actix_web::test]
+async fn test_authenticated_requests_send_the_token() {
+    let client = client(start_fake_services());
+
+    assert_eq!(client.auth.authenticate("session").await.unwrap(), alice());
+    assert_eq!(
+        client.auth.authorize("session", &alice()).await.unwrap(),
+        alice()
+    );
+    client.auth.logout("session").await.unwrap();
+
+    let picture: ProfilePicture = "new.png".to_string().into();
+    client
+        .users
+        .save_info("session", &alice(), &picture)
+        .await
+        .unwrap();
+
+    let err = client
+        .users
+        .save_info("other", &alice(), &picture)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ServiceError::AuthenticationError));
+}
+
+#[actix_web::test]
+async fn test_queries_and_public_endpoints() {
+    let client = client(start_fake_services());
+
+    assert!(client.auth.user_exists("alice").await.unwrap());
+    assert!(!client.auth.user_exists("bob & carol").await.unwrap());
+
+    assert_eq!(
+        client.users.info(&alice()).await.unwrap(),
+        User {
+            username: "alice".to_string(),
+            profile_picture: "alice.png".to_string(),
+        }
+    );
+}
+
+#[actix_web::test]
+async fn test_timeouts_and_unreachable_services_are_unavailable() {
+    let addr = start_fake_services();
+    let url = format!("http://{}", addr);
+
+    // Nothing listens on the discard port
+    let client =
+        DircClient::new("http://127.0.0.1:9", &url).with_timeout(Duration::from_millis(200));
+
+    let err = client.auth.authenticate("session").await.unwrap_err();
+    assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+
+    let slow = NormalizedUsername::parse("slow").unwrap();
+
+    let err = client.users.info(&slow).await.unwrap_err();
+    assert!(matches!(err, ServiceError::ServiceUnavailable(_)));
+}
+
+#[get("/trace")]
+async fn traced_info(client: web::Data<DircClient>) -> HttpResponse {
+    let trace = NormalizedUsername::parse("trace").unwrap();
+
+    match client.users.info(&trace).await {
+        Ok(user) => HttpResponse::Ok().body(user.profile_picture),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+#[actix_web::test]
+async fn test_request_ids_are_propagated() {
+    let client = client(start_fake_services());
+
+    let app = test::init_service(
+        App::new()
+            .wrap(AssignRequestId)
+            .app_data(web::Data::new(client.clone()))
+            .service(traced_info),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/trace")
+        .insert_header((REQUEST_ID_HEADER, "register-42"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "register-42");
+
+    // Outside of a request no id is sent
+    let trace = NormalizedUsername::parse("trace").unwrap();
+    assert_eq!(client.users.info(&trace).await.unwrap().profile_picture, "");
+}
@@ -1,66 +1,51 @@
-use std::time::Duration;
+use core_rs::{error::ServiceError, username::NormalizedUsername, ProfilePicture};
+use mongodb::Client;
+use tracing::instrument;
 
-use core_rs::{error::ServiceError, ProfilePicture};
-use mongodb::{
-    bson::doc,
-    options::{ClientOptions, IndexOptions},
-    Client, Database, IndexModel,
+use crate::{
+    store::{MongoStore, UserStore},
+    User,
 };
 
-use crate::User;
-
+/// Users handles reading and updating user profiles.
+///
+/// It is generic over the storage backend, defaulting to MongoDB.
 #[derive(Clone, Debug)]
-pub struct Users {
-    client: Client,
-    database: Database,
+pub struct Users<S = MongoStore> {
+    store: S,
 }
 
-impl Users {
+impl Users<MongoStore> {
     pub async fn get_client(&self) -> Client {
-        self.client.clone()
+        self.store.client()
     }
 
-    /// Creates a new Authenticator instance given a mongodb url and database name
+    /// Creates a new Users instance given a mongodb url and database name
     ///
     /// # Errors
     /// Construction will fail if a database error occurs
     pub async fn new(url: String, db_name: String) -> anyhow::Result<Self> {
-        let mut client_options = ClientOptions::parse(url).await?;
-        client_options.app_name = Some("users".to_string());
-        client_options.connect_timeout = Some(Duration::from_secs(3));
-        client_options.server_selection_timeout = Some(Duration::from_secs(10));
-
-        let client = Client::with_options(client_options)?;
-        let database = client.database(db_name.as_str());
-
-        let credentials_options = IndexOptions::builder().unique(true).build();
-        let credentials_model = IndexModel::builder()
-            .keys(doc! {"username": 1})
-            .options(credentials_options)
-            .build();
-
-        database
-            .collection::<User>("users")
-            .create_index(credentials_model, None)
-            .await?;
+        let store = MongoStore::new(url, db_name).await?;
+
+        Ok(Self::with_store(store))
+    }
+}
 
-        Ok(Self { client, database })
+impl<S: UserStore> Users<S> {
+    /// Creates a new Users instance backed by the given store
+    pub fn with_store(store: S) -> Self {
+        Self { store }
     }
 
     /// Checks if a user with the given username exists
     ///
     /// # Errors
     /// `AuthError::DatabaseError` if a database error occurs
-    pub async fn exists(&self, username: String) -> Result<bool, ServiceError> {
-        let mut session = self.client.start_session(None).await?;
+    #[instrument(skip_all)]
+    pub async fn exists(&self, username: NormalizedUsername) -> Result<bool, ServiceError> {
+        let user_option = self.store.find_user(username.as_str()).await?;
 
-        let user_collection = self.database.collection::<User>("users");
-
-        let credentials_option = user_collection
-            .find_one_with_session(doc! { "username": username.clone() }, None, &mut session)
-            .await?;
-
-        Ok(credentials_option.is_some())
+        Ok(user_option.is_some())
     }
 
     /// Gets the user info for the given username
@@ -68,19 +53,14 @@ impl Users {
     /// # Errors
     /// `AuthError::DatabaseError` if a database error occurs
     /// `AuthError::UserNotFound` if the user does not exist
-    pub async fn info(&self, username: String) -> Result<User, ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-
-        let user_collection = self.database.collection::<User>("users");
-
-        let user_option = user_collection
-            .find_one_with_session(doc! { "username": username.clone() }, None, &mut session)
-            .await?;
+    #[instrument(skip_all)]
+    pub async fn info(&self, username: NormalizedUsername) -> Result<User, ServiceError> {
+        let user_option = self.store.find_user(username.as_str()).await?;
 
         if let Some(user) = user_option {
             Ok(user)
         } else {
-            Err(ServiceError::UserNotFound(username))
+            Err(ServiceError::UserNotFound(username.into()))
         }
     }
 
@@ -88,65 +68,44 @@ impl Users {
     ///
     /// # Errors
     /// `AuthError::DatabaseError` if a database error occurs
+    #[instrument(skip_all)]
     pub async fn save_info(
         &self,
-        username: String,
+        username: NormalizedUsername,
         profile_picture: ProfilePicture,
     ) -> Result<(), ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-
-        let user_collection = self.database.collection::<User>("users");
-
-        let user_option = user_collection
-            .find_one_with_session(doc! { "username": username.clone() }, None, &mut session)
-            .await?;
+        let user_option = self.store.find_user(username.as_str()).await?;
 
         match user_option {
             Some(mut user) => {
                 user.profile_picture = profile_picture.profile_picture.clone();
 
-                user_collection
-                    .replace_one_with_session(
-                        doc! { "username": username.clone() },
-                        user,
-                        None,
-                        &mut session,
-                    )
-                    .await?;
+                self.store.replace_user(user).await?;
             }
             None => {
                 let user = User {
-                    username: username.clone(),
+                    username: username.into(),
                     profile_picture: profile_picture.profile_picture.clone(),
                 };
 
-                user_collection
-                    .insert_one_with_session(user, None, &mut session)
-                    .await?;
+                self.store.insert_user(user).await?;
             }
         };
 
         Ok(())
     }
 
+    #[instrument(skip_all)]
     pub async fn create_info(
         &self,
-        username: String,
+        username: NormalizedUsername,
         profile_picture: ProfilePicture,
     ) -> Result<(), ServiceError> {
-        let mut session = self.client.start_session(None).await?;
-
-        let user_collection = self.database.collection::<User>("users");
-
-        user_collection
-            .insert_one_with_session(
-                User {
-                    username: username.clone(),
-                    profile_picture: profile_picture.profile_picture.clone(),
-                },
-                None,
-                &mut session,
-            )
+        self.store
+            .insert_user(User {
+                username: username.into(),
+                profile_picture: profile_picture.profile_picture.clone(),
+            })
             .await?;
 
         Ok(())
@@ -1,13 +1,7 @@
-use actix_web::{error::ParseError, http::header::Header, HttpRequest};
-use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
-use core_rs::{
-    error::{ServiceError, ServiceErrorJSON},
-    Username,
-};
-use reqwest::header::AUTHORIZATION;
 use serde::{Deserialize, Serialize};
 
 pub mod db;
+pub mod store;
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -24,43 +18,3 @@ impl User {
         }
     }
 }
-
-pub async fn get_bearer_token(req: &HttpRequest) -> Result<String, ServiceError> {
-    let parsed_auth = Authorization::<Bearer>::parse(req);
-
-    match parsed_auth {
-        Ok(auth) => Ok(auth.into_scheme().token().to_string()),
-        Err(ParseError::Header) => Err(ServiceError::AuthorizationHeaderError),
-        Err(_) => Err(ServiceError::AuthenticationError),
-    }
-}
-
-pub async fn authenticate(req: &HttpRequest, expected_username: &str) -> Result<(), ServiceError> {
-    let session_token = get_bearer_token(req).await?;
-
-    let client = reqwest::Client::new();
-    let response = client
-        .get("http://auth:8080/authenticate")
-        .header(AUTHORIZATION, format!("Bearer {}", session_token))
-        .send()
-        .await
-        .expect("Failed to make request to auth/authenticate");
-
-    let status = response.status();
-
-    let body = response.text().await.expect("Failed to read response body");
-
-    if status != 200 {
-        let service_error_serde = serde_json::from_str::<ServiceErrorJSON>(&body).unwrap();
-        let service_error = ServiceError::from(service_error_serde);
-        return Err(service_error);
-    }
-
-    let auth_result: Username = serde_json::from_str(&body).unwrap();
-
-    if auth_result.username != expected_username {
-        return Err(ServiceError::AuthenticationError);
-    }
-
-    Ok(())
-}
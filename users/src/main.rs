@@ -1,39 +1,76 @@
-use std::env;
+use std::sync::Arc;
 
 use actix_cors::Cors;
 use actix_web::{
     get,
     http::{self},
-    post, put, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+    post, put, web, App, HttpRequest, HttpResponse, HttpServer,
 };
 
 use core_rs::{
+    auth_client::{AuthenticatedUser, TokenAuthenticator},
+    client_ip::TrustedProxies,
+    config::{Config, UsersStorage},
     create_json_cfg,
     error::{Response, ServiceError},
+    health::{
+        self, BackgroundSetup, HealthChecks, HttpCheck, MongoPing, DEFAULT_SETUP_RETRY_INTERVAL,
+        HEALTH_PATH, READY_PATH,
+    },
+    metrics::{self, Metrics, RecordMetrics},
+    rate_limit::{
+        MemoryRateLimitStore, MongoRateLimitStore, RateLimit, RateLimitStore, RateLimiter,
+    },
+    request_id::{self, AssignRequestId, REQUEST_ID_HEADER},
+    telemetry::{Telemetry, TraceExporter, TraceRequests},
+    username::NormalizedUsername,
+    validation::ValidJson,
     ProfilePicture,
 };
-use users::{authenticate, db::Users, User};
+use dirc_client::AuthApi;
+use futures::future;
+use users::{
+    db,
+    store::{MemoryStore, MongoStore, SqliteStore, UserStore},
+    User,
+};
+
+/// The users service used by the handlers, with the storage backend chosen at startup
+type Users = db::Users<Arc<dyn UserStore>>;
 
 #[get("/{username}/exists")]
 async fn exists(users: web::Data<Users>, path: web::Path<String>) -> Response<bool> {
-    users.exists(path.into_inner()).await.into()
+    let username = match NormalizedUsername::parse(&path.into_inner()) {
+        Ok(username) => username,
+        Err(err) => return Response::Err(err),
+    };
+
+    users.exists(username).await.into()
 }
 
 #[get("/{username}/info")]
 async fn info(users: web::Data<Users>, path: web::Path<String>) -> Response<User> {
-    users.info(path.into_inner()).await.into()
+    let username = match NormalizedUsername::parse(&path.into_inner()) {
+        Ok(username) => username,
+        Err(err) => return Response::Err(err),
+    };
+
+    users.info(username).await.into()
 }
 
 #[post("/{username}/info")]
 async fn put_info(
     users: web::Data<Users>,
     path: web::Path<String>,
-    profile_picture: web::Json<ProfilePicture>,
-    req: HttpRequest,
+    profile_picture: ValidJson<ProfilePicture>,
+    user: AuthenticatedUser,
 ) -> Response<()> {
-    let username = path.into_inner();
+    let username = match NormalizedUsername::parse(&path.into_inner()) {
+        Ok(username) => username,
+        Err(err) => return Response::Err(err),
+    };
 
-    if let Err(err) = authenticate(&req, &username).await {
+    if let Err(err) = user.require(&username) {
         return Response::Err(err);
     }
 
@@ -48,9 +85,12 @@ async fn put_info(
 async fn create_info(
     users: web::Data<Users>,
     path: web::Path<String>,
-    profile_picture: web::Json<ProfilePicture>,
+    profile_picture: ValidJson<ProfilePicture>,
 ) -> Response<()> {
-    let username = path.into_inner();
+    let username = match NormalizedUsername::parse(&path.into_inner()) {
+        Ok(username) => username,
+        Err(err) => return Response::Err(err),
+    };
 
     users
         .create_info(username, profile_picture.into_inner())
@@ -58,44 +98,164 @@ async fn create_info(
         .into()
 }
 /// Custom 404 handler to return JSON
-async fn not_found() -> impl Responder {
-    HttpResponse::NotFound().json(ServiceError::NotFound)
+async fn not_found(req: HttpRequest) -> HttpResponse {
+    ServiceError::NotFound.response_for(&req)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Starting users server...");
 
-    let mongodb_hostname = env::var("MONGODB_HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let config = Config::load().expect("Invalid configuration");
 
-    let mongodb_url = format!("mongodb://{}:27017", mongodb_hostname);
+    let trace_exporter = TraceExporter::from_config(&config.telemetry);
 
-    println!("MongoDB url: {}", mongodb_url);
+    println!("Trace exporter: {}", trace_exporter);
 
-    let users = Users::new(mongodb_url, "users".to_string())
-        .await
-        .expect("Failed to connect to MongoDB");
+    let telemetry = Telemetry::init("users", trace_exporter).expect("Failed to set up tracing");
+
+    for warning in &config.warnings {
+        tracing::warn!("{}", warning);
+    }
+
+    let metrics = Metrics::new();
+
+    let (store, rate_limit_store, health_checks): (
+        Arc<dyn UserStore>,
+        Arc<dyn RateLimitStore>,
+        HealthChecks,
+    ) = match config.users.storage {
+        UsersStorage::Memory => {
+            println!("Using in-memory storage, data will not be persisted");
+
+            (
+                Arc::new(MemoryStore::new()),
+                Arc::new(MemoryRateLimitStore::new()),
+                HealthChecks::new(),
+            )
+        }
+        UsersStorage::Sqlite => {
+            let sqlite_path = config.users.sqlite_path.clone();
+
+            println!("SQLite path: {}", sqlite_path);
+
+            (
+                Arc::new(SqliteStore::open(sqlite_path).expect("Failed to open SQLite database")),
+                Arc::new(MemoryRateLimitStore::new()),
+                HealthChecks::new(),
+            )
+        }
+        UsersStorage::Mongodb => {
+            let database = config.mongodb.database_or("users");
+
+            println!("MongoDB url: {}", config.mongodb.redacted_url());
+            println!("MongoDB database: {}", database);
+
+            let store = MongoStore::with_metrics(
+                config.mongodb.url.clone(),
+                database.to_string(),
+                &metrics,
+            )
+            .await
+            .expect("Invalid MongoDB configuration");
+
+            let rate_limit_store =
+                MongoRateLimitStore::with_database(&store.client().database(database));
+
+            // MongoDB may not be available yet, the service reports not ready until it is set up
+            let setup = {
+                let store = store.clone();
+                let rate_limit_store = rate_limit_store.clone();
+
+                BackgroundSetup::spawn("MongoDB", DEFAULT_SETUP_RETRY_INTERVAL, move || {
+                    let store = store.clone();
+                    let rate_limit_store = rate_limit_store.clone();
 
-    HttpServer::new(move || {
+                    async move {
+                        store.setup().await?;
+                        rate_limit_store.setup().await
+                    }
+                })
+            };
+
+            let health_checks = HealthChecks::new()
+                .with_check("mongodb", MongoPing::new(store.client()))
+                .with_check("mongodb_setup", setup);
+
+            (Arc::new(store), Arc::new(rate_limit_store), health_checks)
+        }
+    };
+
+    // Checking whether users exist is limited more strictly so it can't be used to enumerate them
+    let rate_limiter = RateLimiter::new(rate_limit_store)
+        .trusted_proxies(TrustedProxies::new(config.server.trusted_proxies.clone()))
+        .route("/{username}/exists", RateLimit::per_minute(30))
+        .exempt(HEALTH_PATH)
+        .exempt(READY_PATH)
+        .default_limit(RateLimit::per_minute(300))
+        .with_config(&config.rate_limits);
+
+    let users = Users::with_store(store);
+
+    let auth_api = AuthApi::from_config(&config);
+
+    auth_api
+        .register_cache_metrics(&metrics)
+        .expect("Failed to register metrics");
+
+    println!("Auth service url: {}", auth_api.base_url());
+
+    let health_checks = health_checks.with_check("auth", HttpCheck::service(auth_api.base_url()));
+
+    let authenticator: Arc<dyn TokenAuthenticator> = Arc::new(auth_api);
+
+    println!("Readiness checks: {}", health_checks.names().join(", "));
+
+    let json_limit = config.server.json_limit;
+    let bind_address = config.server.bind_address;
+    let metrics_address = config.server.metrics_address;
+
+    println!("Listening on {}", bind_address);
+    println!("Serving metrics on {}", metrics_address);
+
+    let metrics_server = metrics::serve(metrics.clone(), metrics_address)?;
+
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:3000")
             .allowed_origin("http://auth:8080")
             .allowed_methods(vec!["GET", "POST", "PUT"])
             .allowed_headers(vec![http::header::AUTHORIZATION])
             .allowed_header(http::header::CONTENT_TYPE)
+            .allowed_header(REQUEST_ID_HEADER)
+            .expose_headers(vec![REQUEST_ID_HEADER])
             .max_age(3600);
 
         App::new()
+            .wrap(rate_limiter.clone())
             .wrap(cors)
-            .app_data(create_json_cfg())
+            .wrap(RecordMetrics::new(metrics.clone()))
+            .wrap(request_id::access_logger())
+            .wrap(TraceRequests)
+            .wrap(AssignRequestId)
+            .app_data(create_json_cfg(json_limit))
             .app_data(web::Data::new(users.clone()))
+            .app_data(web::Data::from(authenticator.clone()))
+            .app_data(web::Data::new(health_checks.clone()))
+            .route(HEALTH_PATH, web::get().to(health::healthz))
+            .route(READY_PATH, web::get().to(health::readyz))
             .service(exists)
             .service(info)
             .service(put_info)
             .service(create_info)
             .default_service(web::route().to(not_found))
     })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .bind(bind_address)?
+    .run();
+
+    future::try_join(server, metrics_server).await?;
+
+    telemetry.shutdown();
+
+    Ok(())
 }
@@ -0,0 +1,58 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use core_rs::error::ServiceError;
+
+use crate::User;
+
+use super::UserStore;
+
+/// Keeps users in process memory.
+///
+/// Nothing is persisted, so this is only meant for tests and local development.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    users: Arc<Mutex<HashMap<String, User>>>,
+}
+
+impl MemoryStore {
+    /// Creates a new empty MemoryStore
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, ServiceError> {
+        Ok(self.users.lock().unwrap().get(username).cloned())
+    }
+
+    async fn insert_user(&self, user: User) -> Result<(), ServiceError> {
+        let mut users = self.users.lock().unwrap();
+
+        if users.contains_key(&user.username) {
+            return Err(ServiceError::DatabaseError(format!(
+                "User '{}' already exists",
+                user.username
+            )));
+        }
+
+        users.insert(user.username.clone(), user);
+
+        Ok(())
+    }
+
+    async fn replace_user(&self, user: User) -> Result<(), ServiceError> {
+        let mut users = self.users.lock().unwrap();
+
+        if let Some(existing) = users.get_mut(&user.username) {
+            *existing = user;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use core_rs::error::ServiceError;
+
+use crate::User;
+
+pub mod memory;
+pub mod mongo;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use mongo::MongoStore;
+pub use sqlite::SqliteStore;
+
+/// Storage backend for user profiles
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// Returns the user with the given username, if any
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn find_user(&self, username: &str) -> Result<Option<User>, ServiceError>;
+
+    /// Stores a new user
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs or the user already exists
+    async fn insert_user(&self, user: User) -> Result<(), ServiceError>;
+
+    /// Replaces the stored user with the same username
+    ///
+    /// # Errors
+    /// `ServiceError::DatabaseError` if a database error occurs
+    async fn replace_user(&self, user: User) -> Result<(), ServiceError>;
+}
+
+#[async_trait]
+impl<T: UserStore + ?Sized> UserStore for Arc<T> {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, ServiceError> {
+        (**self).find_user(username).await
+    }
+
+    async fn insert_user(&self, user: User) -> Result<(), ServiceError> {
+        (**self).insert_user(user).await
+    }
+
+    async fn replace_user(&self, user: User) -> Result<(), ServiceError> {
+        (**self).replace_user(user).await
+    }
+}
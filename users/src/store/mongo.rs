@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use core_rs::{
+    error::ServiceError, metrics::Metrics, telemetry::mongodb_span, username::NormalizedUsername,
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, Document, Regex},
+    error::{ErrorKind, WriteFailure},
+    options::{ClientOptions, IndexOptions},
+    Client, Database, IndexModel,
+};
+use tracing::Instrument;
+
+use crate::User;
+
+use super::UserStore;
+
+/// MongoDB error code for a unique index violation
+const DUPLICATE_KEY_ERROR: i32 = 11000;
+
+/// Stores users in a MongoDB database
+#[derive(Clone, Debug)]
+pub struct MongoStore {
+    client: Client,
+    database: Database,
+}
+
+impl MongoStore {
+    /// Creates a new MongoStore given a mongodb url and database name
+    ///
+    /// # Errors
+    /// Construction will fail if a database error occurs
+    pub async fn new(url: String, db_name: String) -> anyhow::Result<Self> {
+        let store = Self::with_options(Self::client_options(url).await?, &db_name)?;
+
+        store.setup().await?;
+
+        Ok(store)
+    }
+
+    /// Creates a new MongoStore like [`MongoStore::new`], recording how long its commands take
+    /// in the given metrics.
+    ///
+    /// Nothing is sent to MongoDB yet, so this succeeds while it is unavailable. The store has to
+    /// be set up with [`MongoStore::setup`] once it is.
+    ///
+    /// # Errors
+    /// Fails if the url is invalid
+    pub async fn with_metrics(
+        url: String,
+        db_name: String,
+        metrics: &Metrics,
+    ) -> anyhow::Result<Self> {
+        let mut client_options = Self::client_options(url).await?;
+        client_options.command_event_handler = Some(metrics.mongodb_event_handler());
+
+        Self::with_options(client_options, &db_name)
+    }
+
+    /// Parses a mongodb url into the client options of the store
+    ///
+    /// # Errors
+    /// Fails if the url is invalid
+    async fn client_options(url: String) -> anyhow::Result<ClientOptions> {
+        let mut client_options = ClientOptions::parse(url).await?;
+        client_options.app_name = Some("users".to_string());
+        client_options.connect_timeout = Some(Duration::from_secs(3));
+        client_options.server_selection_timeout = Some(Duration::from_secs(10));
+
+        Ok(client_options)
+    }
+
+    /// Creates a store with the given client options, without connecting yet
+    ///
+    /// # Errors
+    /// Fails if the options are invalid
+    fn with_options(client_options: ClientOptions, db_name: &str) -> anyhow::Result<Self> {
+        let client = Client::with_options(client_options)?;
+        let database = client.database(db_name);
+
+        Ok(Self { client, database })
+    }
+
+    /// Migrates documents stored by older versions and creates the indexes of the users collection. Can be
+    /// retried if it fails.
+    ///
+    /// # Errors
+    /// Fails if a database error occurs
+    pub async fn setup(&self) -> anyhow::Result<()> {
+        let database = &self.database;
+
+        let credentials_options = IndexOptions::builder().unique(true).build();
+        let credentials_model = IndexModel::builder()
+            .keys(doc! {"username": 1})
+            .options(credentials_options)
+            .build();
+
+        database
+            .collection::<User>("users")
+            .create_index(credentials_model, None)
+            .await?;
+
+        MongoStore::migrate_usernames(database).await?;
+
+        Ok(())
+    }
+
+    /// Moves users stored under a username that is not normalized, from before usernames were
+    /// normalized, to the normalized username.
+    ///
+    /// A user is left alone if the username is not valid or its normalized form is taken by
+    /// another user, so it can be sorted out by hand.
+    ///
+    /// # Errors
+    /// Fails if a database error occurs
+    async fn migrate_usernames(database: &Database) -> anyhow::Result<()> {
+        let users = database.collection::<Document>("users");
+
+        // Usernames made of these characters only can't change when normalized
+        let mut candidates = users
+            .find(
+                doc! { "username": { "$not": Regex {
+                    pattern: "^[a-z0-9._-]*$".to_string(),
+                    options: String::new(),
+                } } },
+                None,
+            )
+            .await?;
+
+        while let Some(candidate) = candidates.try_next().await? {
+            let raw_username = candidate.get_str("username")?;
+
+            let username = match NormalizedUsername::parse(raw_username) {
+                Ok(username) if username == raw_username => continue,
+                Ok(username) => username,
+                Err(err) => {
+                    tracing::warn!("Can't normalize username {:?}: {}", raw_username, err);
+                    continue;
+                }
+            };
+
+            let result = users
+                .update_one(
+                    doc! { "_id": candidate.get("_id"), "username": raw_username },
+                    doc! { "$set": { "username": username.as_str() } },
+                    None,
+                )
+                .await;
+
+            match result {
+                Ok(_) => tracing::info!("Normalized username {:?} to {}", raw_username, username),
+                Err(err) => match *err.kind {
+                    ErrorKind::Write(WriteFailure::WriteError(ref write_error))
+                        if write_error.code == DUPLICATE_KEY_ERROR =>
+                    {
+                        tracing::warn!(
+                            "Can't normalize username {:?}, {} is taken by another user",
+                            raw_username,
+                            username
+                        );
+                    }
+                    _ => return Err(err.into()),
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the underlying MongoDB client
+    pub fn client(&self) -> Client {
+        self.client.clone()
+    }
+}
+
+#[async_trait]
+impl UserStore for MongoStore {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+
+            let user_collection = self.database.collection::<User>("users");
+
+            let user_option = user_collection
+                .find_one_with_session(doc! { "username": username }, None, &mut session)
+                .await?;
+
+            Ok(user_option)
+        }
+        .instrument(mongodb_span("users", "find_one"))
+        .await
+    }
+
+    async fn insert_user(&self, user: User) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+
+            let user_collection = self.database.collection::<User>("users");
+
+            user_collection
+                .insert_one_with_session(user, None, &mut session)
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("users", "insert_one"))
+        .await
+    }
+
+    async fn replace_user(&self, user: User) -> Result<(), ServiceError> {
+        async {
+            let mut session = self.client.start_session(None).await?;
+
+            let user_collection = self.database.collection::<User>("users");
+
+            user_collection
+                .replace_one_with_session(
+                    doc! { "username": user.username.clone() },
+                    user,
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            Ok(())
+        }
+        .instrument(mongodb_span("users", "replace_one"))
+        .await
+    }
+}
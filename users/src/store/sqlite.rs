@@ -0,0 +1,160 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::web;
+use async_trait::async_trait;
+use core_rs::{error::ServiceError, username::NormalizedUsername};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
+
+use crate::User;
+
+use super::UserStore;
+
+/// Stores users in a SQLite database file
+#[derive(Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the SQLite database at the given path
+    ///
+    /// # Errors
+    /// Construction will fail if the database can't be opened or migrated
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Creates a SQLite database that only lives in memory
+    ///
+    /// # Errors
+    /// Construction will fail if the database can't be created
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS users (
+                username TEXT PRIMARY KEY NOT NULL,
+                profile_picture TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        Self::migrate_usernames(&connection)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Moves users stored under a username that is not normalized, from before usernames were
+    /// normalized, to the normalized username.
+    ///
+    /// A user is left alone if the username is not valid or its normalized form is taken by
+    /// another user, so it can be sorted out by hand.
+    fn migrate_usernames(connection: &Connection) -> anyhow::Result<()> {
+        let raw_usernames = connection
+            .prepare("SELECT username FROM users")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for raw_username in raw_usernames {
+            let username = match NormalizedUsername::parse(&raw_username) {
+                Ok(username) if username == raw_username.as_str() => continue,
+                Ok(username) => username,
+                Err(err) => {
+                    tracing::warn!("Can't normalize username {:?}: {}", raw_username, err);
+                    continue;
+                }
+            };
+
+            let result = connection.execute(
+                "UPDATE users SET username = ?2 WHERE username = ?1",
+                params![raw_username, username.as_str()],
+            );
+
+            match result {
+                Ok(_) => tracing::info!("Normalized username {:?} to {}", raw_username, username),
+                Err(rusqlite::Error::SqliteFailure(err, _))
+                    if err.code == ErrorCode::ConstraintViolation =>
+                {
+                    tracing::warn!(
+                        "Can't normalize username {:?}, {} is taken by another user",
+                        raw_username,
+                        username
+                    );
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs a query against the connection on the blocking thread pool
+    async fn run<T, F>(&self, query: F) -> Result<T, ServiceError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        web::block(move || {
+            // A query that panicked may have left the connection mid-transaction, so it is not used
+            // again once the lock is poisoned
+            let connection = connection
+                .lock()
+                .map_err(|err| ServiceError::DatabaseError(err.to_string()))?;
+
+            query(&connection).map_err(|err| ServiceError::DatabaseError(err.to_string()))
+        })
+        .await
+        .map_err(|err| ServiceError::DatabaseError(err.to_string()))?
+    }
+}
+
+#[async_trait]
+impl UserStore for SqliteStore {
+    async fn find_user(&self, username: &str) -> Result<Option<User>, ServiceError> {
+        let username = username.to_string();
+
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT username, profile_picture FROM users WHERE username = ?1",
+                    params![username],
+                    |row| Ok(User::new(row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+        })
+        .await
+    }
+
+    async fn insert_user(&self, user: User) -> Result<(), ServiceError> {
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO users (username, profile_picture) VALUES (?1, ?2)",
+                params![user.username, user.profile_picture],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn replace_user(&self, user: User) -> Result<(), ServiceError> {
+        self.run(move |connection| {
+            connection.execute(
+                "UPDATE users SET profile_picture = ?2 WHERE username = ?1",
+                params![user.username, user.profile_picture],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use core_rs::{error::ServiceError, username::NormalizedUsername};
+use users::{
+    db::Users,
+    store::{MemoryStore, SqliteStore, UserStore},
+    User,
+};
+
+fn memory_users() -> Users<MemoryStore> {
+    Users::with_store(MemoryStore::new())
+}
+
+fn sqlite_users() -> Users<SqliteStore> {
+    Users::with_store(SqliteStore::open_in_memory().expect("Failed to create SQLite database"))
+}
+
+async fn check_info_roundtrip<S: UserStore>(users: Users<S>) {
+    let username = NormalizedUsername::parse("username").unwrap();
+
+    assert_eq!(users.exists(username.clone()).await, Ok(false));
+
+    users
+        .create_info(username.clone(), "picture.png".to_string().into())
+        .await
+        .expect("Creating user info should succeed");
+
+    assert_eq!(users.exists(username.clone()).await, Ok(true));
+
+    let user = users.info(username.clone()).await.unwrap_or_else(|err| {
+        panic!("{:?}", err);
+    });
+    assert_eq!(username, user.username.as_str());
+    assert_eq!(user.profile_picture, "picture.png");
+}
+
+async fn check_save_info<S: UserStore>(users: Users<S>) {
+    let username = NormalizedUsername::parse("username").unwrap();
+
+    users
+        .save_info(username.clone(), "first.png".to_string().into())
+        .await
+        .expect("Saving new user info should succeed");
+
+    users
+        .save_info(username.clone(), "second.png".to_string().into())
+        .await
+        .expect("Updating user info should succeed");
+
+    let user = users.info(username).await.unwrap_or_else(|err| {
+        panic!("{:?}", err);
+    });
+    assert_eq!(user.profile_picture, "second.png");
+}
+
+async fn check_missing_user<S: UserStore>(users: Users<S>) {
+    let result = users
+        .info(NormalizedUsername::parse("Nobody").unwrap())
+        .await;
+
+    assert_eq!(
+        result.err(),
+        Some(ServiceError::UserNotFound("nobody".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn test_memory_info_roundtrip() {
+    check_info_roundtrip(memory_users()).await;
+}
+
+#[tokio::test]
+async fn test_memory_save_info() {
+    check_save_info(memory_users()).await;
+}
+
+#[tokio::test]
+async fn test_memory_missing_user() {
+    check_missing_user(memory_users()).await;
+}
+
+#[actix_web::test]
+async fn test_sqlite_info_roundtrip() {
+    check_info_roundtrip(sqlite_users()).await;
+}
+
+#[actix_web::test]
+async fn test_sqlite_save_info() {
+    check_save_info(sqlite_users()).await;
+}
+
+#[actix_web::test]
+async fn test_sqlite_missing_user() {
+    check_missing_user(sqlite_users()).await;
+}
+
+#[actix_web::test]
+async fn test_sqlite_normalizes_stored_usernames() {
+    let path = std::env::temp_dir().join(format!("users-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // Stored by a version that did not normalize usernames
+    let store = SqliteStore::open(&path).expect("Failed to create SQLite database");
+    for (username, profile_picture) in [("User.Name", "legacy.png"), ("Taken", "legacy.png")] {
+        store
+            .insert_user(User::new(username.to_string(), profile_picture.to_string()))
+            .await
+            .unwrap();
+    }
+    store
+        .insert_user(User::new("taken".to_string(), "current.png".to_string()))
+        .await
+        .unwrap();
+    drop(store);
+
+    let users =
+        Users::with_store(SqliteStore::open(&path).expect("Failed to open SQLite database"));
+
+    let user = users
+        .info(NormalizedUsername::parse("User.Name").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(user.username, "user.name");
+    assert_eq!(user.profile_picture, "legacy.png");
+
+    // A username whose normalized form is taken is left alone
+    let user = users
+        .info(NormalizedUsername::parse("taken").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(user.profile_picture, "current.png");
+
+    std::fs::remove_file(&path).unwrap();
+}